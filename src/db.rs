@@ -3,22 +3,79 @@
 use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use pest_derive::Parser;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{stdin, stdout, Write};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::time::Duration;
 use serde::{Serialize, Deserialize};
-use rand::{Rng, distributions::Alphanumeric};
+mod datetime;
+mod decimal;
+mod distinct;
+mod enums;
+mod expr;
+mod functions;
+mod index;
+mod json;
+mod keys;
+mod lob;
+mod lock;
+mod mvcc;
+mod pattern;
+mod select;
+mod sequence;
+mod uuid;
+mod window;
+use datetime::Interval;
+use decimal::Decimal;
+use enums::{Enum, EnumDef};
+use index::Index;
+use lob::Lob;
+use uuid::Uuid;
+use expr::{Field, SQLExpr, SQLType, SQLValue, Scope};
+use keys::{Key, KeyIndex};
+use lock::{Acquired, LockManager, Mode, Resource};
+use mvcc::{Isolation, Snapshot, Version, PENDING};
+use select::{Catalog, ResultSet, Returning, Select};
+use sequence::Sequence;
 
 #[derive(Parser)]
 #[grammar = "sql.pest"]
 struct SQLParser;
 
+/// Text of a name pair, double quoted names lose their quotes
+fn identifier(name: Pair<Rule>) -> String {
+    let text = name.as_str();
+    match text.strip_prefix('"').and_then(|text| text.strip_suffix('"')) {
+        Some(text) => text.replace("\"\"", "\""),
+        None => String::from(text),
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Serialize, Deserialize)]
 pub struct DBMS {
     databases: HashMap<String, DataBase>,
     curr_db: Option<String>,
     path: Option<String>,
-    id: String,
+    /// Number of the last commit saved to the dump, row versions are stamped with the commit that made them
+    #[serde(default)]
+    commits: u64,
+    /// Commits the open transaction reads, None outside a transaction where every statement sees the latest rows
+    #[serde(skip)]
+    snapshot: Snapshot,
+    /// Locks held until the end of the line, or of the transaction if one has started
+    #[serde(skip)]
+    locks: LockManager,
+    /// Whether BEGIN TRANSACTION has started a transaction that hasn't been committed yet
+    #[serde(skip)]
+    transaction: bool,
+    /// Isolation level transactions of this session start at
+    #[serde(skip)]
+    isolation: Isolation,
+    /// Isolation level of the open transaction
+    #[serde(skip)]
+    level: Isolation,
 }
 
 /// Data Base management system
@@ -28,80 +85,93 @@ impl DBMS {
 
     /// Creates a new DBMS.
     /// path can be none or filesystem path to serde_json encoded DBMS
-    pub fn new(path: Option<String>) -> Self {
-        match path {
-            None => {
-                Self {
-                    databases: HashMap::new(),
-                    curr_db: None,
-                    path,
-                    id: rand::thread_rng()
-                        .sample_iter(&Alphanumeric)
-                        .take(7)
-                        .map(char::from)
-                        .collect(),
-                }
-            }
-            Some(unwrapped_path) => {
-                match std::fs::read_to_string(unwrapped_path.as_str()) {
-                    Ok(file) => {
-                        let mut dbms : Self = serde_json::from_str(file.as_str()).unwrap();
-                        dbms.id = rand::thread_rng()
-                                .sample_iter(&Alphanumeric)
-                                .take(7)
-                                .map(char::from)
-                                .collect();
-                        dbms
-                    }
-                    Err(_) => {
-                        Self {
-                            databases: HashMap::new(),
-                            curr_db: None,
-                            path: Some(unwrapped_path),
-                            id: rand::thread_rng()
-                                .sample_iter(&Alphanumeric)
-                                .take(7)
-                                .map(char::from)
-                                .collect(),
-                        }
-                    }
-                }
+    /// Fails when the dump or its overflow files can't be read
+    pub fn new(path: Option<String>) -> Result<Self, String> {
+        let dump = match path.as_deref() {
+            Some(path) => DBMS::read_dump(path)?,
+            None => None,
+        };
+        Ok(match dump {
+            Some(mut dbms) => {
+                dbms.locks = LockManager::new(dbms.path.as_ref().unwrap());
+                dbms
+            },
+            None => Self {
+                databases: HashMap::new(),
+                curr_db: None,
+                commits: 0,
+                snapshot: None,
+                locks: path.as_deref().map_or_else(LockManager::default, LockManager::new),
+                path,
+                transaction: false,
+                isolation: Isolation::default(),
+                level: Isolation::default(),
+            },
+        })
+    }
+
+    /// How long a statement waits for a lock another instance holds before it fails
+    pub fn set_lock_timeout(&mut self, timeout: Duration) {
+        self.locks.set_timeout(timeout);
+    }
+
+    /// Breaks every lock on the dump at path once the user confirms, for when an instance is stuck holding them
+    pub fn break_locks(path: &str) {
+        let locks = lock::locks(path, None);
+        if locks.is_empty() {
+            println!("No locks are held.");
+        } else if confirm(&locks) {
+            lock::break_locks(&locks);
+            match locks.len() {
+                1 => println!("1 lock broken."),
+                n => println!("{} locks broken.", n),
             }
+        } else {
+            println!("No locks broken.");
         }
     }
 
     /// Opens an interactive prompt and parses and runs data fed to it
+    /// Outside a transaction every line is saved when it is done and its locks are let go
     pub fn interactive(&mut self) {
         let mut line = String::new();
         loop {
-            stdout().write(DBMS::PROMPT.as_bytes()).unwrap();
+            stdout().write_all(DBMS::PROMPT.as_bytes()).unwrap();
             stdout().flush().unwrap();
             stdin().read_line(&mut line).unwrap();
-            self.load();
+            if let Err(e) = self.load() {
+                println!("{e}");
+            }
             match SQLParser::parse(Rule::SQL, &line) {
                 Ok(k) => {
                     for command in k {
                         match self.run(command) {
-                            Ok(k) => {
-                                match k {
-                                    Some(s) => println!("{}", s),
-                                    None => ()
+                            Ok(Some(s)) => println!("{}", s),
+                            Ok(None) => (),
+                            Err(e) => {
+                                if mvcc::is_serialization_failure(&e) && self.transaction {
+                                    if let Err(e) = self.abort() {
+                                        println!("{e}");
+                                    }
                                 }
+                                println!("{e}")
                             }
-                            Err(e) => println!("{e}")
                         }
                     }
                 },
                 Err(e) => println!("Error parsing\n{}", e)
             }
-            if !std::path::Path::new(&format!("{}.lock", self.path.as_ref().unwrap())).exists() {
-                self.save();
+            if !self.transaction {
+                if let Err(e) = self.save() {
+                    println!("{e}");
+                }
+                self.locks.release();
             }
             line.clear();
         }
     }
     
-    /// Runs sql from a file.sql located at path
+    // Runs sql from a file.sql located at path
 //    pub fn sql_from_file(&mut self, path: &str) {
 //        match fs::read_to_string(path) {
 //            Err(e) => println!("Error reading from {}\n{}", path,  e),
@@ -132,12 +202,19 @@ impl DBMS {
     /// runs already parsed commands
     /// it is just a big brancing switch-case
     fn run(&mut self, command: Pair<Rule>) -> Result<Option<String>, String>{
+        if self.transaction && self.level == Isolation::ReadCommitted && matches!(command.as_rule(), Rule::select | Rule::insert | Rule::update | Rule::delete) {
+            self.take_snapshot()?;
+        }
+        self.lock(&command)?;
+        for db in self.databases.values_mut() {
+            db.snapshot = self.snapshot;
+        }
         match command.as_rule() {
             Rule::create => {
                 let mut it = command.into_inner();
                 match it.next().unwrap().as_rule() {
                     Rule::database => {
-                        let name = it.next().unwrap().as_str();
+                        let name = &identifier(it.next().unwrap());
                         if self.databases.contains_key(name) {
                             Err(format!("!Failed to create database {} because it already exists.", name))
                         } else {
@@ -155,7 +232,40 @@ impl DBMS {
                                     }
                                 }
                             },
-                            None => Err(format!("!No database supplied"))
+                            None => Err(String::from("!No database supplied"))
+                        }
+                    },
+                    Rule::sequence => {
+                        match &self.curr_db {
+                            Some(db) => {
+                                match self.databases.get_mut(db) {
+                                    None => Err(format!("!Database {} was deleted", db)),
+                                    Some(db) => db.create_sequence(it),
+                                }
+                            },
+                            None => Err(String::from("!No database supplied"))
+                        }
+                    },
+                    Rule::typeKw => {
+                        match &self.curr_db {
+                            Some(db) => {
+                                match self.databases.get_mut(db) {
+                                    None => Err(format!("!Database {} was deleted", db)),
+                                    Some(db) => db.create_type(it),
+                                }
+                            },
+                            None => Err(String::from("!No database supplied"))
+                        }
+                    },
+                    Rule::indexKw => {
+                        match &self.curr_db {
+                            Some(db) => {
+                                match self.databases.get_mut(db) {
+                                    None => Err(format!("!Database {} was deleted", db)),
+                                    Some(db) => db.create_index(it),
+                                }
+                            },
+                            None => Err(String::from("!No database supplied"))
                         }
                     },
                     _ => Err(format!("An uknown parsing error happened on line: {}", line!()))
@@ -171,7 +281,7 @@ impl DBMS {
                             }
                         }
                     },
-                    None => Err(format!("!No database supplied"))
+                    None => Err(String::from("!No database supplied"))
                 }
 
             }
@@ -179,7 +289,7 @@ impl DBMS {
                 let mut it = command.into_inner();
                 match it.next().unwrap().as_rule() {
                     Rule::database => {
-                        let name = it.next().unwrap().as_str();
+                        let name = &identifier(it.next().unwrap());
                         if self.databases.contains_key(name) {
                             self.databases.remove(&String::from(name));
                             Ok(Some(format!("Database {} deleted.", name)))
@@ -195,7 +305,40 @@ impl DBMS {
                                     None => Err(format!("!Database {} was deleted", db.as_str()))
                                 }
                             },
-                            None => Err(format!("!No database supplied."))
+                            None => Err(String::from("!No database supplied."))
+                        }
+                    },
+                    Rule::sequence => {
+                        match &self.curr_db {
+                            Some(db) => {
+                                match self.databases.get_mut(db.as_str()) {
+                                    Some(db) => db.drop_sequence(it),
+                                    None => Err(format!("!Database {} was deleted", db.as_str()))
+                                }
+                            },
+                            None => Err(String::from("!No database supplied."))
+                        }
+                    },
+                    Rule::typeKw => {
+                        match &self.curr_db {
+                            Some(db) => {
+                                match self.databases.get_mut(db.as_str()) {
+                                    Some(db) => db.drop_type(it),
+                                    None => Err(format!("!Database {} was deleted", db.as_str()))
+                                }
+                            },
+                            None => Err(String::from("!No database supplied."))
+                        }
+                    },
+                    Rule::indexKw => {
+                        match &self.curr_db {
+                            Some(db) => {
+                                match self.databases.get_mut(db.as_str()) {
+                                    Some(db) => db.drop_index(it),
+                                    None => Err(format!("!Database {} was deleted", db.as_str()))
+                                }
+                            },
+                            None => Err(String::from("!No database supplied."))
                         }
                     }
                     _ => Err(format!("An uknown parsing error happened on line: {}", line!()))
@@ -203,7 +346,7 @@ impl DBMS {
             },
             Rule::_use => {
                 let mut it = command.into_inner();
-                let name = it.next().unwrap().as_str();
+                let name = &identifier(it.next().unwrap());
                 if self.databases.contains_key(name) {
                     self.curr_db = Some(String::from(name));
                     Ok(Some(format!("Using database {}.", name)))
@@ -229,7 +372,7 @@ impl DBMS {
                             Err(format!("!Database {} was deleted", db.as_str()))
                         }
                     },
-                    None => Err(format!("!No database supplied"))
+                    None => Err(String::from("!No database supplied"))
                 }
             },
             Rule::alter => {
@@ -245,10 +388,10 @@ impl DBMS {
                                     None => Err(format!("!Database {} was deleted.", db.as_str()))
                                 }
                             },
-                            None => Err(format!("!No database supplied."))
+                            None => Err(String::from("!No database supplied."))
                         }
                     },
-                    _ => Err(format!("An uknown parsing error happened"))
+                    _ => Err(String::from("An uknown parsing error happened"))
                 }
             },
             Rule::update => {
@@ -257,11 +400,11 @@ impl DBMS {
                         match self.databases.get_mut(db) {
                             None => Err(format!("!Database {} was deleted", db)),
                             Some(db) => {
-                                db.update(command.into_inner(), self.id.as_str())
+                                db.update(command.into_inner())
                             }
                         }
                     },
-                    None => Err(format!("!No database supplied"))
+                    None => Err(String::from("!No database supplied"))
                 }
             },
             Rule::delete => {
@@ -274,72 +417,419 @@ impl DBMS {
                             }
                         }
                     },
-                    None => Err(format!("!No database supplied"))
+                    None => Err(String::from("!No database supplied"))
                 }
             },
             Rule::EOI => {
                 Ok(None)
             },
             Rule::begin => {
-                if !std::path::Path::new(&format!("{}.lock", self.path.as_ref().unwrap())).exists() {
-                    std::fs::File::create(&format!("{}.lock", self.path.as_ref().unwrap())).unwrap().write(self.id.as_bytes()).unwrap();
+                if !self.transaction {
+                    // Statements earlier on the same line are saved on their own
+                    self.save()?;
+                    self.locks.release();
+                    self.level = command.into_inner().next().map_or(self.isolation, Isolation::parse);
+                    self.begin()?;
+                }
+                self.transaction = true;
+                Ok(Some(String::from("Transaction starts.")))
+            }
+            Rule::setTransaction => {
+                let level = Isolation::parse(command.into_inner().next().unwrap());
+                if !self.transaction {
+                    self.isolation = level;
+                    Ok(Some(format!("Transactions will run at isolation level {}.", level)))
+                } else if self.locks.is_empty() {
+                    self.level = level;
+                    self.begin()?;
+                    Ok(Some(format!("Transaction isolation level set to {}.", level)))
+                } else {
+                    Err(String::from("!SET TRANSACTION must come before the transaction reads or changes anything"))
                 }
-                Ok(Some(format!("Transaction starts.")))
             }
             Rule::commit => {
-                if std::path::Path::new(&format!("{}.lock", self.path.as_ref().unwrap())).exists() {
-                    if std::fs::read_to_string(&format!("{}.lock", self.path.as_ref().unwrap())).unwrap() == self.id {
-                        std::fs::remove_file(&format!("{}.lock", self.path.as_ref().unwrap())).unwrap();
-                        Ok(Some(format!("Transaction Committed")))
-                    } else {
-                        Err(format!("Transaction Aborted."))
+                if self.transaction {
+                    self.transaction = false;
+                    let saved = self.save();
+                    self.locks.release();
+                    self.locks.unregister();
+                    self.snapshot = None;
+                    match saved {
+                        Ok(()) => Ok(Some(String::from("Transaction Committed"))),
+                        Err(e) => Err(format!("{}\nTransaction Aborted.", e)),
                     }
                 } else {
-                    Err(format!("Transaction Aborted."))
+                    Err(String::from("Transaction Aborted."))
+                }
+            }
+            Rule::unlock => {
+                let name = &identifier(command.into_inner().nth(1).unwrap());
+                match &self.path {
+                    _ if self.locks.holds(name) => Err(format!("!This instance holds locks on database {}, COMMIT first", name)),
+                    Some(path) => {
+                        let locks = lock::locks(path, Some(name));
+                        if locks.is_empty() {
+                            Ok(Some(format!("No locks are held on database {}.", name)))
+                        } else if confirm(&locks) {
+                            lock::break_locks(&locks);
+                            Ok(Some(format!("Database {} unlocked.", name)))
+                        } else {
+                            Ok(Some(format!("Database {} not unlocked.", name)))
+                        }
+                    },
+                    None => Ok(Some(format!("No locks are held on database {}.", name))),
                 }
             }
             _ => Err(format!("Command \"{}\" was parsed but could not be ran", command.as_str()))
         }
     }
 
-    /// saves serde_json encoded data to self.path
-    fn save(&mut self) {
-        match &self.path {
-            Some(path) => {
-                let mut f = std::fs::OpenOptions::new().write(true).truncate(true).create(true).open(path).unwrap();
-                let ser = serde_json::to_string(&self).unwrap();
-                f.write_all(ser.as_bytes()).unwrap();
-                f.flush().unwrap();
+    /// Directory next to the dump holding TEXT and BLOB values too large to keep inline
+    fn overflow_dir(path: &str) -> PathBuf {
+        PathBuf::from(format!("{}.overflow", path))
+    }
+
+    /// Reads large TEXT and BLOB values of every database back in from the overflow directory next to path
+    /// and puts the definitions of enum types back in the columns of them
+    fn fill(&mut self, path: &str) -> Result<(), String> {
+        let dir = DBMS::overflow_dir(path);
+        self.databases.values_mut().try_for_each(|db| {
+            db.link();
+            db.fill(&dir)
+        })
+    }
+
+    /// Reads the dump at path, None if there isn't one yet
+    /// Large values are read from their overflow files under the same lock, so a save can't remove them in between
+    fn read_dump(path: &str) -> Result<Option<DBMS>, String> {
+        if !Path::new(path).exists() {
+            return Ok(None);
+        }
+        let _storage = lock::storage(path, Mode::Shared)?;
+        let s = std::fs::read_to_string(path).map_err(|e| format!("!Could not read {}: {}", path, e))?;
+        let mut dbms: DBMS = serde_json::from_str(s.as_str()).map_err(|e| format!("!Could not read {}: {}", path, e))?;
+        dbms.fill(path)?;
+        Ok(Some(dbms))
+    }
+
+    /// Locks a command needs, each database before its tables
+    /// Changing what tables, types or sequences a database has, or calling nextval or setval, locks the whole database
+    fn requests(&self, command: &Pair<Rule>) -> Vec<(Resource, Mode)> {
+        let mut it = command.clone().into_inner();
+        let curr = match &self.curr_db {
+            Some(db) => db.clone(),
+            None => String::new(),
+        };
+        let target = match command.as_rule() {
+            Rule::create | Rule::drop => {
+                return match it.next().unwrap().as_rule() {
+                    Rule::database => vec![(Resource::Database(identifier(it.next().unwrap())), Mode::Exclusive)],
+                    _ if self.curr_db.is_some() => vec![(Resource::Database(curr), Mode::Exclusive)],
+                    _ => Vec::new(),
+                };
             },
-            None => ()
-        }
-    }
-    fn load(&mut self) {
-        match &self.path {
-            Some(path) => {
-                if std::path::Path::new(path).exists() {
-                    if std::path::Path::new(&format!("{}.lock", path)).exists() {
-                        if std::fs::read_to_string(&format!("{}.lock", self.path.as_ref().unwrap())).unwrap() != self.id {
-                            let s = std::fs::read_to_string(path).unwrap();
-                            let ser : DBMS = serde_json::from_str(s.as_str()).unwrap();
-                            self.databases = ser.databases;
+            Rule::alter => it.nth(1).map(identifier),
+            Rule::insert | Rule::update | Rule::delete => it.next().map(identifier),
+            Rule::select => None,
+            _ => return Vec::new(),
+        };
+        let db = match self.databases.get(&curr) {
+            Some(db) => db,
+            None => return Vec::new(),
+        };
+        let pairs: Vec<Pair<Rule>> = command.clone().into_inner().flatten().collect();
+        // Defaults left out of an INSERT are worked out as well
+        let defaults = command.as_rule() == Rule::insert
+            && target.as_ref().and_then(|target| db.tables.get(target)).is_some_and(Table::defaults_change_sequences);
+        if defaults || changes_sequences(pairs.iter().cloned()) {
+            return vec![(Resource::Database(curr), Mode::Exclusive)];
+        }
+        let mut requests = vec![(Resource::Database(curr.clone()), Mode::Shared)];
+        if let Some(target) = &target {
+            requests.push((Resource::Table(curr.clone(), target.clone()), Mode::Exclusive));
+        }
+        // Tables that are only read are locked by serializable transactions alone,
+        // others read the row versions of their snapshot
+        if !self.transaction || self.level != Isolation::Serializable {
+            return requests;
+        }
+        // Names that aren't tables are CTEs
+        for source in pairs.iter().filter(|pair| pair.as_rule() == Rule::source) {
+            let name = source.clone().into_inner().next().unwrap();
+            if name.as_rule() == Rule::name {
+                let name = identifier(name);
+                if db.tables.contains_key(&name) && target.as_ref() != Some(&name) {
+                    requests.push((Resource::Table(curr.clone(), name), Mode::Shared));
+                }
+            }
+        }
+        requests
+    }
+
+    /// Takes the locks a command needs before it runs
+    /// Whatever was not locked already is read again from the dump, another process may have changed it since it was loaded
+    /// Under strict two phase locking a lock that can't be had, or was lost while being made exclusive, fails the transaction
+    fn lock(&mut self, command: &Pair<Rule>) -> Result<(), String> {
+        let serializable = self.transaction && self.level == Isolation::Serializable;
+        let mut stale = Vec::new();
+        for (resource, mode) in self.requests(command) {
+            let held = self.locks.mode(&resource).is_some();
+            match self.locks.acquire(&resource, mode) {
+                Err(e) if serializable => return Err(mvcc::serialization_failure(e.trim_start_matches("Error: ").trim_end_matches('!'))),
+                Err(e) => return Err(e),
+                Ok(Acquired::New) if serializable && held => {
+                    return Err(mvcc::serialization_failure(&format!("the lock on {} was lost while waiting to make it exclusive", resource)));
+                },
+                Ok(Acquired::New) => stale.push(resource),
+                // Other tables of a database could change while it was only held shared
+                Ok(Acquired::Upgraded) if matches!(resource, Resource::Database(_)) => stale.push(resource),
+                _ => (),
+            }
+        }
+        if !stale.is_empty() {
+            self.refresh(&stale)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the given databases and tables with what the dump holds now
+    /// Tables this process has locked are kept as they are, they are up to date and may hold its changes
+    fn refresh(&mut self, stale: &[Resource]) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return Ok(()),
+        };
+        if let Some(dbms) = DBMS::read_dump(&path)? {
+            self.replace(dbms.databases, stale);
+        }
+        Ok(())
+    }
+
+    /// Replaces the given databases and tables with those in fresh, read from the dump
+    fn replace(&mut self, mut fresh: HashMap<String, DataBase>, stale: &[Resource]) {
+        keep_currvals(&self.databases, &fresh);
+        for resource in stale {
+            match resource {
+                Resource::Database(name) => match fresh.remove(name) {
+                    Some(mut db) => {
+                        if let Some(ours) = self.databases.remove(name) {
+                            for (table_name, table) in ours.tables {
+                                let resource = Resource::Table(name.clone(), table_name.clone());
+                                if self.locks.mode(&resource).is_some() && !stale.contains(&resource) {
+                                    db.tables.insert(table_name, table);
+                                }
+                            }
                         }
-                    } else {
-                        let s = std::fs::read_to_string(path).unwrap();
-                        let ser : DBMS = serde_json::from_str(s.as_str()).unwrap();
-                        self.databases = ser.databases;
+                        self.databases.insert(name.clone(), db);
+                    },
+                    None => {
+                        self.databases.remove(name);
+                    },
+                },
+                Resource::Table(db, table_name) => {
+                    if self.locks.mode(&Resource::Database(db.clone())) == Some(Mode::Exclusive) {
+                        continue;
                     }
+                    if let (Some(ours), Some(theirs)) = (self.databases.get_mut(db), fresh.get_mut(db)) {
+                        match theirs.tables.remove(table_name) {
+                            Some(table) => {
+                                ours.tables.insert(table_name.clone(), table);
+                            },
+                            None => {
+                                ours.tables.remove(table_name);
+                            },
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    /// saves serde_json encoded data to self.path
+    /// Only what this process holds exclusive locks on is written,
+    /// over the dump as it is now so changes other processes saved to what they had locked are kept
+    /// Large TEXT and BLOB values are written to the overflow directory first and files no longer used are removed after
+    /// Nothing is written if any lock was broken, its changes are dropped and the dump read again
+    fn save(&mut self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return Ok(()),
+        };
+        let exclusive = self.locks.exclusive();
+        if exclusive.is_empty() {
+            return Ok(());
+        }
+        let broken = self.locks.broken();
+        if !broken.is_empty() {
+            self.abort()?;
+            let names: Vec<String> = broken.iter().map(|resource| resource.to_string()).collect();
+            return Err(format!("!Locks on {} were broken by another instance, changes were not saved", names.join(", ")));
+        }
+        let _storage = lock::storage(&path, Mode::Exclusive)?;
+        let (mut merged, commits) = match std::fs::read_to_string(&path) {
+            Ok(s) => {
+                let disk = serde_json::from_str::<DBMS>(s.as_str()).map_err(|e| format!("!Could not read {}: {}", path, e))?;
+                (disk.databases, disk.commits)
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (HashMap::new(), self.commits),
+            Err(e) => return Err(format!("!Could not read {}: {}", path, e)),
+        };
+        for resource in &exclusive {
+            if let Resource::Database(name) = resource {
+                match self.databases.remove(name) {
+                    Some(db) => merged.insert(name.clone(), db),
+                    None => merged.remove(name),
+                };
+            }
+        }
+        for resource in &exclusive {
+            if let Resource::Table(db, table_name) = resource {
+                if self.locks.mode(&Resource::Database(db.clone())) == Some(Mode::Exclusive) {
+                    continue;
+                }
+                if let (Some(ours), Some(theirs)) = (self.databases.get_mut(db), merged.get_mut(db)) {
+                    match ours.tables.remove(table_name) {
+                        Some(table) => theirs.tables.insert(table_name.clone(), table),
+                        None => theirs.tables.remove(table_name),
+                    };
                 }
             }
-            None => (),
         }
+        keep_currvals(&self.databases, &merged);
+        self.databases = merged;
+        let commit = commits + 1;
+        let horizon = self.locks.horizon(commit);
+        for resource in &exclusive {
+            let (db, table_name) = match resource {
+                Resource::Database(db) => (db, None),
+                Resource::Table(db, table_name) => (db, Some(table_name)),
+            };
+            if let Some(db) = self.databases.get_mut(db) {
+                for (name, table) in db.tables.iter_mut() {
+                    if table_name.is_none_or(|table_name| table_name == name) {
+                        table.commit(commit, horizon);
+                    }
+                }
+            }
+        }
+        self.commits = commit;
+        let dir = DBMS::overflow_dir(&path);
+        let mut used = HashSet::new();
+        for db in self.databases.values_mut() {
+            db.spill(&dir, &mut used)?;
+        }
+        let ser = serde_json::to_string(&self).map_err(|e| format!("!Could not save {}: {}", path, e))?;
+        let mut f = std::fs::OpenOptions::new().write(true).truncate(true).create(true).open(&path)
+            .map_err(|e| format!("!Could not save {}: {}", path, e))?;
+        f.write_all(ser.as_bytes()).and_then(|_| f.flush()).map_err(|e| format!("!Could not save {}: {}", path, e))?;
+        lob::sweep(&dir, &used);
+        // Still under the storage lock, values of tables merged from the dump are read before anyone can remove their files
+        self.fill(&path)
+    }
+
+    /// Reads the dump again so changes saved by other processes are seen
+    /// A transaction keeps working on its own copy, what it locks is read again as it goes
+    fn load(&mut self) -> Result<(), String> {
+        if self.transaction {
+            return Ok(());
+        }
+        if let Some(dbms) = self.path.as_deref().map(DBMS::read_dump).transpose()?.flatten() {
+            keep_currvals(&self.databases, &dbms.databases);
+            self.databases = dbms.databases;
+            self.commits = dbms.commits;
+        }
+        Ok(())
     }
+
+    /// Starts the open transaction at its isolation level
+    fn begin(&mut self) -> Result<(), String> {
+        match self.level {
+            // Tables are read again as they are locked, and stay locked, so the latest rows are the ones to read
+            Isolation::Serializable => {
+                self.locks.unregister();
+                self.snapshot = None;
+                Ok(())
+            },
+            _ => self.take_snapshot(),
+        }
+    }
+
+    /// Moves the open transaction's snapshot up to the latest commit, reading again every database it hasn't locked exclusively
+    /// The dump is read and the snapshot registered under the storage lock so no commit in between drops versions it sees
+    fn take_snapshot(&mut self) -> Result<(), String> {
+        if let Some(path) = self.path.clone() {
+            let _storage = lock::storage(&path, Mode::Shared)?;
+            if let Some(dbms) = DBMS::read_dump(&path)? {
+                let names: HashSet<&String> = dbms.databases.keys().chain(self.databases.keys()).collect();
+                let stale: Vec<Resource> = names.into_iter().map(|name| Resource::Database(name.clone()))
+                    .filter(|resource| self.locks.mode(resource) != Some(Mode::Exclusive)).collect();
+                self.commits = dbms.commits;
+                self.replace(dbms.databases, &stale);
+            }
+            self.locks.register(self.commits)?;
+        }
+        self.snapshot = Some(self.commits);
+        Ok(())
+    }
+
+    /// Ends a transaction without saving it, its changes are dropped by reading the dump again
+    fn abort(&mut self) -> Result<(), String> {
+        self.locks.release();
+        self.locks.unregister();
+        self.transaction = false;
+        self.snapshot = None;
+        self.load()
+    }
+}
+
+/// Whether any function call among pairs is nextval or setval, which change a sequence of the database
+fn changes_sequences<'a>(mut pairs: impl Iterator<Item = Pair<'a, Rule>>) -> bool {
+    pairs.any(|pair| {
+        let name = match pair.as_rule() {
+            Rule::functionCall => pair.into_inner().next().unwrap().as_str(),
+            _ => return false,
+        };
+        name.eq_ignore_ascii_case("nextval") || name.eq_ignore_ascii_case("setval")
+    })
+}
+
+/// Values currval returns belong to the session and aren't saved, so they are carried over to databases read again
+fn keep_currvals(ours: &HashMap<String, DataBase>, fresh: &HashMap<String, DataBase>) {
+    for (name, db) in ours {
+        if let Some(fresh) = fresh.get(name) {
+            for (sequence_name, sequence) in &db.sequences {
+                if let Some(other) = fresh.sequences.get(sequence_name) {
+                    other.keep_currval(sequence);
+                }
+            }
+        }
+    }
+}
+
+/// Lists locks about to be broken and asks the user to type yes
+fn confirm(locks: &[lock::Lock]) -> bool {
+    for lock in locks {
+        println!("{}", lock);
+    }
+    print!("Breaking a lock lets another instance change what its holder is using. Type yes to break these locks: ");
+    stdout().flush().unwrap();
+    let mut answer = String::new();
+    stdin().read_line(&mut answer).unwrap();
+    answer.trim().eq_ignore_ascii_case("yes")
 }
 
 /// DataBase that holds a hashmap of tables
 #[derive(Serialize,Deserialize)]
 struct DataBase {
     tables: HashMap<String, Table>,
+    #[serde(default)]
+    sequences: HashMap<String, Sequence>,
+    /// Enum types made by CREATE TYPE, columns and values of them only hold their id
+    #[serde(default)]
+    types: HashMap<String, EnumDef>,
+    /// Commits the running statement sees, set before every statement
+    #[serde(skip)]
+    snapshot: Snapshot,
 }
 
 impl DataBase {
@@ -347,34 +837,191 @@ impl DataBase {
     /// Creates a new empty database
     fn new() -> Self {
         Self {
-            tables: HashMap::new()
+            tables: HashMap::new(),
+            sequences: HashMap::new(),
+            types: HashMap::new(),
+            snapshot: None,
+        }
+    }
+
+    fn spill(&mut self, dir: &Path, used: &mut HashSet<String>) -> Result<(), String> {
+        self.tables.values_mut().flat_map(|table| table.data.iter_mut()).try_for_each(|column| column.spill(dir, used))
+    }
+
+    fn fill(&mut self, dir: &Path) -> Result<(), String> {
+        self.tables.values_mut().try_for_each(|table| table.fill(dir))
+    }
+
+    /// The enum type with id, for a column or value of it
+    fn enum_type(&self, id: u32) -> Result<Enum, String> {
+        match self.types.values().find(|def| def.id == id) {
+            Some(def) => Ok(Enum::new(def.clone())),
+            None => Err(format!("!Enum type {} does not exist", id)),
+        }
+    }
+
+    /// How a type is printed, enum types go by the name they were made with
+    fn type_name(&self, sql_type: SQLType) -> String {
+        match sql_type {
+            SQLType::Enum(id) => match self.types.values().find(|def| def.id == id) {
+                Some(def) => def.name.clone(),
+                None => sql_type.to_string(),
+            },
+            sql_type => sql_type.to_string(),
+        }
+    }
+
+    /// Puts the definitions of its enum types back in the columns and values that were read with only their ids
+    fn link(&mut self) {
+        let types: HashMap<u32, Enum> = self.types.values().map(|def| (def.id, Enum::new(def.clone()))).collect();
+        for table in self.tables.values_mut() {
+            table.link(&types);
         }
     }
 
     /// Updates a table
-    fn update(&mut self, mut list: Pairs<Rule>, id: &str) -> Result<Option<String>, String> {
-        let table_name = list.next().unwrap().as_str();
-        match self.tables.get_mut(table_name) {
+    fn update(&mut self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
+        let table_name = &identifier(list.next().unwrap());
+        match self.tables.get(table_name) {
             Some(table) => {
-                table.update(list, id)
+                // Changes are worked out against the whole database first so subqueries can read it
+                let changes = table.update(self, table_name, list.clone())?;
+                let returning = self.returning(list, table_name, changes.iter().map(|(_, row)| row.clone()).collect())?;
+                let modified = self.tables.get_mut(table_name).unwrap().apply(changes)?;
+                Ok(returning.or(modified))
             },
             None => Err(format!("!Failed to insert into table {} as it does not exist.", table_name))
         }
     }
     /// Deletes part of a table
     fn delete(&mut self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
-        let table_name = list.next().unwrap().as_str();
-        match self.tables.get_mut(table_name) {
+        let table_name = &identifier(list.next().unwrap());
+        match self.tables.get(table_name) {
             Some(table) => {
-                table.delete(list)
+                let rows = table.delete(self, table_name, list.clone())?;
+                let returning = self.returning(list, table_name, rows.iter().map(|i| table.row(*i)).collect())?;
+                let deleted = self.tables.get_mut(table_name).unwrap().remove(&rows)?;
+                Ok(returning.or(deleted))
             },
             None => Err(format!("!Failed to insert into table {} as it does not exist.", table_name))
         }
     }
 
+    /// Creates a sequence, it starts at 1 and counts up by 1 unless told otherwise
+    fn create_sequence(&mut self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
+        let name = &identifier(list.next().unwrap());
+        if self.sequences.contains_key(name) {
+            return Err(format!("!Failed to create sequence {} because it already exists.", name));
+        }
+        let mut start = 1;
+        let mut increment = 1;
+        for pair in list {
+            let rule = pair.as_rule();
+            let expr = pair.into_inner().find(|pair| pair.as_rule() == Rule::expr).unwrap();
+            let val = match constant(expr, self)? {
+                (_, SQLValue::Int(val)) => val,
+                (sql_type, _) => return Err(format!("!Sequence {} needs int values but got {}", name, sql_type)),
+            };
+            match rule {
+                Rule::startWith => start = val,
+                _ => increment = val,
+            }
+        }
+        self.sequences.insert(String::from(name), Sequence::new(start, increment)?);
+        Ok(Some(format!("Sequence {} created.", name)))
+    }
+
+    fn drop_sequence(&mut self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
+        let name = &identifier(list.next().unwrap());
+        match self.sequences.remove(name) {
+            Some(_) => Ok(Some(format!("Sequence {} deleted.", name))),
+            None => Err(format!("!Failed to delete sequence {} because it does not exist", name)),
+        }
+    }
+
+    /// Creates an enum type, its values sort in the order the labels are listed
+    fn create_type(&mut self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
+        let name = &identifier(list.next().unwrap());
+        if self.types.contains_key(name) {
+            return Err(format!("!Failed to create type {} because it already exists.", name));
+        }
+        let mut labels = Vec::new();
+        for pair in list.filter(|pair| pair.as_rule() == Rule::columnVal) {
+            let label = match SQLValue::from_literal(pair)? {
+                SQLValue::Char(label) => label,
+                val => return Err(format!("!Enum labels must be text but got {}", val)),
+            };
+            if labels.contains(&label) {
+                return Err(format!("!Enum label '{}' is listed more than once", label));
+            }
+            labels.push(label);
+        }
+        let id = self.types.values().map(|def| def.id + 1).max().unwrap_or(0);
+        self.types.insert(String::from(name), EnumDef { id, name: String::from(name), labels });
+        Ok(Some(format!("Type {} created.", name)))
+    }
+
+    /// Drops an enum type, no column may still use it
+    fn drop_type(&mut self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
+        let name = &identifier(list.next().unwrap());
+        let kind = match self.types.get(name) {
+            Some(def) => def.id,
+            None => return Err(format!("!Failed to delete type {} because it does not exist", name)),
+        };
+        for (table_name, table) in &self.tables {
+            if let Some(field) = table.fields().into_iter().find(|field| field.sql_type == SQLType::Enum(kind)) {
+                return Err(format!("!Failed to delete type {} because column {}.{} uses it", name, table_name, field.name));
+            }
+        }
+        self.types.remove(name);
+        Ok(Some(format!("Type {} deleted.", name)))
+    }
+
+    /// Creates an index on expressions of a table's columns, index names are unique within the database
+    fn create_index(&mut self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
+        let name = &identifier(list.next().unwrap());
+        if self.tables.values().flat_map(|table| &table.indexes).any(|index| &index.name == name) {
+            return Err(format!("!Failed to create index {} because it already exists.", name));
+        }
+        let table_name = &identifier(list.find(|pair| pair.as_rule() == Rule::name).unwrap());
+        let table = match self.tables.get_mut(table_name) {
+            Some(table) => table,
+            None => return Err(format!("!Failed to create index {} as table {} does not exist", name, table_name)),
+        };
+        let exprs = list.filter(|pair| pair.as_rule() == Rule::expr).map(|pair| String::from(pair.as_str())).collect();
+        let rows: Vec<Vec<SQLValue>> = (0..table.len).map(|i| table.row(i)).collect();
+        let index = Index::new(name, exprs, &table.fields(), &rows.iter().map(Vec::as_slice).collect::<Vec<_>>())?;
+        table.indexes.push(index);
+        Ok(Some(format!("Index {} created.", name)))
+    }
+
+    fn drop_index(&mut self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
+        let name = &identifier(list.next().unwrap());
+        for table in self.tables.values_mut() {
+            if let Some(i) = table.indexes.iter().position(|index| &index.name == name) {
+                table.indexes.remove(i);
+                return Ok(Some(format!("Index {} deleted.", name)));
+            }
+        }
+        Err(format!("!Failed to delete index {} because it does not exist", name))
+    }
+
+    /// Runs the RETURNING clause of an INSERT, UPDATE or DELETE if it has one
+    /// rows are the values the affected rows end up with, or had before a delete
+    fn returning(&self, mut list: Pairs<Rule>, table_name: &str, rows: Vec<Vec<SQLValue>>) -> Result<Option<String>, String> {
+        match list.find(|pair| pair.as_rule() == Rule::returning) {
+            Some(pair) => {
+                let fields = self.tables[table_name].qualified_fields(table_name);
+                let result = Returning::parse(pair)?.execute(&Catalog::new(self), &fields, rows)?;
+                Ok(Some(result.show(self)))
+            },
+            None => Ok(None),
+        }
+    }
+
     /// Drops a table from this database
     fn drop(&mut self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
-        let table_name = list.next().unwrap().as_str();
+        let table_name = &identifier(list.next().unwrap());
         if self.tables.contains_key(table_name) {
             self.tables.remove(table_name);
             Ok(Some(format!("Table {} deleted.", table_name)))
@@ -385,10 +1032,36 @@ impl DataBase {
     
     /// Inserts data into table
     fn insert(&mut self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
-        let table_name = list.next().unwrap().as_str();
-        match self.tables.get_mut(table_name) {
+        let table_name = &identifier(list.next().unwrap());
+        match self.tables.get(table_name) {
             Some(table) => {
-                table.insert(list.next().unwrap())
+                // Every row is worked out before the table is touched so a bad row inserts nothing
+                let conflict = list.clone().find(|pair| pair.as_rule() == Rule::onConflict);
+                let rows = table.values(self, list.clone())?;
+                let (rows, changes) = table.upsert(self, table_name, rows, conflict)?;
+                let affected = rows.iter().cloned().chain(changes.iter().map(|(_, row)| row.clone())).collect();
+                let returning = self.returning(list, table_name, affected)?;
+                let table = self.tables.get_mut(table_name).unwrap();
+                let (len, next) = (table.len, table.auto_increment.clone());
+                let inserted = table.insert(rows)?.unwrap();
+                // Rows that conflicted are updated after the others are added, which are taken out again if that fails
+                let modified = match changes.is_empty() {
+                    true => None,
+                    false => match table.apply(changes) {
+                        Ok(modified) => modified,
+                        Err(e) => {
+                            let removed: Vec<bool> = (0..table.len).map(|i| i >= len).collect();
+                            table.drop_versions(&removed);
+                            table.auto_increment = next;
+                            return Err(e);
+                        },
+                    },
+                };
+                match (returning, modified) {
+                    (Some(returning), _) => Ok(Some(returning)),
+                    (None, None) => Ok(Some(inserted)),
+                    (None, Some(modified)) => Ok(Some(format!("{}, {}", inserted, modified))),
+                }
             },
             None => Err(format!("!Failed to insert into table {} as it does not exist.", table_name))
         }
@@ -396,10 +1069,13 @@ impl DataBase {
 
     /// Alters a table in the database
     fn alter(&mut self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
-        let table_name = list.next().unwrap().as_str();
-        match self.tables.get_mut(table_name) {
-            Some(table) => {
-                match table.alter(list) {
+        let table_name = &identifier(list.next().unwrap());
+        // Out of the database while it changes so defaults of new columns can be worked out against the rest of it
+        match self.tables.remove(table_name) {
+            Some(mut table) => {
+                let altered = table.alter(list, self);
+                self.tables.insert(table_name.clone(), table);
+                match altered {
                     Ok(_) => Ok(Some(format!("Table {} modified", table_name))),
                     Err(_) => Err(format!("Table {} not modified", table_name))
                 }
@@ -408,25 +1084,27 @@ impl DataBase {
         }
     }
 
-    /// Selects a table in the database
-    fn select(&self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
-        let select_args = list.next().unwrap();
-        let table_name = list.nth(1).unwrap().as_str();
-        match self.tables.get(table_name) {
-            Some(table) => {
-                table.select(select_args)
-            },
-            None => Err(format!("!Failed to query {} as it does not exist", table_name))
-        }
+    /// Runs a query against the tables in the database
+    fn select(&self, list: Pairs<Rule>) -> Result<Option<String>, String> {
+        let query = Select::parse(list)?;
+        Ok(Some(query.execute(&Catalog::new(self), None)?.show(self)))
     }
 
-    /// Creates a table in the database
+    /// Creates a table in the database, either from column definitions or from the result of a query
     fn create(&mut self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
-        let name = list.next().unwrap().as_str();
+        let name = &identifier(list.next().unwrap());
         match self.tables.get(name) {
             Some(_) => Err(format!("!Failed to create table {} because it already exists.", name)),
             None => {
-                self.tables.insert(String::from(name), Table::new(list));
+                let first = list.next().unwrap();
+                let table = match first.as_rule() {
+                    Rule::listDef => Table::new(first, self)?,
+                    _ => {
+                        let query = Select::parse(list.next().unwrap().into_inner())?;
+                        Table::from_result(query.execute(&Catalog::new(self), None)?, self)?
+                    },
+                };
+                self.tables.insert(String::from(name), table);
                 Ok(Some(format!("Table {} created.", name)))
             }
         }
@@ -440,230 +1118,1115 @@ enum SQLHeaderDef {
     Varchar(String, u32),
     Float(String),
     Int(String),
+    SmallInt(String),
+    BigInt(String),
+    Real(String),
+    Double(String),
+    Bool(String),
+    Text(String),
+    Blob(String),
+    Json(String),
+    /// Precision and scale
+    Decimal(String, u32, u32),
+    Date(String),
+    Time(String),
+    Timestamp(String),
+    TimestampTz(String),
+    Interval(String),
+    Uuid(String),
+    Enum(String, Enum),
+}
+
+/// Column, default and the text of its expression, key and AUTO_INCREMENT read from a columnDef
+type ColumnDef = (SQLHeaderDef, SQLValue, Option<String>, Option<bool>, bool);
+
+/// Evaluates an expression that reads no columns, against the database it runs in so it can use its sequences and tables
+fn constant(expr: Pair<Rule>, db: &DataBase) -> Result<(SQLType, SQLValue), String> {
+    let catalog = Catalog::new(db);
+    let scope = Scope::new(&catalog, &[], &[], None);
+    let expr = SQLExpr::parse(expr)?;
+    Ok((expr.check(&scope)?, expr.eval(&scope)?))
+}
+
+impl SQLHeaderDef {
+    /// A column of sql_type, an enum type is looked up in db
+    fn new(name: &str, sql_type: SQLType, db: &DataBase) -> Result<Self, String> {
+        Ok(match sql_type {
+            SQLType::Char(size) => SQLHeaderDef::Char(String::from(name), size),
+            SQLType::Varchar(size) => SQLHeaderDef::Varchar(String::from(name), size),
+            SQLType::Float => SQLHeaderDef::Float(String::from(name)),
+            SQLType::SmallInt => SQLHeaderDef::SmallInt(String::from(name)),
+            SQLType::BigInt => SQLHeaderDef::BigInt(String::from(name)),
+            SQLType::Real => SQLHeaderDef::Real(String::from(name)),
+            SQLType::Double => SQLHeaderDef::Double(String::from(name)),
+            SQLType::Bool => SQLHeaderDef::Bool(String::from(name)),
+            SQLType::Text => SQLHeaderDef::Text(String::from(name)),
+            SQLType::Blob => SQLHeaderDef::Blob(String::from(name)),
+            SQLType::Json => SQLHeaderDef::Json(String::from(name)),
+            SQLType::Decimal(precision, scale) => SQLHeaderDef::Decimal(String::from(name), precision, scale),
+            SQLType::Date => SQLHeaderDef::Date(String::from(name)),
+            SQLType::Time => SQLHeaderDef::Time(String::from(name)),
+            SQLType::Timestamp => SQLHeaderDef::Timestamp(String::from(name)),
+            SQLType::TimestampTz => SQLHeaderDef::TimestampTz(String::from(name)),
+            SQLType::Interval => SQLHeaderDef::Interval(String::from(name)),
+            SQLType::Uuid => SQLHeaderDef::Uuid(String::from(name)),
+            SQLType::Enum(id) => SQLHeaderDef::Enum(String::from(name), db.enum_type(id)?),
+            _ => SQLHeaderDef::Int(String::from(name)),
+        })
+    }
+
+    /// Reads a columnDef into the column, its default value which is NULL when it has none along with its expression,
+    /// its key which is Some(true) for PRIMARY KEY and Some(false) for UNIQUE
+    /// and whether it is AUTO_INCREMENT
+    /// A type given by name has to be one of the enum types of db
+    /// The default is worked out against db when the table has rows that need it, otherwise it is only checked
+    /// as it is worked out again for every row inserted
+    fn parse(def: Pair<Rule>, db: &DataBase, rows: bool) -> Result<ColumnDef, String> {
+        let mut it = def.into_inner();
+        let name = &identifier(it.next().unwrap());
+        let sql_type = it.next().unwrap();
+        let mut auto_increment = sql_type.as_rule() == Rule::serial;
+        let header = match sql_type.as_rule() {
+            Rule::serial => SQLHeaderDef::new(name, SQLType::Int, db)?,
+            Rule::name => match db.types.get(&identifier(sql_type.clone())) {
+                Some(def) => SQLHeaderDef::Enum(String::from(name), Enum::new(def.clone())),
+                None => return Err(format!("!Type {} does not exist", sql_type.as_str())),
+            },
+            _ => SQLHeaderDef::new(name, SQLType::parse(sql_type)?, db)?,
+        };
+        let mut default = None;
+        let mut source = None;
+        let mut key = None;
+        while let Some(pair) = it.next() {
+            match pair.as_rule() {
+                Rule::default => {
+                    let expr = it.next().unwrap();
+                    source = Some(String::from(expr.as_str()));
+                    let default_expr = SQLExpr::parse(expr)?;
+                    let catalog = Catalog::new(db);
+                    let scope = Scope::new(&catalog, &[], &[], None);
+                    let sql_type = default_expr.check(&scope)?;
+                    if !header.field().sql_type.accepts(sql_type) {
+                        return Err(format!("!Cannot assign {} to {} column {}", sql_type, header.type_name(), name));
+                    }
+                    default = Some(match rows {
+                        true => header.fit(default_expr.eval(&scope)?)?,
+                        false => SQLValue::Null,
+                    });
+                },
+                Rule::primaryKey => key = Some(true),
+                Rule::unique => key = key.or(Some(false)),
+                Rule::autoIncrement => auto_increment = true,
+                _ => (),
+            }
+        }
+        if auto_increment && !header.field().sql_type.is_integer() {
+            return Err(format!("!AUTO_INCREMENT column {} must be an integer", name));
+        }
+        if auto_increment && default.is_some() {
+            return Err(format!("!AUTO_INCREMENT column {} cannot have a DEFAULT", name));
+        }
+        Ok((header, default.unwrap_or(SQLValue::Null), source, key, auto_increment))
+    }
+
+    /// Converts a value of an accepted type to what is stored, text and numbers must fit the column
+    /// Text stored in a date, time, decimal, JSON or uuid column has to parse as one, text stored in a blob becomes its bytes
+    /// and text stored in an enum column has to be one of its labels
+    fn fit(&self, mut val: SQLValue) -> Result<SQLValue, String> {
+        let field = self.field();
+        let parsed = matches!((&val, field.sql_type), (SQLValue::Char(_), SQLType::Decimal(..)));
+        if let (SQLHeaderDef::Enum(_, kind), SQLValue::Char(label)) = (self, &val) {
+            val = SQLValue::Enum(kind.clone(), kind.ordinal(label)?);
+        } else if parsed || datetime::is_datetime(field.sql_type) || matches!(field.sql_type, SQLType::Blob | SQLType::Json | SQLType::Uuid) {
+            val = val.cast(field.sql_type)?;
+        }
+        if !val.fits(field.sql_type) {
+            return Err(format!("!Value {} is out of range for {} column {}", val, self.type_name(), field.name));
+        }
+        match val.coerce(field.sql_type) {
+            SQLValue::Char(val) if val.chars().count() > field.sql_type.size() as usize => {
+                Err(format!("!Value {} is too long for {} column {}", val, self.type_name(), field.name))
+            },
+            val => Ok(val),
+        }
+    }
+
+    /// Name of the column's type, enum types go by the name they were made with
+    fn type_name(&self) -> String {
+        match self {
+            SQLHeaderDef::Enum(_, kind) => kind.to_string(),
+            header => header.field().sql_type.to_string(),
+        }
+    }
+
+    /// Name and type of the column as seen by expressions
+    fn field(&self) -> Field {
+        match self {
+            SQLHeaderDef::Char(name, size) => Field { table: None, name: name.clone(), sql_type: SQLType::Char(*size) },
+            SQLHeaderDef::Varchar(name, size) => Field { table: None, name: name.clone(), sql_type: SQLType::Varchar(*size) },
+            SQLHeaderDef::Float(name) => Field { table: None, name: name.clone(), sql_type: SQLType::Float },
+            SQLHeaderDef::Int(name) => Field { table: None, name: name.clone(), sql_type: SQLType::Int },
+            SQLHeaderDef::SmallInt(name) => Field { table: None, name: name.clone(), sql_type: SQLType::SmallInt },
+            SQLHeaderDef::BigInt(name) => Field { table: None, name: name.clone(), sql_type: SQLType::BigInt },
+            SQLHeaderDef::Real(name) => Field { table: None, name: name.clone(), sql_type: SQLType::Real },
+            SQLHeaderDef::Double(name) => Field { table: None, name: name.clone(), sql_type: SQLType::Double },
+            SQLHeaderDef::Bool(name) => Field { table: None, name: name.clone(), sql_type: SQLType::Bool },
+            SQLHeaderDef::Text(name) => Field { table: None, name: name.clone(), sql_type: SQLType::Text },
+            SQLHeaderDef::Blob(name) => Field { table: None, name: name.clone(), sql_type: SQLType::Blob },
+            SQLHeaderDef::Json(name) => Field { table: None, name: name.clone(), sql_type: SQLType::Json },
+            SQLHeaderDef::Decimal(name, precision, scale) => Field { table: None, name: name.clone(), sql_type: SQLType::Decimal(*precision, *scale) },
+            SQLHeaderDef::Date(name) => Field { table: None, name: name.clone(), sql_type: SQLType::Date },
+            SQLHeaderDef::Time(name) => Field { table: None, name: name.clone(), sql_type: SQLType::Time },
+            SQLHeaderDef::Timestamp(name) => Field { table: None, name: name.clone(), sql_type: SQLType::Timestamp },
+            SQLHeaderDef::TimestampTz(name) => Field { table: None, name: name.clone(), sql_type: SQLType::TimestampTz },
+            SQLHeaderDef::Interval(name) => Field { table: None, name: name.clone(), sql_type: SQLType::Interval },
+            SQLHeaderDef::Uuid(name) => Field { table: None, name: name.clone(), sql_type: SQLType::Uuid },
+            SQLHeaderDef::Enum(name, kind) => Field { table: None, name: name.clone(), sql_type: SQLType::Enum(kind.id()) },
+        }
+    }
 }
 
+/// Values of one column, None is NULL
+/// Int keeps 64 bit storage so tables saved before the other widths existed still load
 #[derive(Debug, Serialize, Deserialize)]
 enum SQLColumn {
-    Char(Vec<String>),
-    Float(Vec<f64>),
-    Int(Vec<i64>)
+    Char(Vec<Option<String>>),
+    Float(Vec<Option<f64>>),
+    Int(Vec<Option<i64>>),
+    SmallInt(Vec<Option<i16>>),
+    BigInt(Vec<Option<i64>>),
+    Real(Vec<Option<f32>>),
+    Double(Vec<Option<f64>>),
+    Bool(Vec<Option<bool>>),
+    Text(Vec<Option<Lob>>),
+    Blob(Vec<Option<Lob>>),
+    Json(Vec<Option<serde_json::Value>>),
+    Decimal(Vec<Option<Decimal>>),
+    Date(Vec<Option<i32>>),
+    Time(Vec<Option<i64>>),
+    Timestamp(Vec<Option<i64>>),
+    TimestampTz(Vec<Option<i64>>),
+    Interval(Vec<Option<Interval>>),
+    Uuid(Vec<Option<Uuid>>),
+    /// Position of each value's label in the enum
+    Enum(Enum, Vec<Option<u32>>),
+}
+
+impl SQLColumn {
+    fn new(header: &SQLHeaderDef) -> Self {
+        if let SQLHeaderDef::Enum(_, kind) = header {
+            return SQLColumn::Enum(kind.clone(), Vec::new());
+        }
+        match header.field().sql_type {
+            SQLType::Char(_) | SQLType::Varchar(_) => SQLColumn::Char(Vec::new()),
+            SQLType::Float => SQLColumn::Float(Vec::new()),
+            SQLType::SmallInt => SQLColumn::SmallInt(Vec::new()),
+            SQLType::BigInt => SQLColumn::BigInt(Vec::new()),
+            SQLType::Real => SQLColumn::Real(Vec::new()),
+            SQLType::Double => SQLColumn::Double(Vec::new()),
+            SQLType::Bool => SQLColumn::Bool(Vec::new()),
+            SQLType::Text => SQLColumn::Text(Vec::new()),
+            SQLType::Blob => SQLColumn::Blob(Vec::new()),
+            SQLType::Json => SQLColumn::Json(Vec::new()),
+            SQLType::Decimal(..) => SQLColumn::Decimal(Vec::new()),
+            SQLType::Date => SQLColumn::Date(Vec::new()),
+            SQLType::Time => SQLColumn::Time(Vec::new()),
+            SQLType::Timestamp => SQLColumn::Timestamp(Vec::new()),
+            SQLType::TimestampTz => SQLColumn::TimestampTz(Vec::new()),
+            SQLType::Interval => SQLColumn::Interval(Vec::new()),
+            SQLType::Uuid => SQLColumn::Uuid(Vec::new()),
+            _ => SQLColumn::Int(Vec::new()),
+        }
+    }
+
+    /// Reads the value at row i
+    fn get(&self, i: usize) -> SQLValue {
+        match self {
+            SQLColumn::Char(vals) => vals[i].clone().map_or(SQLValue::Null, SQLValue::Char),
+            SQLColumn::Float(vals) => vals[i].map_or(SQLValue::Null, SQLValue::Float),
+            SQLColumn::Int(vals) => vals[i].map_or(SQLValue::Null, SQLValue::Int),
+            SQLColumn::SmallInt(vals) => vals[i].map_or(SQLValue::Null, |val| SQLValue::Int(val as i64)),
+            SQLColumn::BigInt(vals) => vals[i].map_or(SQLValue::Null, SQLValue::Int),
+            SQLColumn::Real(vals) => vals[i].map_or(SQLValue::Null, |val| SQLValue::Float(expr::single(val as f64))),
+            SQLColumn::Double(vals) => vals[i].map_or(SQLValue::Null, SQLValue::Float),
+            SQLColumn::Bool(vals) => vals[i].map_or(SQLValue::Null, SQLValue::Bool),
+            SQLColumn::Text(vals) => vals[i].as_ref().map_or(SQLValue::Null, |val| SQLValue::Char(val.text())),
+            SQLColumn::Blob(vals) => vals[i].as_ref().map_or(SQLValue::Null, |val| SQLValue::Blob(val.bytes().to_vec())),
+            SQLColumn::Json(vals) => vals[i].clone().map_or(SQLValue::Null, SQLValue::Json),
+            SQLColumn::Decimal(vals) => vals[i].map_or(SQLValue::Null, SQLValue::Decimal),
+            SQLColumn::Date(vals) => vals[i].map_or(SQLValue::Null, SQLValue::Date),
+            SQLColumn::Time(vals) => vals[i].map_or(SQLValue::Null, SQLValue::Time),
+            SQLColumn::Timestamp(vals) => vals[i].map_or(SQLValue::Null, SQLValue::Timestamp),
+            SQLColumn::TimestampTz(vals) => vals[i].map_or(SQLValue::Null, SQLValue::TimestampTz),
+            SQLColumn::Interval(vals) => vals[i].map_or(SQLValue::Null, SQLValue::Interval),
+            SQLColumn::Uuid(vals) => vals[i].map_or(SQLValue::Null, SQLValue::Uuid),
+            SQLColumn::Enum(kind, vals) => vals[i].map_or(SQLValue::Null, |val| SQLValue::Enum(kind.clone(), val)),
+        }
+    }
+
+    /// Overwrites the value at row i, converting ints into floats where needed
+    /// Values are expected to have been range checked by SQLHeaderDef::fit
+    fn set(&mut self, i: usize, val: SQLValue) -> Result<(), String> {
+        match (self, val) {
+            (SQLColumn::Char(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Float(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Int(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::SmallInt(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::BigInt(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Real(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Double(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Bool(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Text(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Blob(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Json(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Decimal(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Date(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Time(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Timestamp(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::TimestampTz(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Interval(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Uuid(vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Enum(_, vals), SQLValue::Null) => vals[i] = None,
+            (SQLColumn::Char(vals), SQLValue::Char(val)) => vals[i] = Some(val),
+            (SQLColumn::Float(vals), SQLValue::Float(val)) => vals[i] = Some(val),
+            (SQLColumn::Float(vals), SQLValue::Int(val)) => vals[i] = Some(val as f64),
+            (SQLColumn::Int(vals), SQLValue::Int(val)) => vals[i] = Some(val),
+            (SQLColumn::SmallInt(vals), SQLValue::Int(val)) => match i16::try_from(val) {
+                Ok(val) => vals[i] = Some(val),
+                Err(_) => return Err(format!("!Value {} is out of range for smallint", val)),
+            },
+            (SQLColumn::BigInt(vals), SQLValue::Int(val)) => vals[i] = Some(val),
+            (SQLColumn::Real(vals), SQLValue::Float(val)) => vals[i] = Some(val as f32),
+            (SQLColumn::Real(vals), SQLValue::Int(val)) => vals[i] = Some(val as f32),
+            (SQLColumn::Double(vals), SQLValue::Float(val)) => vals[i] = Some(val),
+            (SQLColumn::Double(vals), SQLValue::Int(val)) => vals[i] = Some(val as f64),
+            (SQLColumn::Bool(vals), SQLValue::Bool(val)) => vals[i] = Some(val),
+            (SQLColumn::Text(vals), SQLValue::Char(val)) => vals[i] = Some(Lob::new(val.into_bytes())),
+            (SQLColumn::Blob(vals), SQLValue::Blob(val)) => vals[i] = Some(Lob::new(val)),
+            (SQLColumn::Json(vals), SQLValue::Json(val)) => vals[i] = Some(val),
+            (SQLColumn::Decimal(vals), SQLValue::Decimal(val)) => vals[i] = Some(val),
+            (SQLColumn::Date(vals), SQLValue::Date(val)) => vals[i] = Some(val),
+            (SQLColumn::Time(vals), SQLValue::Time(val)) => vals[i] = Some(val),
+            (SQLColumn::Timestamp(vals), SQLValue::Timestamp(val)) => vals[i] = Some(val),
+            (SQLColumn::TimestampTz(vals), SQLValue::TimestampTz(val)) => vals[i] = Some(val),
+            (SQLColumn::Interval(vals), SQLValue::Interval(val)) => vals[i] = Some(val),
+            (SQLColumn::Uuid(vals), SQLValue::Uuid(val)) => vals[i] = Some(val),
+            (SQLColumn::Enum(kind, vals), SQLValue::Enum(other, val)) if *kind == other => vals[i] = Some(val),
+            (_, val) => return Err(format!("!Value {} does not match the column type", val)),
+        }
+        Ok(())
+    }
+
+    /// Adds a row holding NULL and then sets it to val
+    fn push(&mut self, val: SQLValue) -> Result<(), String> {
+        let i = self.len();
+        match self {
+            SQLColumn::Char(vals) => vals.push(None),
+            SQLColumn::Float(vals) => vals.push(None),
+            SQLColumn::Int(vals) => vals.push(None),
+            SQLColumn::SmallInt(vals) => vals.push(None),
+            SQLColumn::BigInt(vals) => vals.push(None),
+            SQLColumn::Real(vals) => vals.push(None),
+            SQLColumn::Double(vals) => vals.push(None),
+            SQLColumn::Bool(vals) => vals.push(None),
+            SQLColumn::Text(vals) => vals.push(None),
+            SQLColumn::Blob(vals) => vals.push(None),
+            SQLColumn::Json(vals) => vals.push(None),
+            SQLColumn::Decimal(vals) => vals.push(None),
+            SQLColumn::Date(vals) => vals.push(None),
+            SQLColumn::Time(vals) => vals.push(None),
+            SQLColumn::Timestamp(vals) => vals.push(None),
+            SQLColumn::TimestampTz(vals) => vals.push(None),
+            SQLColumn::Interval(vals) => vals.push(None),
+            SQLColumn::Uuid(vals) => vals.push(None),
+            SQLColumn::Enum(_, vals) => vals.push(None),
+        }
+        self.set(i, val)
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            SQLColumn::Char(vals) => vals.len(),
+            SQLColumn::Float(vals) => vals.len(),
+            SQLColumn::Int(vals) => vals.len(),
+            SQLColumn::SmallInt(vals) => vals.len(),
+            SQLColumn::BigInt(vals) => vals.len(),
+            SQLColumn::Real(vals) => vals.len(),
+            SQLColumn::Double(vals) => vals.len(),
+            SQLColumn::Bool(vals) => vals.len(),
+            SQLColumn::Text(vals) => vals.len(),
+            SQLColumn::Blob(vals) => vals.len(),
+            SQLColumn::Json(vals) => vals.len(),
+            SQLColumn::Decimal(vals) => vals.len(),
+            SQLColumn::Date(vals) => vals.len(),
+            SQLColumn::Time(vals) => vals.len(),
+            SQLColumn::Timestamp(vals) => vals.len(),
+            SQLColumn::TimestampTz(vals) => vals.len(),
+            SQLColumn::Interval(vals) => vals.len(),
+            SQLColumn::Uuid(vals) => vals.len(),
+            SQLColumn::Enum(_, vals) => vals.len(),
+        }
+    }
+
+    fn truncate(&mut self, len: usize) {
+        match self {
+            SQLColumn::Char(vals) => vals.truncate(len),
+            SQLColumn::Float(vals) => vals.truncate(len),
+            SQLColumn::Int(vals) => vals.truncate(len),
+            SQLColumn::SmallInt(vals) => vals.truncate(len),
+            SQLColumn::BigInt(vals) => vals.truncate(len),
+            SQLColumn::Real(vals) => vals.truncate(len),
+            SQLColumn::Double(vals) => vals.truncate(len),
+            SQLColumn::Bool(vals) => vals.truncate(len),
+            SQLColumn::Text(vals) => vals.truncate(len),
+            SQLColumn::Blob(vals) => vals.truncate(len),
+            SQLColumn::Json(vals) => vals.truncate(len),
+            SQLColumn::Decimal(vals) => vals.truncate(len),
+            SQLColumn::Date(vals) => vals.truncate(len),
+            SQLColumn::Time(vals) => vals.truncate(len),
+            SQLColumn::Timestamp(vals) => vals.truncate(len),
+            SQLColumn::TimestampTz(vals) => vals.truncate(len),
+            SQLColumn::Interval(vals) => vals.truncate(len),
+            SQLColumn::Uuid(vals) => vals.truncate(len),
+            SQLColumn::Enum(_, vals) => vals.truncate(len),
+        }
+    }
+
+    /// Drops the rows marked in removed, which has an entry per row
+    fn remove(&mut self, removed: &[bool]) {
+        let mut keep = removed.iter().map(|removed| !removed);
+        match self {
+            SQLColumn::Char(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Float(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Int(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::SmallInt(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::BigInt(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Real(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Double(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Bool(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Text(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Blob(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Json(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Decimal(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Date(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Time(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Timestamp(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::TimestampTz(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Interval(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Uuid(vals) => vals.retain(|_| keep.next().unwrap()),
+            SQLColumn::Enum(_, vals) => vals.retain(|_| keep.next().unwrap()),
+        }
+    }
+
+    /// Moves large TEXT and BLOB values to overflow files before the dump is written
+    fn spill(&mut self, dir: &Path, used: &mut HashSet<String>) -> Result<(), String> {
+        match self {
+            SQLColumn::Text(vals) | SQLColumn::Blob(vals) => vals.iter_mut().flatten().try_for_each(|val| val.spill(dir, used)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads TEXT and BLOB values back from overflow files after the dump is loaded
+    fn fill(&mut self, dir: &Path) -> Result<(), String> {
+        match self {
+            SQLColumn::Text(vals) | SQLColumn::Blob(vals) => vals.iter_mut().flatten().try_for_each(|val| val.fill(dir)),
+            _ => Ok(()),
+        }
+    }
 }
 
+/// (row, new values) for every row a statement changes
+type Changes = Vec<(usize, Vec<SQLValue>)>;
+
+/// col = expr assignments and the WHERE clause of an UPDATE
+type Assignments = (Vec<(usize, SQLExpr)>, Option<SQLExpr>);
+
 /// Currently just holds the header data of the table
 #[derive(Serialize, Deserialize)]
 struct Table {
     header: Vec<SQLHeaderDef>,
     data: Vec<SQLColumn>,
-    len: usize
+    len: usize,
+    /// Value of each column when an INSERT leaves it out, NULL when it has no DEFAULT
+    #[serde(default)]
+    defaults: Vec<SQLValue>,
+    /// Text of each column's DEFAULT expression, evaluated again for every row an INSERT adds
+    /// so gen_random_uuid() and NOW() differ between rows
+    #[serde(default)]
+    default_exprs: Vec<Option<String>>,
+    #[serde(default)]
+    keys: Vec<Key>,
+    /// Next value of each AUTO_INCREMENT column, None for other columns
+    #[serde(default)]
+    auto_increment: Vec<Option<i64>>,
+    /// When each row version was created and deleted, tables saved before versions existed have none stored
+    #[serde(default)]
+    versions: Vec<Version>,
+    #[serde(default)]
+    indexes: Vec<Index>,
 }
 
 impl Table {
     /// Creates a new table and populates the header
-    fn new(mut list: Pairs<Rule>) -> Self {
-        let mut header: Vec<SQLHeaderDef> = Vec::new();
-        let mut data: Vec<SQLColumn> = Vec::new();
-        for element in list.next().unwrap().into_inner() {
+    /// Types and defaults of its columns are read against db, the database it is made in
+    fn new(list: Pair<Rule>, db: &DataBase) -> Result<Self, String> {
+        let mut table = Self {
+            header: Vec::new(),
+            data: Vec::new(),
+            len: 0,
+            defaults: Vec::new(),
+            default_exprs: Vec::new(),
+            keys: Vec::new(),
+            auto_increment: Vec::new(),
+            versions: Vec::new(),
+            indexes: Vec::new(),
+        };
+        let mut keys = Vec::new();
+        for element in list.into_inner() {
             match element.as_rule() {
-                Rule::columnDef => {
-                    let mut it = element.into_inner();
-                    let name = it.next().unwrap().as_str();
-                    let column = it.next().unwrap();
-                    match column.as_rule() {
-                        Rule::char => {
-                            data.push(SQLColumn::Char(Vec::new()));
-                            header.push(
-                                SQLHeaderDef::Char(
-                                    String::from(name), 
-                                    column.into_inner().nth(1).unwrap().as_str().parse::<u32>().unwrap())
-                            )
-                        },
-                        Rule::varchar => {
-                            data.push(SQLColumn::Char(Vec::new()));
-                            header.push(
-                                SQLHeaderDef::Varchar(
-                                    String::from(name), 
-                                    column.into_inner().nth(1).unwrap().as_str().parse::<u32>().unwrap())
-                            )
-                        }
-                        Rule::float => {
-                            data.push(SQLColumn::Float(Vec::new()));
-                            header.push(
-                                SQLHeaderDef::Float(
-                                    String::from(name)
-                                )
-                            )
-                        }
-                        Rule::int => {
-                            data.push(SQLColumn::Int(Vec::new()));
-                            header.push(
-                                SQLHeaderDef::Int(
-                                    String::from(name)
-                                )
-                            )
-                        }
-                        _ => (),
-                    };
-                }
+                Rule::columnDef => table.add_column_def(element, db)?,
+                // Table keys can name columns defined after them
+                Rule::keyDef => keys.push(element),
                 _ => ()
             }
         }
-        Self {
-            header,
-            data,
+        for key in keys {
+            let mut it = key.into_inner();
+            let primary = it.next().unwrap().as_rule() == Rule::primaryKey;
+            let columns = table.column_list(it.next().unwrap())?;
+            table.add_key(Key { columns, primary })?;
+        }
+        Ok(table)
+    }
+
+    /// Adds a column from a columnDef along with the key it declares
+    fn add_column_def(&mut self, def: Pair<Rule>, db: &DataBase) -> Result<(), String> {
+        let (header, default, source, key, auto_increment) = SQLHeaderDef::parse(def, db, self.len > 0)?;
+        self.add_column(header, default)?;
+        let idx = self.header.len() - 1;
+        self.default_exprs[idx] = source;
+        if auto_increment {
+            // Rows already in the table are numbered in order
+            let rows: Vec<usize> = self.current().collect();
+            for (n, i) in rows.iter().enumerate() {
+                self.data[idx].set(*i, SQLValue::Int(n as i64 + 1))?;
+            }
+            self.auto_increment[idx] = Some(rows.len() as i64 + 1);
+        }
+        if let Some(primary) = key {
+            if let Err(e) = self.add_key(Key { columns: vec![idx], primary }) {
+                self.header.pop();
+                self.data.pop();
+                self.defaults.pop();
+                self.default_exprs.pop();
+                self.auto_increment.pop();
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a key, the rows already in the table must not break it
+    fn add_key(&mut self, key: Key) -> Result<(), String> {
+        if key.primary && self.keys.iter().any(|key| key.primary) {
+            return Err(String::from("!Table can only have one primary key"));
+        }
+        let fields = self.fields();
+        KeyIndex::new(std::slice::from_ref(&key), &fields, self.current().map(|i| (i, self.row(i))))?;
+        self.keys.push(key);
+        Ok(())
+    }
+
+    /// Positions of the columns named in a columnList
+    fn column_list(&self, list: Pair<Rule>) -> Result<Vec<usize>, String> {
+        let fields = self.fields();
+        let mut columns = Vec::new();
+        for name in list.into_inner().filter(|pair| pair.as_rule() == Rule::name) {
+            let name = identifier(name);
+            let idx = match fields.iter().position(|field| field.name == name) {
+                Some(i) => i,
+                None => return Err(format!("!Column {} does not exist", name)),
+            };
+            if columns.contains(&idx) {
+                return Err(format!("!Column {} is listed more than once", name));
+            }
+            columns.push(idx);
+        }
+        Ok(columns)
+    }
+
+    /// Creates a table holding a query result, the columns take the names and types of the result
+    fn from_result(result: ResultSet, db: &DataBase) -> Result<Self, String> {
+        let mut table = Self {
+            header: Vec::new(),
+            data: Vec::new(),
             len: 0,
+            defaults: Vec::new(),
+            default_exprs: Vec::new(),
+            keys: Vec::new(),
+            auto_increment: Vec::new(),
+            versions: Vec::new(),
+            indexes: Vec::new(),
+        };
+        for field in &result.fields {
+            if field.sql_type == SQLType::Null {
+                return Err(format!("!Column {} is {} which cannot be stored in a table", field.name, field.sql_type));
+            }
+            table.add_column(SQLHeaderDef::new(&field.name, field.sql_type, db)?, SQLValue::Null)?;
+        }
+        let mut rows = Vec::new();
+        for row in result.rows {
+            rows.push(table.fit(row.into_iter().enumerate(), &mut Vec::new(), db)?);
+        }
+        table.insert(rows)?;
+        Ok(table)
+    }
+
+    /// Adds a column, existing rows take its default
+    fn add_column(&mut self, header: SQLHeaderDef, default: SQLValue) -> Result<(), String> {
+        let field = header.field();
+        if self.header.iter().any(|column| column.field().name == field.name) {
+            return Err(format!("!Column {} already exists", field.name));
+        }
+        let mut column = SQLColumn::new(&header);
+        for _ in 0..self.len {
+            column.push(default.clone())?;
         }
+        // Tables saved before defaults existed have none stored
+        self.defaults.resize(self.header.len(), SQLValue::Null);
+        self.default_exprs.resize(self.header.len(), None);
+        self.auto_increment.resize(self.header.len(), None);
+        self.header.push(header);
+        self.data.push(column);
+        self.defaults.push(default);
+        self.default_exprs.push(None);
+        self.auto_increment.push(None);
+        Ok(())
     }
+
     /// Updates entries in the table
-    fn update(&mut self, mut list: Pairs<Rule>, id: &str) -> Result<Option<String>, String> {
-        if !std::path::Path::new(&format!("{}.lock", "dbms")).exists() || std::fs::read_to_string(&format!("{}.lock", "dbms")).unwrap() == id {
-            let set_name = list.nth(0).unwrap().as_str();
-            let mut _set_idx = 0;
-            let set_val = list.nth(0).unwrap().as_str().trim().parse::<i64>().unwrap();
-            let mut list = list.nth(0).unwrap().into_inner();
-            let where_name = list.nth(0).unwrap().as_str();
-            let mut _where_idx = 0;
-            let where_val = list.nth(1).unwrap().as_str().trim().parse::<i64>().unwrap();
-            //let mut set_val;
-            for i in 0..self.header.len() {
-                if let SQLHeaderDef::Int(name) = self.header.get_mut(i).unwrap() {
-                    if name.as_str() == where_name {
-                        _where_idx = i;
-                    }
-                    if name.as_str() == set_name {
-                        _set_idx = i;
-                    }
+    /// Works out the new values of every row an update touches
+    fn update(&self, db: &DataBase, table_name: &str, list: Pairs<Rule>) -> Result<Changes, String> {
+        let fields = self.qualified_fields(table_name);
+        let catalog = Catalog::new(db);
+        let scope = Scope::new(&catalog, &fields, &[], None);
+        let (assignments, filter) = self.parse_assignments(list, &scope)?;
+        // Every new value is computed from the old row before anything is written
+        let mut changes = Vec::new();
+        let mut rows = Vec::new();
+        for i in self.visible(db.snapshot) {
+            let row = self.row(i);
+            let scope = Scope::new(&catalog, &fields, &row, None);
+            if let Some(filter) = &filter {
+                if filter.eval(&scope)? != SQLValue::Bool(true) {
+                    continue;
                 }
             }
-            if let (SQLColumn::Int(where_vals), [SQLColumn::Int(set_vals)]) = self.data.split_first_mut().unwrap() {
-                for i in 0..self.len {
-                    if where_vals[i] == where_val {
-                        set_vals[i] = set_val;
-                    }
+            self.check_current(i)?;
+            let mut new = row.clone();
+            for (idx, expr) in &assignments {
+                new[*idx] = self.header[*idx].fit(expr.eval(&scope)?)?;
+            }
+            rows.push(row);
+            changes.push((i, new));
+        }
+        // Old key values are all dropped first so rows can swap values
+        let mut index = KeyIndex::new(&self.keys, &fields, self.current().map(|i| (i, self.row(i))))?;
+        for old in &rows {
+            index.remove(old);
+        }
+        for (i, new) in &changes {
+            index.insert(*i, new)?;
+        }
+        Ok(changes)
+    }
+
+    /// Reads TEXT and BLOB values back from overflow files after the table is loaded
+    fn fill(&mut self, dir: &Path) -> Result<(), String> {
+        for index in self.indexes.iter_mut() {
+            index.build();
+        }
+        self.data.iter_mut().try_for_each(|column| column.fill(dir))
+    }
+
+    /// Swaps the enum types read with only their ids for the definitions in types
+    fn link(&mut self, types: &HashMap<u32, Enum>) {
+        let linked = |kind: &mut Enum| if let Some(def) = types.get(&kind.id()) {
+            *kind = def.clone();
+        };
+        for header in self.header.iter_mut() {
+            if let SQLHeaderDef::Enum(_, kind) = header {
+                linked(kind);
+            }
+        }
+        for column in self.data.iter_mut() {
+            if let SQLColumn::Enum(kind, _) = column {
+                linked(kind);
+            }
+        }
+        for val in self.defaults.iter_mut() {
+            if let SQLValue::Enum(kind, _) = val {
+                linked(kind);
+            }
+        }
+    }
+
+    /// Columns of the table qualified with its name
+    fn qualified_fields(&self, table_name: &str) -> Vec<Field> {
+        let mut fields = self.fields();
+        for field in fields.iter_mut() {
+            field.table = Some(String::from(table_name));
+        }
+        fields
+    }
+
+    /// Reads the col = expr assignments and WHERE clause of an UPDATE or ON CONFLICT DO UPDATE
+    fn parse_assignments(&self, list: Pairs<Rule>, scope: &Scope) -> Result<Assignments, String> {
+        let fields = self.fields();
+        let mut assignments = Vec::new();
+        let mut filter = None;
+        for pair in list {
+            match pair.as_rule() {
+                Rule::assignment => {
+                    let mut it = pair.into_inner();
+                    let name = identifier(it.next().unwrap());
+                    let idx = match fields.iter().position(|field| field.name == name) {
+                        Some(i) => i,
+                        None => return Err(format!("!Column {} does not exist", name)),
+                    };
+                    let expr = SQLExpr::parse(it.next().unwrap())?;
+                    self.check_assign(idx, expr.check(scope)?)?;
+                    assignments.push((idx, expr));
+                },
+                Rule::r#where => {
+                    let expr = SQLExpr::parse(pair.into_inner().next().unwrap())?;
+                    select::check_filter(&expr, scope)?;
+                    filter = Some(expr);
+                },
+                _ => (),
+            }
+        }
+        Ok((assignments, filter))
+    }
+
+    /// Works out what an INSERT does given its rows, as rows to add and changes to existing rows
+    /// A row that repeats a key is an error unless ON CONFLICT covers that key
+    /// DO NOTHING drops the row and DO UPDATE changes the existing row instead,
+    /// where excluded.col is the value the row would have inserted
+    fn upsert(&self, db: &DataBase, table_name: &str, rows: Vec<Vec<SQLValue>>, conflict: Option<Pair<Rule>>) -> Result<(Vec<Vec<SQLValue>>, Changes), String> {
+        let table_fields = self.qualified_fields(table_name);
+        let mut index = KeyIndex::new(&self.keys, &table_fields, self.current().map(|i| (i, self.row(i))))?;
+        // Both the existing row and the excluded row are in scope for DO UPDATE
+        let mut fields = table_fields.clone();
+        fields.extend(self.qualified_fields("excluded"));
+        let catalog = Catalog::new(db);
+        let scope = Scope::new(&catalog, &fields, &[], None);
+        let mut target = None;
+        let mut action = None;
+        if let Some(conflict) = conflict {
+            for pair in conflict.into_inner() {
+                match pair.as_rule() {
+                    Rule::columnList => {
+                        let mut columns = self.column_list(pair)?;
+                        columns.sort_unstable();
+                        target = match self.keys.iter().position(|key| {
+                            let mut key = key.columns.clone();
+                            key.sort_unstable();
+                            key == columns
+                        }) {
+                            Some(k) => Some(k),
+                            None => return Err(String::from("!ON CONFLICT columns must match a primary or unique key")),
+                        };
+                    },
+                    Rule::nothing => action = Some(None),
+                    Rule::doUpdate => action = Some(Some(self.parse_assignments(pair.into_inner(), &scope)?)),
+                    _ => (),
                 }
             }
-            Err(String::from("1 record modified"))
-        } else {
-            Err(format!("Error: Table {} is locked!", "Flights"))
-        }
-    }
-    /// Deletes entries in the table
-    fn delete(&mut self, _list: Pairs<Rule>) -> Result<Option<String>, String> {
-        Err(String::from(""))
-    }
-    /// Inserts new values into table
-    fn insert(&mut self, list: Pair<Rule>) -> Result<Option<String>, String> {
-        let vals = list.into_inner();
-        let mut i = 0;
-        for val in vals {
-            match val.as_rule() {
-                Rule::columnVal => {
-                    let val = val.into_inner().next().unwrap();
-                    match val.as_rule() {
-                        Rule::floatVal => {
-                            if let SQLColumn::Int(ref mut col) = self.data.get_mut(i).unwrap() {
-                                col.push(val.as_str().parse::<i64>().unwrap());
-                                i += 1;
-                            } else if let SQLColumn::Float(ref mut col) = self.data.get_mut(i).unwrap() {
-                                col.push(val.as_str().parse::<f64>().unwrap());
-                                i += 1;
-                            }
-                        },
-                        Rule::charVal => {
-                            if let SQLColumn::Char(ref mut col) = self.data.get_mut(i).unwrap() {
-                                col.push(String::from(val.as_str()));
-                                i += 1;
-                            }
-                        },
-                        Rule::intVal => {
-                            if let SQLColumn::Int(ref mut col) = self.data.get_mut(i).unwrap() {
-                                col.push(val.as_str().parse::<i64>().unwrap());
-                                i += 1;
-                            }
-                        }
-                        _ => ()
-                    }
+            if target.is_none() && matches!(action, Some(Some(_))) {
+                return Err(String::from("!ON CONFLICT DO UPDATE needs the key columns"));
+            }
+        }
+        let mut inserted = Vec::new();
+        let mut updated: Changes = Vec::new();
+        for row in rows {
+            let i = match index.conflict(&row) {
+                Some((k, i)) if action.is_some() && target.is_none_or(|target| target == k) => i,
+                _ => {
+                    index.insert(self.len + inserted.len(), &row)?;
+                    inserted.push(row);
+                    continue;
                 },
-                _ => ()
+            };
+            let (assignments, filter) = match &action {
+                Some(Some(update)) => update,
+                _ => continue,
+            };
+            if i >= self.len || updated.iter().any(|(j, _)| *j == i) {
+                return Err(String::from("!ON CONFLICT DO UPDATE cannot change the same row twice"));
+            }
+            // The row holding the key was added after the snapshot
+            if !self.version(i).visible(db.snapshot) {
+                return Err(mvcc::serialization_failure(mvcc::CONCURRENT_UPDATE));
+            }
+            let old = self.row(i);
+            let values: Vec<SQLValue> = old.iter().chain(&row).cloned().collect();
+            let scope = Scope::new(&catalog, &fields, &values, None);
+            if let Some(filter) = filter {
+                if filter.eval(&scope)? != SQLValue::Bool(true) {
+                    continue;
+                }
+            }
+            let mut new = old.clone();
+            for (idx, expr) in assignments {
+                new[*idx] = self.header[*idx].fit(expr.eval(&scope)?)?;
+            }
+            index.remove(&old);
+            index.insert(i, &new)?;
+            updated.push((i, new));
+        }
+        Ok((inserted, updated))
+    }
+
+    /// Writes the changes worked out by update, either all of them or none
+    /// Rows are changed in place so they keep their order,
+    /// a version saved before is first copied to the end of the table for older snapshots to read
+    fn apply(&mut self, changes: Changes) -> Result<Option<String>, String> {
+        let modified = changes.len();
+        let keys = self.index_keys(&changes.iter().map(|(_, row)| row.as_slice()).collect::<Vec<_>>())?;
+        self.versions.resize(self.len, Version::committed());
+        let len = self.len;
+        // What the changed rows held before, put back if a value can't be written
+        let old: Vec<(usize, Vec<SQLValue>, Version)> = changes.iter().map(|(i, _)| (*i, self.row(*i), self.versions[*i])).collect();
+        let mut copied = Vec::new();
+        if let Err(e) = self.write_changes(changes, &mut copied) {
+            for column in self.data.iter_mut() {
+                column.truncate(len);
+            }
+            self.versions.truncate(len);
+            self.len = len;
+            for (i, row, version) in old.into_iter().rev() {
+                for (column, val) in self.data.iter_mut().zip(row) {
+                    column.set(i, val)?;
+                }
+                self.versions[i] = version;
+            }
+            return Err(e);
+        }
+        for (n, (i, copied)) in copied.into_iter().enumerate() {
+            for (index, keys) in self.indexes.iter_mut().zip(&keys) {
+                if copied {
+                    index.copy(i);
+                }
+                index.set(i, keys[n].clone());
             }
         }
-        self.len += 1;
-        Ok(Some(format!("1 new record inserted")))
+        match modified {
+            1 => Ok(Some(String::from("1 record modified."))),
+            n => Ok(Some(format!("{} records modified.", n))),
+        }
     }
-    /// Selects what is needed from the table
-    fn select(&self, _list: Pair<Rule>) -> Result<Option<String>, String> {
-        let mut out = String::new();
-        for column in &self.header {
-            match column {
-                SQLHeaderDef::Int(name) => {
-                    out.push_str(format!("{} int | ", name).as_str());
+
+    /// Writes the rows of apply, noting for each change whether its old version was copied to the end of the table
+    fn write_changes(&mut self, changes: Changes, copied: &mut Vec<(usize, bool)>) -> Result<(), String> {
+        for (i, row) in changes {
+            let copy = self.versions[i].created != PENDING;
+            if copy {
+                let old = self.row(i);
+                for (column, val) in self.data.iter_mut().zip(old) {
+                    column.push(val)?;
+                }
+                self.versions.push(Version { created: self.versions[i].created, deleted: Some(PENDING) });
+                self.versions[i] = Version::pending();
+                self.len += 1;
+            }
+            copied.push((i, copy));
+            for (column, val) in self.data.iter_mut().zip(row) {
+                column.set(i, val)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Works out which rows a delete removes, every row without a WHERE clause
+    fn delete(&self, db: &DataBase, table_name: &str, list: Pairs<Rule>) -> Result<Vec<usize>, String> {
+        let fields = self.qualified_fields(table_name);
+        let catalog = Catalog::new(db);
+        let mut filter = None;
+        for pair in list {
+            if pair.as_rule() == Rule::r#where {
+                let expr = SQLExpr::parse(pair.into_inner().next().unwrap())?;
+                select::check_filter(&expr, &Scope::new(&catalog, &fields, &[], None))?;
+                filter = Some(expr);
+            }
+        }
+        let mut rows = Vec::new();
+        for i in self.visible(db.snapshot) {
+            if let Some(filter) = &filter {
+                if filter.eval(&Scope::new(&catalog, &fields, &self.row(i), None))? != SQLValue::Bool(true) {
+                    continue;
+                }
+            }
+            self.check_current(i)?;
+            rows.push(i);
+        }
+        Ok(rows)
+    }
+
+    /// Removes the rows worked out by delete
+    /// Versions saved before are only marked deleted, older snapshots may still read them
+    fn remove(&mut self, rows: &[usize]) -> Result<Option<String>, String> {
+        self.versions.resize(self.len, Version::committed());
+        let mut removed = vec![false; self.len];
+        for i in rows {
+            if self.versions[*i].created == PENDING {
+                removed[*i] = true;
+            } else {
+                self.versions[*i].deleted = Some(PENDING);
+            }
+        }
+        self.drop_versions(&removed);
+        match rows.len() {
+            1 => Ok(Some(String::from("1 record deleted."))),
+            n => Ok(Some(format!("{} records deleted.", n))),
+        }
+    }
+    /// Works out the rows of an INSERT
+    /// Columns left out of the column list or given as DEFAULT take their default value
+    fn values(&self, db: &DataBase, list: Pairs<Rule>) -> Result<Vec<Vec<SQLValue>>, String> {
+        let fields = self.fields();
+        let catalog = Catalog::new(db);
+        let scope = Scope::new(&catalog, &[], &[], None);
+        let mut columns: Vec<usize> = (0..fields.len()).collect();
+        let mut rows = Vec::new();
+        let mut next = self.auto_increment.clone();
+        for pair in list {
+            match pair.as_rule() {
+                Rule::columnList => columns = self.column_list(pair)?,
+                Rule::valuesRow => {
+                    let vals: Vec<Pair<Rule>> = pair.into_inner().filter(|pair| matches!(pair.as_rule(), Rule::expr | Rule::default)).collect();
+                    if vals.len() != columns.len() {
+                        return Err(format!("!Expected {} values but got {}", columns.len(), vals.len()));
+                    }
+                    let mut row = Vec::new();
+                    for (idx, val) in columns.iter().zip(vals) {
+                        // Left out so fit fills in the default
+                        if val.as_rule() == Rule::default {
+                            continue;
+                        }
+                        let expr = SQLExpr::parse(val)?;
+                        self.check_assign(*idx, expr.check(&scope)?)?;
+                        row.push((*idx, expr.eval(&scope)?));
+                    }
+                    rows.push(self.fit(row.into_iter(), &mut next, db)?);
                 },
-                SQLHeaderDef::Char(name, size) => {
-                    out.push_str(format!("{} char({}) | ", name, size).as_str());
+                Rule::select => {
+                    let query = Select::parse(pair.into_inner())?;
+                    let result = query.fields(&catalog, None)?;
+                    if result.len() != columns.len() {
+                        return Err(format!("!Expected {} values but got {}", columns.len(), result.len()));
+                    }
+                    for (idx, field) in columns.iter().zip(&result) {
+                        self.check_assign(*idx, field.sql_type)?;
+                    }
+                    for row in query.execute(&catalog, None)?.rows {
+                        rows.push(self.fit(columns.iter().copied().zip(row), &mut next, db)?);
+                    }
                 },
-                SQLHeaderDef::Float(name) => {
-                    out.push_str(format!("{} float | ", name).as_str());
+                _ => (),
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Whether a default calls nextval or setval, so an insert leaving its column out changes a sequence
+    fn defaults_change_sequences(&self) -> bool {
+        self.default_exprs.iter().flatten().any(|source| match SQLParser::parse(Rule::expr, source) {
+            Ok(pairs) => changes_sequences(pairs.flatten()),
+            Err(_) => false,
+        })
+    }
+
+    /// Value a column takes when an INSERT leaves it out
+    /// Tables saved before default expressions were kept only have the value worked out when the column was made
+    /// Expressions are worked out against db, the database the insert runs in
+    fn default(&self, idx: usize, db: &DataBase) -> Result<SQLValue, String> {
+        match self.default_exprs.get(idx) {
+            Some(Some(source)) => match SQLParser::parse(Rule::expr, source) {
+                Ok(mut pairs) => self.header[idx].fit(constant(pairs.next().unwrap(), db)?.1),
+                Err(e) => Err(format!("!Invalid DEFAULT {}\n{}", source, e)),
+            },
+            _ => Ok(self.defaults.get(idx).cloned().unwrap_or(SQLValue::Null)),
+        }
+    }
+
+    fn check_assign(&self, idx: usize, sql_type: SQLType) -> Result<(), String> {
+        let field = self.header[idx].field();
+        if field.sql_type.accepts(sql_type) {
+            Ok(())
+        } else {
+            Err(format!("!Cannot assign {} to {} column {}", sql_type, self.header[idx].type_name(), field.name))
+        }
+    }
+
+    /// Builds a full row from (column, value) pairs, columns left out take their default
+    /// AUTO_INCREMENT columns left out or given NULL take their value from next, which moves past any value given
+    fn fit(&self, vals: impl Iterator<Item = (usize, SQLValue)>, next: &mut [Option<i64>], db: &DataBase) -> Result<Vec<SQLValue>, String> {
+        let mut given = vec![None; self.header.len()];
+        for (idx, val) in vals {
+            given[idx] = Some(self.header[idx].fit(val)?);
+        }
+        let mut row = Vec::new();
+        for (idx, val) in given.into_iter().enumerate() {
+            row.push(match (val, next.get_mut(idx)) {
+                (None | Some(SQLValue::Null), Some(Some(next))) => {
+                    let val = *next;
+                    *next = match val.checked_add(1) {
+                        Some(next) => next,
+                        None => return Err(format!("!AUTO_INCREMENT column {} has run out of values", self.header[idx].field().name)),
+                    };
+                    self.header[idx].fit(SQLValue::Int(val))?
                 },
-                SQLHeaderDef::Varchar(name, size) => {
-                    out.push_str(format!("{} varchar({}) | ", name, size).as_str());
+                (Some(SQLValue::Int(val)), Some(Some(next))) => {
+                    *next = (*next).max(val.saturating_add(1));
+                    SQLValue::Int(val)
                 },
+                (Some(val), _) => val,
+                (None, _) => self.default(idx, db)?,
+            });
+        }
+        Ok(row)
+    }
+
+    /// Appends rows worked out by values, either all of them or none
+    fn insert(&mut self, rows: Vec<Vec<SQLValue>>) -> Result<Option<String>, String> {
+        let keys = self.index_keys(&rows.iter().map(Vec::as_slice).collect::<Vec<_>>())?;
+        let len = self.len;
+        for row in &rows {
+            for (column, val) in self.data.iter_mut().zip(row) {
+                if let Err(e) = column.push(val.clone()) {
+                    for column in self.data.iter_mut() {
+                        column.truncate(len);
+                    }
+                    return Err(e);
+                }
             }
         }
-        for i in 0..self.len {
-            out.pop();
-            out.pop();
-            out.push_str("\n");
-            for j in 0..self.data.len() {
-                match self.data.get(j).unwrap() {
-                    SQLColumn::Int(val) => out.push_str(format!("{} | ", val.get(i).unwrap()).as_str()),
-                    SQLColumn::Char(val) => out.push_str(format!("{} | ", val.get(i).unwrap()).as_str()),
-                    SQLColumn::Float(val) => out.push_str(format!("{} | ", val.get(i).unwrap()).as_str()),
+        self.versions.resize(len, Version::committed());
+        self.versions.resize(len + rows.len(), Version::pending());
+        self.len += rows.len();
+        for (index, keys) in self.indexes.iter_mut().zip(keys) {
+            keys.into_iter().for_each(|key| index.push(key));
+        }
+        // Counters move past every value stored, generated or not
+        for (idx, next) in self.auto_increment.iter_mut().enumerate() {
+            if let Some(next) = next {
+                for row in &rows {
+                    if let SQLValue::Int(val) = row[idx] {
+                        *next = (*next).max(val.saturating_add(1));
+                    }
                 }
             }
         }
-        out.pop();
-        out.pop();
-        Ok(Some(out))
+        match rows.len() {
+            1 => Ok(Some(String::from("1 new record inserted"))),
+            n => Ok(Some(format!("{} new records inserted", n))),
+        }
     }
-    
+
+    /// Name and type of every column
+    fn fields(&self) -> Vec<Field> {
+        self.header.iter().map(|column| column.field()).collect()
+    }
+
+    /// Reads a single row out of the columns
+    fn row(&self, i: usize) -> Vec<SQLValue> {
+        self.data.iter().map(|column| column.get(i)).collect()
+    }
+
+    /// Reads every row of the table a snapshot sees
+    fn scan(&self, snapshot: Snapshot) -> ResultSet {
+        ResultSet {
+            fields: self.fields(),
+            rows: self.visible(snapshot).map(|i| self.row(i)).collect(),
+        }
+    }
+
+    /// Reads the rows a snapshot sees that can pass filter, through the first index that narrows them down
+    /// Columns in filter may be qualified by alias, every row is read when no index applies
+    fn scan_where(&self, snapshot: Snapshot, filter: &SQLExpr, alias: &str) -> Result<ResultSet, String> {
+        let fields = self.fields();
+        for index in &self.indexes {
+            if let Some(positions) = index.find(&fields, filter, alias)? {
+                let rows = positions.into_iter().filter(|i| self.version(*i).visible(snapshot)).map(|i| self.row(i)).collect();
+                return Ok(ResultSet { fields, rows });
+            }
+        }
+        Ok(self.scan(snapshot))
+    }
+
+    /// Key of each row in every index
+    fn index_keys(&self, rows: &[&[SQLValue]]) -> Result<Vec<Vec<Vec<SQLValue>>>, String> {
+        let fields = self.fields();
+        self.indexes.iter().map(|index| index.keys(&fields, rows)).collect()
+    }
+
+    fn version(&self, i: usize) -> Version {
+        self.versions.get(i).copied().unwrap_or(Version::committed())
+    }
+
+    /// Positions of the row versions a snapshot sees
+    fn visible(&self, snapshot: Snapshot) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(move |i| self.version(*i).visible(snapshot))
+    }
+
+    /// Positions of the latest version of every row, which keys hold to
+    fn current(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(|i| self.version(*i).is_current())
+    }
+
+    /// Fails if a row a statement is about to change was changed by a transaction that committed after its snapshot
+    fn check_current(&self, i: usize) -> Result<(), String> {
+        match self.version(i).is_current() {
+            true => Ok(()),
+            false => Err(mvcc::serialization_failure(mvcc::CONCURRENT_UPDATE)),
+        }
+    }
+
+    /// Drops the row versions marked in removed for good
+    fn drop_versions(&mut self, removed: &[bool]) {
+        for column in self.data.iter_mut() {
+            column.remove(removed);
+        }
+        let mut keep = removed.iter().map(|removed| !removed);
+        self.versions.retain(|_| keep.next().unwrap());
+        self.len -= removed.iter().filter(|removed| **removed).count();
+        for index in self.indexes.iter_mut() {
+            index.remove(removed);
+        }
+    }
+
+    /// Stamps the versions this instance wrote with the commit saving them,
+    /// then drops versions no snapshot from horizon on can see
+    fn commit(&mut self, commit: u64, horizon: u64) {
+        self.versions.resize(self.len, Version::committed());
+        for version in self.versions.iter_mut() {
+            version.stamp(commit);
+        }
+        let removed: Vec<bool> = self.versions.iter().map(|version| version.is_garbage(horizon)).collect();
+        if removed.contains(&true) {
+            self.drop_versions(&removed);
+        }
+    }
+
     /// Alters the table
-    fn alter(&mut self, mut list: Pairs<Rule>) -> Result<Option<String>, String> {
+    fn alter(&mut self, mut list: Pairs<Rule>, db: &DataBase) -> Result<Option<String>, String> {
         match list.next().unwrap().as_rule() {
             Rule::add => {
                 for element in list.next().unwrap().into_inner() {
-                    let mut it = element.into_inner();
-                    let name = it.next().unwrap().as_str();
-                    let column = it.next().unwrap();
-                    match column.as_rule() {
-                        Rule::char => {
-                            self.header.push(
-                                SQLHeaderDef::Char(
-                                    String::from(name), 
-                                    column.into_inner().nth(1).unwrap().as_str().parse::<u32>().unwrap())
-                            )
-                        },
-                        Rule::varchar => {
-                            self.header.push(
-                                SQLHeaderDef::Varchar(
-                                    String::from(name), 
-                                    column.into_inner().nth(1).unwrap().as_str().parse::<u32>().unwrap())
-                            )
-                        }
-                        Rule::float => {
-                            self.header.push(
-                                SQLHeaderDef::Float(
-                                    String::from(name)
-                                )
-                            )
-                        }
-                        Rule::int => {
-                            self.header.push(
-                                SQLHeaderDef::Int(
-                                    String::from(name)
-                                )
-                            )
-                        }
-                        _ => (),
-                    };
+                    if element.as_rule() == Rule::columnDef {
+                        self.add_column_def(element, db)?;
+                    }
                 }
                 Ok(Some(String::from("Table {} modified.")))
             },
@@ -0,0 +1,700 @@
+use serde::{Serialize, Deserialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::expr::{BinaryOp, SQLType, SQLValue};
+
+/// Dates are days since 1970-01-01, times are microseconds since midnight
+/// and timestamps are microseconds since 1970-01-01 00:00:00 UTC
+pub const MICROS_PER_DAY: i64 = 86_400_000_000;
+const MICROS_PER_HOUR: i64 = 3_600_000_000;
+const MICROS_PER_MINUTE: i64 = 60_000_000;
+const MICROS_PER_SECOND: i64 = 1_000_000;
+/// Months count as 30 days when intervals are compared or spread out
+const DAYS_PER_MONTH: i64 = 30;
+
+/// A span of time kept as months, days and microseconds since none of them convert exactly into the others
+/// Adding a month to the 31st of January lands on the last day of February
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Interval {
+    pub months: i32,
+    pub days: i32,
+    pub micros: i64,
+}
+
+impl Interval {
+    /// Length in microseconds with a month as 30 days, used for ordering
+    fn key(&self) -> i128 {
+        (self.months as i128 * DAYS_PER_MONTH as i128 + self.days as i128) * MICROS_PER_DAY as i128 + self.micros as i128
+    }
+
+    fn checked_add(self, other: Interval) -> Option<Interval> {
+        Some(Interval {
+            months: self.months.checked_add(other.months)?,
+            days: self.days.checked_add(other.days)?,
+            micros: self.micros.checked_add(other.micros)?,
+        })
+    }
+
+    pub fn checked_neg(self) -> Option<Interval> {
+        Some(Interval { months: self.months.checked_neg()?, days: self.days.checked_neg()?, micros: self.micros.checked_neg()? })
+    }
+
+    /// Multiplies each part, fractions of a month spill into days and fractions of a day into microseconds
+    fn scale(self, factor: f64) -> Option<Interval> {
+        let months = self.months as f64 * factor;
+        let days = self.days as f64 * factor + months.fract() * DAYS_PER_MONTH as f64;
+        let micros = (self.micros as f64 * factor + days.fract() * MICROS_PER_DAY as f64).round();
+        let fits = |val: f64, min: f64, max: f64| val.is_finite() && val >= min && val <= max;
+        if !fits(months.trunc(), i32::MIN as f64, i32::MAX as f64) || !fits(days.trunc(), i32::MIN as f64, i32::MAX as f64)
+            || !fits(micros, i64::MIN as f64, i64::MAX as f64) {
+            return None;
+        }
+        Some(Interval { months: months.trunc() as i32, days: days.trunc() as i32, micros: micros as i64 })
+    }
+}
+
+/// e.g. 1 year 2 months 3 days 04:05:06
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        let plural = |n: i64, unit: &str| format!("{} {}{}", n, unit, if n.abs() == 1 { "" } else { "s" });
+        let years = self.months / 12;
+        let months = self.months % 12;
+        if years != 0 {
+            parts.push(plural(years as i64, "year"));
+        }
+        if months != 0 {
+            parts.push(plural(months as i64, "month"));
+        }
+        if self.days != 0 {
+            parts.push(plural(self.days as i64, "day"));
+        }
+        if self.micros != 0 || parts.is_empty() {
+            let sign = if self.micros < 0 { "-" } else { "" };
+            parts.push(format!("{}{}", sign, format_time(self.micros.unsigned_abs() as i128)));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// (year, month, day) of a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Reads a run of digits, at least min and at most max of them
+fn digits(text: &mut &str, min: usize, max: usize) -> Option<i64> {
+    let len = text.bytes().take(max).take_while(|byte| byte.is_ascii_digit()).count();
+    if len < min {
+        return None;
+    }
+    let val = text[..len].parse().ok()?;
+    *text = &text[len..];
+    Some(val)
+}
+
+/// Consumes prefix if text starts with it
+fn skip(text: &mut &str, prefix: char) -> bool {
+    match text.strip_prefix(prefix) {
+        Some(rest) => {
+            *text = rest;
+            true
+        },
+        None => false,
+    }
+}
+
+/// YYYY-MM-DD at the start of text
+fn read_date(text: &mut &str) -> Option<i64> {
+    let year = digits(text, 4, 6)?;
+    if !skip(text, '-') {
+        return None;
+    }
+    let month = digits(text, 1, 2)? as u32;
+    if !skip(text, '-') {
+        return None;
+    }
+    let day = digits(text, 1, 2)? as u32;
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day))
+}
+
+/// HH:MM[:SS[.ffffff]] at the start of text, as microseconds
+fn read_time(text: &mut &str) -> Option<i64> {
+    let hour = digits(text, 1, 2)?;
+    if !skip(text, ':') {
+        return None;
+    }
+    let minute = digits(text, 2, 2)?;
+    let mut second = 0;
+    let mut fraction = 0;
+    if skip(text, ':') {
+        second = digits(text, 2, 2)?;
+        if skip(text, '.') {
+            let len = text.bytes().take_while(|byte| byte.is_ascii_digit()).count();
+            if len == 0 {
+                return None;
+            }
+            // Digits past microseconds are dropped
+            let kept = &text[..len.min(6)];
+            fraction = kept.parse::<i64>().ok()? * 10_i64.pow(6 - kept.len() as u32);
+            *text = &text[len..];
+        }
+    }
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    Some(hour * MICROS_PER_HOUR + minute * MICROS_PER_MINUTE + second * MICROS_PER_SECOND + fraction)
+}
+
+/// Z or +HH[:MM] or -HH[:MM] at the start of text, as microseconds east of UTC
+fn read_zone(text: &mut &str) -> Option<i64> {
+    *text = text.trim_start();
+    if skip(text, 'Z') || skip(text, 'z') {
+        return Some(0);
+    }
+    let sign = if skip(text, '+') {
+        1
+    } else if skip(text, '-') {
+        -1
+    } else {
+        return None;
+    };
+    let hours = digits(text, 2, 2)?;
+    skip(text, ':');
+    let minutes = digits(text, 0, 2).unwrap_or(0);
+    if hours > 15 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * MICROS_PER_HOUR + minutes * MICROS_PER_MINUTE))
+}
+
+pub fn parse_date(text: &str) -> Result<i32, String> {
+    let mut rest = text.trim();
+    match read_date(&mut rest) {
+        Some(days) if rest.is_empty() && i32::try_from(days).is_ok() => Ok(days as i32),
+        _ => Err(format!("!Invalid date '{}'", text)),
+    }
+}
+
+pub fn parse_time(text: &str) -> Result<i64, String> {
+    let mut rest = text.trim();
+    match read_time(&mut rest) {
+        Some(micros) if rest.is_empty() => Ok(micros),
+        _ => Err(format!("!Invalid time '{}'", text)),
+    }
+}
+
+/// YYYY-MM-DD[( |T)HH:MM[:SS[.ffffff]]][zone]
+/// With zone the result is in UTC, without it any zone given is ignored
+pub fn parse_timestamp(text: &str, zone: bool) -> Result<i64, String> {
+    let invalid = || format!("!Invalid timestamp '{}'", text);
+    let mut rest = text.trim();
+    let days = read_date(&mut rest).ok_or_else(invalid)?;
+    let mut micros = 0;
+    if skip(&mut rest, 'T') || skip(&mut rest, ' ') {
+        rest = rest.trim_start();
+        micros = read_time(&mut rest).ok_or_else(invalid)?;
+    }
+    let offset = match rest.trim() {
+        "" => 0,
+        _ => read_zone(&mut rest).ok_or_else(invalid)?,
+    };
+    if !rest.trim().is_empty() {
+        return Err(invalid());
+    }
+    let offset = if zone { offset } else { 0 };
+    days.checked_mul(MICROS_PER_DAY)
+        .and_then(|val| val.checked_add(micros))
+        .and_then(|val| val.checked_sub(offset))
+        .ok_or_else(invalid)
+}
+
+/// Either ISO 8601 like P1Y2M3DT4H5M6S or a list like '1 year 2 months 3 days 04:05:06 ago'
+pub fn parse_interval(text: &str) -> Result<Interval, String> {
+    let invalid = || format!("!Invalid interval '{}'", text);
+    let trimmed = text.trim();
+    let mut interval = Interval { months: 0, days: 0, micros: 0 };
+    let mut add = |amount: f64, unit: &str| -> Option<()> {
+        let part = match unit {
+            "y" | "year" | "years" => Interval { months: 12, days: 0, micros: 0 },
+            "mon" | "mons" | "month" | "months" => Interval { months: 1, days: 0, micros: 0 },
+            "w" | "week" | "weeks" => Interval { months: 0, days: 7, micros: 0 },
+            "d" | "day" | "days" => Interval { months: 0, days: 1, micros: 0 },
+            "h" | "hour" | "hours" => Interval { months: 0, days: 0, micros: MICROS_PER_HOUR },
+            "m" | "min" | "mins" | "minute" | "minutes" => Interval { months: 0, days: 0, micros: MICROS_PER_MINUTE },
+            "s" | "sec" | "secs" | "second" | "seconds" => Interval { months: 0, days: 0, micros: MICROS_PER_SECOND },
+            "ms" | "millisecond" | "milliseconds" => Interval { months: 0, days: 0, micros: 1000 },
+            "us" | "microsecond" | "microseconds" => Interval { months: 0, days: 0, micros: 1 },
+            _ => return None,
+        };
+        interval = interval.checked_add(part.scale(amount)?)?;
+        Some(())
+    };
+    if let Some(iso) = trimmed.strip_prefix('P').or_else(|| trimmed.strip_prefix('p')) {
+        let mut in_time = false;
+        let mut number = String::new();
+        for c in iso.chars() {
+            match c.to_ascii_uppercase() {
+                'T' if number.is_empty() => in_time = true,
+                '0'..='9' | '.' | '-' => number.push(c),
+                unit => {
+                    let amount: f64 = number.parse().map_err(|_| invalid())?;
+                    let unit = match (unit, in_time) {
+                        ('Y', false) => "y",
+                        ('M', false) => "mon",
+                        ('W', false) => "w",
+                        ('D', false) => "d",
+                        ('H', true) => "h",
+                        ('M', true) => "m",
+                        ('S', true) => "s",
+                        _ => return Err(invalid()),
+                    };
+                    add(amount, unit).ok_or_else(invalid)?;
+                    number.clear();
+                },
+            }
+        }
+        if !number.is_empty() || iso.is_empty() {
+            return Err(invalid());
+        }
+        return Ok(interval);
+    }
+    let words: Vec<String> = trimmed.split_whitespace().map(|word| word.to_ascii_lowercase()).collect();
+    let (words, ago) = match words.split_last() {
+        Some((last, rest)) if last == "ago" => (rest, true),
+        _ => (&words[..], false),
+    };
+    if words.is_empty() {
+        return Err(invalid());
+    }
+    let mut it = words.iter();
+    while let Some(word) = it.next() {
+        if word.contains(':') {
+            let (sign, time) = match word.strip_prefix('-') {
+                Some(time) => (-1, time),
+                None => (1, word.trim_start_matches('+')),
+            };
+            // HH:MM or HH:MM:SS where hours can go past a day
+            let mut parts = time.splitn(2, ':');
+            let hours: i64 = parts.next().unwrap().parse().map_err(|_| invalid())?;
+            let mut rest = format!("00:{}", parts.next().unwrap_or(""));
+            if rest.matches(':').count() == 1 {
+                rest.push_str(":00");
+            }
+            let mut rest_str = rest.as_str();
+            let rest = read_time(&mut rest_str).filter(|_| rest_str.is_empty()).ok_or_else(invalid)?;
+            let micros = hours.checked_mul(MICROS_PER_HOUR).and_then(|micros| micros.checked_add(rest)).ok_or_else(invalid)?;
+            add((sign * micros) as f64, "us").ok_or_else(invalid)?;
+            continue;
+        }
+        // A number followed by its unit, either as one word like 3d or two like 3 days
+        let split = word.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(word.len());
+        let (number, unit) = word.split_at(split);
+        let unit = match unit {
+            "" => it.next().ok_or_else(invalid)?.as_str(),
+            unit => unit,
+        };
+        let amount: f64 = number.parse().map_err(|_| invalid())?;
+        add(amount, unit).ok_or_else(invalid)?;
+    }
+    if ago {
+        interval = interval.checked_neg().ok_or_else(invalid)?;
+    }
+    Ok(interval)
+}
+
+pub fn format_date(days: i32) -> String {
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// HH:MM:SS with a fraction only when there is one, hours can go past 24 for intervals
+fn format_time(micros: i128) -> String {
+    let hours = micros / MICROS_PER_HOUR as i128;
+    let minutes = micros / MICROS_PER_MINUTE as i128 % 60;
+    let seconds = micros / MICROS_PER_SECOND as i128 % 60;
+    let fraction = micros % MICROS_PER_SECOND as i128;
+    if fraction == 0 {
+        format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        let fraction = format!("{:06}", fraction);
+        format!("{:02}:{:02}:{:02}.{}", hours, minutes, seconds, fraction.trim_end_matches('0'))
+    }
+}
+
+pub fn format_time_of_day(micros: i64) -> String {
+    format_time(micros as i128)
+}
+
+pub fn format_timestamp(micros: i64) -> String {
+    let days = micros.div_euclid(MICROS_PER_DAY);
+    format!("{} {}", format_date(days as i32), format_time(micros.rem_euclid(MICROS_PER_DAY) as i128))
+}
+
+/// Microseconds since 1970-01-01 00:00:00 UTC right now
+pub fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_micros() as i64,
+        Err(before) => -(before.duration().as_micros() as i64),
+    }
+}
+
+fn date_to_timestamp(days: i32) -> i64 {
+    days as i64 * MICROS_PER_DAY
+}
+
+pub fn timestamp_to_date(micros: i64) -> i32 {
+    micros.div_euclid(MICROS_PER_DAY) as i32
+}
+
+pub fn timestamp_to_time(micros: i64) -> i64 {
+    micros.rem_euclid(MICROS_PER_DAY)
+}
+
+/// Moves a timestamp by an interval, months first so the day of the month is kept where it exists
+fn add_interval(micros: i64, interval: Interval) -> Option<i64> {
+    let days = micros.div_euclid(MICROS_PER_DAY);
+    let time = micros.rem_euclid(MICROS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let months = year * 12 + month as i64 - 1 + interval.months as i64;
+    let (year, month) = (months.div_euclid(12), months.rem_euclid(12) as u32 + 1);
+    let day = day.min(days_in_month(year, month));
+    let days = days_from_civil(year, month, day).checked_add(interval.days as i64)?;
+    days.checked_mul(MICROS_PER_DAY)?.checked_add(time)?.checked_add(interval.micros)
+}
+
+/// Difference of two timestamps as whole days and the microseconds left over
+fn between(a: i64, b: i64) -> Option<Interval> {
+    let diff = a.checked_sub(b)?;
+    Some(Interval { months: 0, days: i32::try_from(diff / MICROS_PER_DAY).ok()?, micros: diff % MICROS_PER_DAY })
+}
+
+pub fn is_datetime(sql_type: SQLType) -> bool {
+    matches!(sql_type, SQLType::Date | SQLType::Time | SQLType::Timestamp | SQLType::TimestampTz | SQLType::Interval)
+}
+
+/// Result type of arithmetic involving a date, time or interval, None when the operator doesn't apply
+pub fn arithmetic_type(lhs: SQLType, op: BinaryOp, rhs: SQLType) -> Option<SQLType> {
+    use SQLType::*;
+    match (lhs, op, rhs) {
+        (a, _, Null) if is_datetime(a) => Some(a),
+        (Null, _, b) if is_datetime(b) => Some(b),
+        (Timestamp | TimestampTz, BinaryOp::Add | BinaryOp::Sub, Interval) => Some(lhs),
+        (Interval, BinaryOp::Add, Timestamp | TimestampTz) => Some(rhs),
+        (Date, BinaryOp::Add | BinaryOp::Sub, Interval) | (Interval, BinaryOp::Add, Date) => Some(Timestamp),
+        (Date, BinaryOp::Add, Time) | (Time, BinaryOp::Add, Date) => Some(Timestamp),
+        (Time, BinaryOp::Add | BinaryOp::Sub, Interval) | (Interval, BinaryOp::Add, Time) => Some(Time),
+        (Date, BinaryOp::Add | BinaryOp::Sub, b) if b.is_integer() => Some(Date),
+        (a, BinaryOp::Add, Date) if a.is_integer() => Some(Date),
+        (Date, BinaryOp::Sub, Date) => Some(Int),
+        (Date | Timestamp | TimestampTz, BinaryOp::Sub, Date | Timestamp | TimestampTz) => Some(Interval),
+        (Time, BinaryOp::Sub, Time) => Some(Interval),
+        (Interval, BinaryOp::Add | BinaryOp::Sub, Interval) => Some(Interval),
+        (Interval, BinaryOp::Mul | BinaryOp::Div, b) if b.is_numeric() => Some(Interval),
+        (a, BinaryOp::Mul, Interval) if a.is_numeric() => Some(Interval),
+        _ => None,
+    }
+}
+
+/// Arithmetic involving a date, time or interval, None when neither side is one
+pub fn arithmetic(lhs: &SQLValue, op: BinaryOp, rhs: &SQLValue) -> Option<Result<SQLValue, String>> {
+    use SQLValue::*;
+    let out = match (lhs, op, rhs) {
+        (Timestamp(ts), BinaryOp::Add, Interval(iv)) | (Interval(iv), BinaryOp::Add, Timestamp(ts)) => add_interval(*ts, *iv).map(Timestamp),
+        (TimestampTz(ts), BinaryOp::Add, Interval(iv)) | (Interval(iv), BinaryOp::Add, TimestampTz(ts)) => add_interval(*ts, *iv).map(TimestampTz),
+        (Timestamp(ts), BinaryOp::Sub, Interval(iv)) => iv.checked_neg().and_then(|iv| add_interval(*ts, iv)).map(Timestamp),
+        (TimestampTz(ts), BinaryOp::Sub, Interval(iv)) => iv.checked_neg().and_then(|iv| add_interval(*ts, iv)).map(TimestampTz),
+        (Date(d), BinaryOp::Add, Interval(iv)) | (Interval(iv), BinaryOp::Add, Date(d)) => add_interval(date_to_timestamp(*d), *iv).map(Timestamp),
+        (Date(d), BinaryOp::Sub, Interval(iv)) => iv.checked_neg().and_then(|iv| add_interval(date_to_timestamp(*d), iv)).map(Timestamp),
+        (Date(d), BinaryOp::Add, Time(t)) | (Time(t), BinaryOp::Add, Date(d)) => date_to_timestamp(*d).checked_add(*t).map(Timestamp),
+        (Time(t), BinaryOp::Add, Interval(iv)) | (Interval(iv), BinaryOp::Add, Time(t)) => {
+            Some(Time((*t as i128 + iv.micros as i128).rem_euclid(MICROS_PER_DAY as i128) as i64))
+        },
+        (Time(t), BinaryOp::Sub, Interval(iv)) => Some(Time((*t as i128 - iv.micros as i128).rem_euclid(MICROS_PER_DAY as i128) as i64)),
+        (Date(d), BinaryOp::Add, Int(n)) | (Int(n), BinaryOp::Add, Date(d)) => {
+            i32::try_from(n.saturating_add(*d as i64)).ok().map(Date)
+        },
+        (Date(d), BinaryOp::Sub, Int(n)) => i32::try_from((*d as i64).saturating_sub(*n)).ok().map(Date),
+        (Date(a), BinaryOp::Sub, Date(b)) => Some(Int(*a as i64 - *b as i64)),
+        (Date(_) | Timestamp(_) | TimestampTz(_), BinaryOp::Sub, Date(_) | Timestamp(_) | TimestampTz(_)) => {
+            between(instant(lhs)?, instant(rhs)?).map(Interval)
+        },
+        (Time(a), BinaryOp::Sub, Time(b)) => Some(Interval(self::Interval { months: 0, days: 0, micros: a - b })),
+        (Interval(a), BinaryOp::Add, Interval(b)) => a.checked_add(*b).map(Interval),
+        (Interval(a), BinaryOp::Sub, Interval(b)) => b.checked_neg().and_then(|b| a.checked_add(b)).map(Interval),
+        (Interval(iv), BinaryOp::Mul, n) | (n, BinaryOp::Mul, Interval(iv)) if n.as_float().is_some() => {
+            iv.scale(n.as_float().unwrap()).map(Interval)
+        },
+        (Interval(_), BinaryOp::Div, n) if n.as_float() == Some(0.0) => return Some(Err(String::from("!Division by zero"))),
+        (Interval(iv), BinaryOp::Div, n) if n.as_float().is_some() => iv.scale(1.0 / n.as_float().unwrap()).map(Interval),
+        (Date(_) | Time(_) | Timestamp(_) | TimestampTz(_) | Interval(_), _, _) | (_, _, Date(_) | Time(_) | Timestamp(_) | TimestampTz(_) | Interval(_)) => {
+            return Some(Err(format!("!Cannot apply arithmetic to {} and {}", lhs.sql_type(), rhs.sql_type())));
+        },
+        _ => return None,
+    };
+    Some(out.ok_or_else(|| String::from("!Date or time out of range")))
+}
+
+/// Microseconds since the epoch of a date or timestamp
+fn instant(val: &SQLValue) -> Option<i64> {
+    match val {
+        SQLValue::Date(days) => Some(date_to_timestamp(*days)),
+        SQLValue::Timestamp(micros) | SQLValue::TimestampTz(micros) => Some(*micros),
+        _ => None,
+    }
+}
+
+/// Orders two values when at least one is a date, time or interval, None when neither is
+/// Text on one side is read as the type of the other side
+pub fn compare(a: &SQLValue, b: &SQLValue) -> Option<Result<Ordering, String>> {
+    let parsed;
+    let (a, b) = match (a, b) {
+        (SQLValue::Char(_), SQLValue::Char(_)) => return None,
+        (SQLValue::Char(text), other) => match read_as(text, other)? {
+            Ok(val) => {
+                parsed = val;
+                (&parsed, other)
+            },
+            Err(e) => return Some(Err(e)),
+        },
+        (other, SQLValue::Char(text)) => match read_as(text, other)? {
+            Ok(val) => {
+                parsed = val;
+                (other, &parsed)
+            },
+            Err(e) => return Some(Err(e)),
+        },
+        (a, b) => (a, b),
+    };
+    let key = |val: &SQLValue| -> Option<(u8, i128)> {
+        match val {
+            SQLValue::Date(_) | SQLValue::Timestamp(_) | SQLValue::TimestampTz(_) => Some((0, instant(val)? as i128)),
+            SQLValue::Time(micros) => Some((1, *micros as i128)),
+            SQLValue::Interval(interval) => Some((2, interval.key())),
+            _ => None,
+        }
+    };
+    match (key(a), key(b)) {
+        (Some((x, a)), Some((y, b))) if x == y => Some(Ok(a.cmp(&b))),
+        (None, None) => None,
+        _ => Some(Err(format!("!Cannot compare {} with {}", a.sql_type(), b.sql_type()))),
+    }
+}
+
+/// Parses text as the type of other, None when other isn't a date, time or interval
+fn read_as(text: &str, other: &SQLValue) -> Option<Result<SQLValue, String>> {
+    Some(match other {
+        SQLValue::Date(_) => parse_date(text).map(SQLValue::Date),
+        SQLValue::Time(_) => parse_time(text).map(SQLValue::Time),
+        SQLValue::Timestamp(_) => parse_timestamp(text, false).map(SQLValue::Timestamp),
+        SQLValue::TimestampTz(_) => parse_timestamp(text, true).map(SQLValue::TimestampTz),
+        SQLValue::Interval(_) => parse_interval(text).map(SQLValue::Interval),
+        _ => return None,
+    })
+}
+
+/// Whether EXTRACT knows the field
+pub fn is_field(field: &str) -> bool {
+    matches!(
+        field.to_ascii_lowercase().as_str(),
+        "year" | "quarter" | "month" | "week" | "day" | "dow" | "doy" | "hour" | "minute" | "second" | "epoch"
+    )
+}
+
+/// EXTRACT(field FROM val) for dates, times, timestamps and intervals
+pub fn part(field: &str, val: &SQLValue) -> Result<SQLValue, String> {
+    let field = field.to_ascii_lowercase();
+    let unknown = || format!("!Cannot extract {} from {}", field, val.sql_type());
+    if let SQLValue::Interval(interval) = val {
+        let out = match field.as_str() {
+            "year" => (interval.months / 12) as f64,
+            "month" => (interval.months % 12) as f64,
+            "day" => interval.days as f64,
+            "hour" => (interval.micros / MICROS_PER_HOUR) as f64,
+            "minute" => (interval.micros / MICROS_PER_MINUTE % 60) as f64,
+            "second" => (interval.micros % MICROS_PER_MINUTE) as f64 / MICROS_PER_SECOND as f64,
+            "epoch" => interval.key() as f64 / MICROS_PER_SECOND as f64,
+            _ => return Err(unknown()),
+        };
+        return Ok(SQLValue::Float(out));
+    }
+    let (days, time) = match val {
+        SQLValue::Date(days) => (Some(*days as i64), 0),
+        SQLValue::Timestamp(micros) | SQLValue::TimestampTz(micros) => (Some(micros.div_euclid(MICROS_PER_DAY)), micros.rem_euclid(MICROS_PER_DAY)),
+        SQLValue::Time(micros) => (None, *micros),
+        _ => return Err(unknown()),
+    };
+    let date = days.map(|days| (days, civil_from_days(days)));
+    let out = match (field.as_str(), date) {
+        ("year", Some((_, (year, _, _)))) => year as f64,
+        ("quarter", Some((_, (_, month, _)))) => ((month - 1) / 3 + 1) as f64,
+        ("month", Some((_, (_, month, _)))) => month as f64,
+        ("day", Some((_, (_, _, day)))) => day as f64,
+        // 1970-01-01 was a Thursday, Sunday is 0
+        ("dow", Some((days, _))) => (days + 4).rem_euclid(7) as f64,
+        ("doy", Some((days, (year, _, _)))) => (days - days_from_civil(year, 1, 1) + 1) as f64,
+        // ISO week, the week with the year's first Thursday is week 1
+        ("week", Some((days, _))) => {
+            let thursday = days - (days + 3).rem_euclid(7) + 3;
+            let (year, _, _) = civil_from_days(thursday);
+            ((thursday - days_from_civil(year, 1, 1)) / 7 + 1) as f64
+        },
+        ("hour", _) => (time / MICROS_PER_HOUR) as f64,
+        ("minute", _) => (time / MICROS_PER_MINUTE % 60) as f64,
+        ("second", _) => (time % MICROS_PER_MINUTE) as f64 / MICROS_PER_SECOND as f64,
+        ("epoch", Some((days, _))) => (days * MICROS_PER_DAY + time) as f64 / MICROS_PER_SECOND as f64,
+        ("epoch", None) => time as f64 / MICROS_PER_SECOND as f64,
+        _ => return Err(unknown()),
+    };
+    Ok(SQLValue::Float(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> SQLValue {
+        SQLValue::Date(parse_date(text).unwrap())
+    }
+
+    fn timestamp(text: &str) -> SQLValue {
+        SQLValue::Timestamp(parse_timestamp(text, false).unwrap())
+    }
+
+    fn interval(text: &str) -> SQLValue {
+        SQLValue::Interval(parse_interval(text).unwrap())
+    }
+
+    fn eval(lhs: &SQLValue, op: BinaryOp, rhs: &SQLValue) -> Result<SQLValue, String> {
+        arithmetic(lhs, op, rhs).unwrap()
+    }
+
+    #[test]
+    fn parses_dates_on_the_calendar() {
+        assert_eq!(parse_date("1970-01-01"), Ok(0));
+        assert_eq!(parse_date("1969-12-31"), Ok(-1));
+        assert_eq!(format_date(parse_date("2024-02-29").unwrap()), "2024-02-29");
+        assert_eq!(format_date(parse_date(" 2000-1-5 ").unwrap()), "2000-01-05");
+        for text in ["2023-02-29", "1900-02-29", "2024-13-01", "2024-04-31", "2024-00-10", "24-01-01", "2024-01-01x"] {
+            assert!(parse_date(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_times_and_timestamps() {
+        assert_eq!(format_time_of_day(parse_time("04:05:06.5").unwrap()), "04:05:06.5");
+        assert_eq!(format_time_of_day(parse_time("23:59").unwrap()), "23:59:00");
+        assert!(parse_time("24:00").is_err());
+        assert!(parse_time("12:60").is_err());
+        assert_eq!(format_timestamp(parse_timestamp("2024-03-10T08:30:00", false).unwrap()), "2024-03-10 08:30:00");
+        // The zone is only applied with time zone
+        assert_eq!(format_timestamp(parse_timestamp("2024-03-10 08:30:00+02:00", true).unwrap()), "2024-03-10 06:30:00");
+        assert_eq!(format_timestamp(parse_timestamp("2024-03-10 08:30:00+02:00", false).unwrap()), "2024-03-10 08:30:00");
+        assert_eq!(format_timestamp(parse_timestamp("1969-12-31 23:59:59.999999", false).unwrap()), "1969-12-31 23:59:59.999999");
+    }
+
+    #[test]
+    fn parses_intervals_in_words_and_iso() {
+        assert_eq!(parse_interval("1 year 2 months 3 days 04:05:06").unwrap().to_string(), "1 year 2 months 3 days 04:05:06");
+        assert_eq!(parse_interval("P1Y2M3DT4H5M6S"), parse_interval("1 year 2 months 3 days 04:05:06"));
+        assert_eq!(parse_interval("3d 2h ago").unwrap().to_string(), "-3 days -02:00:00");
+        assert_eq!(parse_interval("1.5 months").unwrap(), Interval { months: 1, days: 15, micros: 0 });
+        assert_eq!(parse_interval("36:00").unwrap().to_string(), "36:00:00");
+        assert_eq!(parse_interval("0 days").unwrap().to_string(), "00:00:00");
+        for text in ["", "ago", "3 fortnights", "P", "P1H", "5"] {
+            assert!(parse_interval(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn adding_months_keeps_the_day_where_it_exists() {
+        let out = eval(&date("2024-01-31"), BinaryOp::Add, &interval("1 month")).unwrap();
+        assert_eq!(out, timestamp("2024-02-29"));
+        let out = eval(&timestamp("2023-03-31 10:00:00"), BinaryOp::Sub, &interval("1 month")).unwrap();
+        assert_eq!(out, timestamp("2023-02-28 10:00:00"));
+        let out = eval(&date("2024-01-31"), BinaryOp::Add, &interval("1 month 1 day")).unwrap();
+        assert_eq!(out, timestamp("2024-03-01"));
+    }
+
+    #[test]
+    fn subtracts_dates_and_timestamps() {
+        assert_eq!(eval(&date("2024-03-01"), BinaryOp::Sub, &date("2024-02-01")), Ok(SQLValue::Int(29)));
+        assert_eq!(eval(&date("2024-03-01"), BinaryOp::Sub, &SQLValue::Int(1)), Ok(date("2024-02-29")));
+        let out = eval(&timestamp("2024-03-02 06:00:00"), BinaryOp::Sub, &timestamp("2024-03-01 00:00:00")).unwrap();
+        assert_eq!(out.to_string(), "1 day 06:00:00");
+        let out = eval(&SQLValue::Time(parse_time("01:00").unwrap()), BinaryOp::Sub, &interval("2 hours")).unwrap();
+        assert_eq!(out.to_string(), "23:00:00");
+    }
+
+    #[test]
+    fn scales_intervals() {
+        assert_eq!(eval(&interval("1 month"), BinaryOp::Mul, &SQLValue::Float(1.5)).unwrap().to_string(), "1 month 15 days");
+        assert_eq!(eval(&interval("1 day"), BinaryOp::Div, &SQLValue::Int(4)).unwrap().to_string(), "06:00:00");
+        assert_eq!(eval(&interval("1 day"), BinaryOp::Div, &SQLValue::Int(0)), Err(String::from("!Division by zero")));
+    }
+
+    #[test]
+    fn reports_results_out_of_range() {
+        let out = eval(&date("2024-01-01"), BinaryOp::Add, &SQLValue::Int(i64::MAX));
+        assert_eq!(out, Err(String::from("!Date or time out of range")));
+        let out = eval(&SQLValue::Timestamp(i64::MAX - 1), BinaryOp::Add, &interval("1 day"));
+        assert_eq!(out, Err(String::from("!Date or time out of range")));
+        assert!(eval(&date("2024-01-01"), BinaryOp::Mul, &SQLValue::Int(2)).is_err());
+    }
+
+    #[test]
+    fn compares_across_types_and_text() {
+        assert_eq!(compare(&date("2024-01-01"), &timestamp("2024-01-01 00:00:01")), Some(Ok(Ordering::Less)));
+        assert_eq!(compare(&SQLValue::Char(String::from("2024-01-02")), &date("2024-01-01")), Some(Ok(Ordering::Greater)));
+        assert_eq!(compare(&interval("1 month"), &interval("30 days")), Some(Ok(Ordering::Equal)));
+        assert!(matches!(compare(&date("2024-01-01"), &SQLValue::Char(String::from("soon"))), Some(Err(_))));
+        assert!(matches!(compare(&date("2024-01-01"), &interval("1 day")), Some(Err(_))));
+        assert_eq!(compare(&SQLValue::Int(1), &SQLValue::Int(2)), None);
+    }
+
+    #[test]
+    fn extracts_parts() {
+        let ts = timestamp("2021-01-03 10:20:30.5");
+        assert_eq!(part("year", &ts), Ok(SQLValue::Float(2021.0)));
+        // 2021-01-03 is a Sunday in the last ISO week of 2020
+        assert_eq!(part("dow", &ts), Ok(SQLValue::Float(0.0)));
+        assert_eq!(part("week", &ts), Ok(SQLValue::Float(53.0)));
+        assert_eq!(part("second", &ts), Ok(SQLValue::Float(30.5)));
+        assert_eq!(part("month", &interval("14 months")), Ok(SQLValue::Float(2.0)));
+        assert!(part("hour", &SQLValue::Int(1)).is_err());
+        assert!(part("year", &SQLValue::Time(0)).is_err());
+    }
+}
@@ -0,0 +1,435 @@
+use serde::{Serialize, Deserialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use super::expr::{BinaryOp, SQLType, SQLValue};
+
+/// Most digits a DECIMAL can hold, all of them fit in an i128
+pub const MAX_PRECISION: u32 = 38;
+/// Precision of DECIMAL without one, as in MySQL
+pub const DEFAULT_PRECISION: u32 = 10;
+/// Digits kept after the point when dividing, unless the operands have more
+const DIV_SCALE: u32 = 16;
+
+/// An exact number, digits / 10^scale
+/// Saved as its text so the dump holds the exact value
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Decimal {
+    digits: i128,
+    scale: u32,
+}
+
+fn pow10(exp: u32) -> Option<i128> {
+    10_i128.checked_pow(exp)
+}
+
+/// n / d rounded half away from zero
+fn div_round(n: i128, d: i128) -> i128 {
+    let q = n / d;
+    let r = n % d;
+    if r.unsigned_abs() * 2 >= d.unsigned_abs() {
+        q + n.signum() * d.signum()
+    } else {
+        q
+    }
+}
+
+impl Decimal {
+    pub fn from_int(val: i64) -> Self {
+        Decimal { digits: val as i128, scale: 0 }
+    }
+
+    /// The decimal a float prints as, so 0.1 becomes exactly 0.1
+    pub fn from_float(val: f64) -> Option<Self> {
+        if val.is_finite() {
+            Decimal::parse(&val.to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Reads text like -12.345 or 1.5e3, None if it isn't a number or has too many digits
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (mantissa, exp) = match text.find(['e', 'E']) {
+            Some(i) => (&text[..i], text[i + 1..].parse::<i32>().ok()?),
+            None => (text, 0),
+        };
+        let (negative, mantissa) = match mantissa.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, mantissa.strip_prefix('+').unwrap_or(mantissa)),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if whole.is_empty() && fraction.is_empty() || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let mut digits: i128 = 0;
+        for c in whole.chars().chain(fraction.chars()) {
+            digits = digits.checked_mul(10)?.checked_add(c.to_digit(10)? as i128)?;
+        }
+        if negative {
+            digits = -digits;
+        }
+        let scale = fraction.len() as i64 - exp as i64;
+        if scale >= 0 {
+            let scale = u32::try_from(scale).ok().filter(|scale| *scale <= MAX_PRECISION)?;
+            Some(Decimal { digits, scale })
+        } else {
+            Some(Decimal { digits: digits.checked_mul(pow10(u32::try_from(-scale).ok()?)?)?, scale: 0 })
+        }
+    }
+
+    pub fn to_float(self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    /// Nearest whole number, None if it is out of range for an i64
+    pub fn to_int(self) -> Option<i64> {
+        i64::try_from(self.rescale(0)?.digits).ok()
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// The same number with scale digits after the point, rounding half away from zero when digits are dropped
+    pub fn rescale(self, scale: u32) -> Option<Self> {
+        match scale.cmp(&self.scale) {
+            Ordering::Equal => Some(self),
+            Ordering::Greater => Some(Decimal { digits: self.digits.checked_mul(pow10(scale - self.scale)?)?, scale }),
+            Ordering::Less => {
+                let digits = match pow10(self.scale - scale) {
+                    Some(d) => div_round(self.digits, d),
+                    // Every digit is dropped
+                    None => 0,
+                };
+                Some(Decimal { digits, scale })
+            },
+        }
+    }
+
+    /// Whether the number rounded to scale has at most precision digits
+    pub fn fits(self, precision: u32, scale: u32) -> bool {
+        match (self.rescale(scale), pow10(precision)) {
+            (Some(val), Some(max)) => val.digits.abs() < max,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Both numbers with the larger of their scales
+    fn align(self, other: Decimal) -> Option<(i128, i128, u32)> {
+        let scale = self.scale.max(other.scale);
+        Some((self.rescale(scale)?.digits, other.rescale(scale)?.digits, scale))
+    }
+
+    pub fn checked_neg(self) -> Option<Self> {
+        Some(Decimal { digits: self.digits.checked_neg()?, scale: self.scale })
+    }
+
+    pub fn checked_abs(self) -> Option<Self> {
+        Some(Decimal { digits: self.digits.checked_abs()?, scale: self.scale })
+    }
+
+    fn checked_add(self, other: Decimal) -> Option<Self> {
+        let (a, b, scale) = self.align(other)?;
+        Some(Decimal { digits: a.checked_add(b)?, scale })
+    }
+
+    fn checked_sub(self, other: Decimal) -> Option<Self> {
+        let (a, b, scale) = self.align(other)?;
+        Some(Decimal { digits: a.checked_sub(b)?, scale })
+    }
+
+    fn checked_mul(self, other: Decimal) -> Option<Self> {
+        let out = Decimal { digits: self.digits.checked_mul(other.digits)?, scale: self.scale + other.scale };
+        out.rescale(out.scale.min(MAX_PRECISION))
+    }
+
+    /// Keeps at least DIV_SCALE digits after the point, fewer when the quotient is too large for that
+    fn checked_div(self, other: Decimal) -> Option<Self> {
+        let wanted = self.scale.max(other.scale).max(DIV_SCALE);
+        (0..=wanted).rev().find_map(|scale| {
+            // digits / 10^scale = (a / 10^sa) / (b / 10^sb)
+            let shift = scale as i64 + other.scale as i64 - self.scale as i64;
+            let (n, d) = if shift >= 0 {
+                (self.digits.checked_mul(pow10(shift as u32)?)?, other.digits)
+            } else {
+                (self.digits, other.digits.checked_mul(pow10((-shift) as u32)?)?)
+            };
+            Some(Decimal { digits: div_round(n, d), scale })
+        })
+    }
+
+    fn checked_rem(self, other: Decimal) -> Option<Self> {
+        let (a, b, scale) = self.align(other)?;
+        Some(Decimal { digits: a.checked_rem(b)?, scale })
+    }
+
+    /// Rounded to digits places after the point, negative digits round to the left of it
+    pub fn round(self, digits: i32) -> Option<Self> {
+        if digits >= 0 {
+            return self.rescale((digits as u32).min(self.scale));
+        }
+        let shift = digits.unsigned_abs();
+        let val = self.rescale(0)?;
+        Some(Decimal { digits: match pow10(shift) {
+            Some(d) => div_round(val.digits, d).checked_mul(d)?,
+            None => 0,
+        }, scale: 0 })
+    }
+
+    /// Largest whole number not above it when up is false, smallest not below it when up is true
+    pub fn whole(self, up: bool) -> Option<Self> {
+        let d = pow10(self.scale)?;
+        let mut digits = self.digits.div_euclid(d);
+        if up && self.digits.rem_euclid(d) != 0 {
+            digits += 1;
+        }
+        Some(Decimal { digits, scale: 0 })
+    }
+
+    /// Exact ordering, even when lining up the scales would overflow
+    pub fn compare(self, other: Decimal) -> Ordering {
+        match self.align(other) {
+            Some((a, b, _)) => a.cmp(&b),
+            // Only the one with the smaller scale is scaled up, so it overflowing means it is the larger in size
+            None if self.scale < other.scale => if self.digits < 0 { Ordering::Less } else { Ordering::Greater },
+            None => if other.digits < 0 { Ordering::Greater } else { Ordering::Less },
+        }
+    }
+
+    /// Same number with trailing zeros after the point removed, so 1.50 and 1.5 are equal
+    fn normalized(self) -> Self {
+        let mut out = self;
+        while out.scale > 0 && out.digits % 10 == 0 {
+            out = Decimal { digits: out.digits / 10, scale: out.scale - 1 };
+        }
+        out
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        let (a, b) = (self.normalized(), other.normalized());
+        a.digits == b.digits && a.scale == b.scale
+    }
+}
+
+impl Eq for Decimal {}
+
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let val = self.normalized();
+        val.digits.hash(state);
+        val.scale.hash(state);
+    }
+}
+
+/// Always shows scale digits after the point, so a decimal(10,2) prints 5 as 5.00
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.digits < 0 { "-" } else { "" };
+        let digits = self.digits.unsigned_abs().to_string();
+        if self.scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+        let digits = format!("{:0>width$}", digits, width = self.scale as usize + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - self.scale as usize);
+        write!(f, "{}{}.{}", sign, whole, fraction)
+    }
+}
+
+impl From<Decimal> for String {
+    fn from(val: Decimal) -> Self {
+        val.to_string()
+    }
+}
+
+impl TryFrom<String> for Decimal {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        Decimal::parse(&text).ok_or_else(|| format!("!Invalid decimal {}", text))
+    }
+}
+
+/// A number as a decimal, None for values that aren't numbers and floats that are too large
+pub fn to_decimal(val: &SQLValue) -> Option<Decimal> {
+    match val {
+        SQLValue::Decimal(val) => Some(*val),
+        SQLValue::Int(val) => Some(Decimal::from_int(*val)),
+        SQLValue::Float(val) => Decimal::from_float(*val),
+        _ => None,
+    }
+}
+
+/// Digits after the point of a numeric type, floats count as none
+fn scale_of(sql_type: SQLType) -> u32 {
+    match sql_type {
+        SQLType::Decimal(_, scale) => scale,
+        _ => 0,
+    }
+}
+
+fn is_float(sql_type: SQLType) -> bool {
+    matches!(sql_type, SQLType::Float | SQLType::Real | SQLType::Double)
+}
+
+/// Result type of arithmetic with a decimal on either side, None when neither is one
+/// A float on the other side makes the result a float
+pub fn arithmetic_type(lhs: SQLType, op: BinaryOp, rhs: SQLType) -> Option<SQLType> {
+    if !matches!(lhs, SQLType::Decimal(..)) && !matches!(rhs, SQLType::Decimal(..)) || !lhs.is_numeric() || !rhs.is_numeric() {
+        return None;
+    }
+    if is_float(lhs) || is_float(rhs) {
+        return None;
+    }
+    let (a, b) = (scale_of(lhs), scale_of(rhs));
+    let scale = match op {
+        BinaryOp::Mul => (a + b).min(MAX_PRECISION),
+        BinaryOp::Div => a.max(b).max(DIV_SCALE),
+        _ => a.max(b),
+    };
+    Some(SQLType::Decimal(MAX_PRECISION, scale))
+}
+
+/// Exact arithmetic when either side is a decimal, None when neither is
+/// Ints on the other side are read as decimals, a float on the other side leaves it to float arithmetic
+pub fn arithmetic(lhs: &SQLValue, op: BinaryOp, rhs: &SQLValue) -> Option<Result<SQLValue, String>> {
+    if !matches!(lhs, SQLValue::Decimal(_)) && !matches!(rhs, SQLValue::Decimal(_)) {
+        return None;
+    }
+    if matches!(lhs, SQLValue::Float(_)) || matches!(rhs, SQLValue::Float(_)) {
+        return None;
+    }
+    let (a, b) = match (to_decimal(lhs), to_decimal(rhs)) {
+        (Some(a), Some(b)) => (a, b),
+        _ if lhs.as_float().is_some() && rhs.as_float().is_some() => return Some(Err(String::from("!Numeric value out of range"))),
+        _ => return Some(Err(format!("!Cannot apply arithmetic to {} and {}", lhs.sql_type(), rhs.sql_type()))),
+    };
+    if b.digits == 0 && matches!(op, BinaryOp::Div | BinaryOp::Mod) {
+        return Some(Err(String::from("!Division by zero")));
+    }
+    let out = match op {
+        BinaryOp::Add => a.checked_add(b),
+        BinaryOp::Sub => a.checked_sub(b),
+        BinaryOp::Mul => a.checked_mul(b),
+        BinaryOp::Div => a.checked_div(b),
+        _ => a.checked_rem(b),
+    };
+    Some(out.map(SQLValue::Decimal).ok_or_else(|| String::from("!Numeric value out of range")))
+}
+
+/// Orders a decimal against another number, None when neither side is a decimal
+pub fn compare(a: &SQLValue, b: &SQLValue) -> Option<Ordering> {
+    if !matches!(a, SQLValue::Decimal(_)) && !matches!(b, SQLValue::Decimal(_)) {
+        return None;
+    }
+    match (to_decimal(a), to_decimal(b)) {
+        (Some(a), Some(b)) => Some(a.compare(b)),
+        // A float too large to be a decimal, or NaN
+        _ => Some(a.as_float()?.partial_cmp(&b.as_float()?).unwrap_or(Ordering::Equal)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(text: &str) -> Decimal {
+        Decimal::parse(text).unwrap()
+    }
+
+    #[test]
+    fn parses_text_and_exponents() {
+        assert_eq!(dec("-12.345").to_string(), "-12.345");
+        assert_eq!(dec("+0.50").to_string(), "0.50");
+        assert_eq!(dec("1.5e3").to_string(), "1500");
+        assert_eq!(dec("15e-3").to_string(), "0.015");
+        assert_eq!(dec(".5").to_string(), "0.5");
+        for text in ["", ".", "1.2.3", "abc", "1e", "--1"] {
+            assert!(Decimal::parse(text).is_none(), "{}", text);
+        }
+        // 39 digits do not fit in 38 digits of precision
+        assert!(Decimal::parse(&"9".repeat(40)).is_none());
+    }
+
+    #[test]
+    fn equal_values_compare_and_hash_alike() {
+        use std::collections::hash_map::DefaultHasher;
+        let hash = |val: Decimal| {
+            let mut hasher = DefaultHasher::new();
+            val.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(dec("1.50"), dec("1.5"));
+        assert_eq!(hash(dec("1.50")), hash(dec("1.5")));
+        assert_ne!(dec("1.5"), dec("15"));
+        assert_eq!(dec("0.1").compare(dec("0.10")), Ordering::Equal);
+        assert_eq!(dec("-2").compare(dec("1.99")), Ordering::Less);
+    }
+
+    #[test]
+    fn compares_when_aligning_scales_overflows() {
+        let large = Decimal { digits: i128::MAX / 10, scale: 0 };
+        let small = Decimal { digits: 1, scale: 30 };
+        assert_eq!(large.compare(small), Ordering::Greater);
+        assert_eq!(small.compare(large), Ordering::Less);
+        assert_eq!(large.checked_neg().unwrap().compare(small), Ordering::Less);
+        assert_eq!(small.compare(large.checked_neg().unwrap()), Ordering::Greater);
+    }
+
+    #[test]
+    fn rescale_rounds_half_away_from_zero() {
+        assert_eq!(dec("1.25").rescale(1).unwrap().to_string(), "1.3");
+        assert_eq!(dec("-1.25").rescale(1).unwrap().to_string(), "-1.3");
+        assert_eq!(dec("1.24").rescale(1).unwrap().to_string(), "1.2");
+        assert_eq!(dec("5").rescale(2).unwrap().to_string(), "5.00");
+        assert!(Decimal { digits: i128::MAX, scale: 0 }.rescale(1).is_none());
+    }
+
+    #[test]
+    fn fits_checks_digits_after_rounding() {
+        assert!(dec("999.994").fits(5, 2));
+        assert!(!dec("999.995").fits(5, 2));
+        assert!(dec("-99").fits(2, 0));
+        assert!(!dec("100").fits(2, 0));
+    }
+
+    #[test]
+    fn arithmetic_lines_up_scales() {
+        assert_eq!(dec("1.5").checked_add(dec("0.25")).unwrap().to_string(), "1.75");
+        assert_eq!(dec("1.5").checked_sub(dec("2")).unwrap().to_string(), "-0.5");
+        assert_eq!(dec("1.5").checked_mul(dec("0.25")).unwrap().to_string(), "0.375");
+        assert_eq!(dec("7.5").checked_rem(dec("2")).unwrap().to_string(), "1.5");
+        assert!(Decimal { digits: i128::MAX, scale: 0 }.checked_add(dec("1")).is_none());
+    }
+
+    #[test]
+    fn division_keeps_sixteen_digits() {
+        assert_eq!(dec("1").checked_div(dec("3")).unwrap().to_string(), "0.3333333333333333");
+        assert_eq!(dec("2").checked_div(dec("3")).unwrap().to_string(), "0.6666666666666667");
+        assert_eq!(dec("-1").checked_div(dec("8")).unwrap().to_string(), "-0.1250000000000000");
+        // More digits than that when an operand has them
+        assert_eq!(dec("1").checked_div(dec("3.00000000000000000")).unwrap().to_string(), "0.33333333333333333");
+        // A quotient too large for sixteen digits after the point keeps fewer
+        let large = Decimal { digits: 10_i128.pow(30), scale: 0 };
+        assert_eq!(large.checked_div(dec("0.5")).unwrap().to_int(), None);
+        assert_eq!(large.checked_div(dec("0.5")).unwrap().compare(Decimal { digits: 2 * 10_i128.pow(30), scale: 0 }), Ordering::Equal);
+    }
+
+    #[test]
+    fn round_and_whole() {
+        assert_eq!(dec("2.345").round(2).unwrap().to_string(), "2.35");
+        assert_eq!(dec("2.5").round(5).unwrap().to_string(), "2.5");
+        assert_eq!(dec("1250").round(-2).unwrap().to_string(), "1300");
+        assert_eq!(dec("-1250").round(-2).unwrap().to_string(), "-1300");
+        assert_eq!(dec("1250").round(-40).unwrap().to_string(), "0");
+        assert_eq!(dec("-1.5").whole(false).unwrap().to_string(), "-2");
+        assert_eq!(dec("-1.5").whole(true).unwrap().to_string(), "-1");
+        assert_eq!(dec("2").whole(true).unwrap().to_string(), "2");
+        assert_eq!(dec("2.5").to_int(), Some(3));
+    }
+}
@@ -0,0 +1,165 @@
+use super::expr::SQLValue;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::mem::{size_of, size_of_val};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Bytes of rows a RowSet keeps in memory before spilling the rest to disk
+const MEMORY_BUDGET: usize = 16 * 1024 * 1024;
+/// Number of files spilled rows are split between
+const PARTITIONS: usize = 16;
+
+/// Counter that keeps the spill files of one process apart
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Positions of the keys that weren't already seen in an earlier key, in order
+/// NULL is equal to NULL here
+pub fn first_of_each(keys: &[Vec<SQLValue>]) -> Result<Vec<usize>, String> {
+    let new = RowSet::new().insert(keys)?;
+    Ok(new.into_iter().enumerate().filter(|(_, new)| *new).map(|(i, _)| i).collect())
+}
+
+/// The rows that weren't already seen in an earlier row, in order
+pub fn unique(rows: Vec<Vec<SQLValue>>) -> Result<Vec<Vec<SQLValue>>, String> {
+    let new = RowSet::new().insert(&rows)?;
+    Ok(rows.into_iter().zip(new).filter(|(_, new)| *new).map(|(row, _)| row).collect())
+}
+
+/// A set of rows that keeps them in a hash set until it holds MEMORY_BUDGET bytes
+/// After that rows the hash set doesn't know are written to partition files by hash
+/// Equal rows always land in the same partition, so a row is looked up by reading only its partition
+/// Rows are added and looked up in batches so each partition is read once per batch
+pub struct RowSet {
+    memory: HashSet<Vec<SQLValue>>,
+    used: usize,
+    spill: Option<Spill>,
+}
+
+impl RowSet {
+    pub fn new() -> Self {
+        Self { memory: HashSet::new(), used: 0, spill: None }
+    }
+
+    /// Adds rows to the set, true for each one that wasn't in it or earlier in rows
+    pub fn insert(&mut self, rows: &[Vec<SQLValue>]) -> Result<Vec<bool>, String> {
+        let mut out = vec![false; rows.len()];
+        let mut spilled = vec![Vec::new(); PARTITIONS];
+        for (i, row) in rows.iter().enumerate() {
+            if self.memory.contains(row) {
+                continue;
+            }
+            // Rows only go to disk once memory is full, so while it isn't no row is on disk
+            if self.used < MEMORY_BUDGET {
+                self.used += size(row);
+                self.memory.insert(row.clone());
+                out[i] = true;
+            } else {
+                spilled[partition(row)].push(i);
+            }
+        }
+        for (partition, positions) in spilled.into_iter().enumerate().filter(|(_, positions)| !positions.is_empty()) {
+            if self.spill.is_none() {
+                self.spill = Some(Spill::new()?);
+            }
+            let spill = self.spill.as_mut().unwrap();
+            let mut known = spill.read(partition)?;
+            for i in positions {
+                if !known.contains(&rows[i]) {
+                    spill.write(partition, &rows[i])?;
+                    known.insert(rows[i].clone());
+                    out[i] = true;
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Whether each of rows is in the set
+    pub fn contains(&mut self, rows: &[Vec<SQLValue>]) -> Result<Vec<bool>, String> {
+        let mut out: Vec<bool> = rows.iter().map(|row| self.memory.contains(row)).collect();
+        if let Some(spill) = &mut self.spill {
+            let mut spilled = vec![Vec::new(); PARTITIONS];
+            for (i, row) in rows.iter().enumerate().filter(|(i, _)| !out[*i]) {
+                spilled[partition(row)].push(i);
+            }
+            for (partition, positions) in spilled.into_iter().enumerate().filter(|(_, positions)| !positions.is_empty()) {
+                let known = spill.read(partition)?;
+                for i in positions {
+                    out[i] = known.contains(&rows[i]);
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn partition(row: &[SQLValue]) -> usize {
+    let mut hasher = DefaultHasher::new();
+    row.hash(&mut hasher);
+    hasher.finish() as usize % PARTITIONS
+}
+
+/// Rough number of bytes a row takes in the hash set
+fn size(row: &[SQLValue]) -> usize {
+    let text: usize = row
+        .iter()
+        .map(|val| match val {
+            SQLValue::Char(val) => val.len(),
+            _ => 0,
+        })
+        .sum();
+    size_of::<Vec<SQLValue>>() + size_of_val(row) + text
+}
+
+fn io_error(e: std::io::Error) -> String {
+    format!("!Could not spill to disk: {}", e)
+}
+
+/// Partition files in the temp directory, deleted when dropped
+struct Spill {
+    paths: Vec<PathBuf>,
+    files: Vec<BufWriter<File>>,
+}
+
+impl Spill {
+    fn new() -> Result<Self, String> {
+        let id = SPILLS.fetch_add(1, Ordering::Relaxed);
+        let mut spill = Self { paths: Vec::new(), files: Vec::new() };
+        for partition in 0..PARTITIONS {
+            let path = std::env::temp_dir().join(format!("rust_db_{}_{}_{}.spill", process::id(), id, partition));
+            let file = File::create(&path).map_err(io_error)?;
+            spill.paths.push(path);
+            spill.files.push(BufWriter::new(file));
+        }
+        Ok(spill)
+    }
+
+    /// Appends a row to its partition as a line of JSON
+    fn write(&mut self, partition: usize, row: &[SQLValue]) -> Result<(), String> {
+        let line = serde_json::to_string(row).map_err(|e| format!("!Could not spill to disk: {}", e))?;
+        writeln!(self.files[partition], "{}", line).map_err(io_error)
+    }
+
+    /// Every row written to a partition so far
+    fn read(&mut self, partition: usize) -> Result<HashSet<Vec<SQLValue>>, String> {
+        self.files[partition].flush().map_err(io_error)?;
+        let file = File::open(&self.paths[partition]).map_err(io_error)?;
+        BufReader::new(file).lines().map(|line| {
+            let line = line.map_err(io_error)?;
+            serde_json::from_str(&line).map_err(|e| format!("!Could not read spilled rows: {}", e))
+        }).collect()
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
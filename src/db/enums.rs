@@ -0,0 +1,78 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+/// A type made by CREATE TYPE name AS ENUM ('a', 'b', ...), saved once with the database that made it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnumDef {
+    /// Number the type has within its database, which is all its columns and values save
+    pub id: u32,
+    pub name: String,
+    /// Labels in declaration order, which is the order values sort in
+    pub labels: Vec<String>,
+}
+
+/// An enum type as held by the columns and values of it, types are the same when their ids are
+/// Only the id is saved, the database puts the definition back in when it is read
+#[derive(Debug, Clone)]
+pub struct Enum(Rc<EnumDef>);
+
+impl Enum {
+    pub fn new(def: EnumDef) -> Self {
+        Enum(Rc::new(def))
+    }
+
+    pub fn id(&self) -> u32 {
+        self.0.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    pub fn label(&self, ordinal: u32) -> &str {
+        &self.0.labels[ordinal as usize]
+    }
+
+    /// Position of a label, the error names the type and its labels
+    pub fn ordinal(&self, label: &str) -> Result<u32, String> {
+        match self.0.labels.iter().position(|known| known == label) {
+            Some(i) => Ok(i as u32),
+            None => Err(format!("!Invalid value '{}' for enum {}, expected one of {}", label, self.name(), self.0.labels.join(", "))),
+        }
+    }
+}
+
+impl PartialEq for Enum {
+    fn eq(&self, other: &Self) -> bool {
+        self.id() == other.id()
+    }
+}
+
+impl Eq for Enum {}
+
+impl Hash for Enum {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id().hash(state);
+    }
+}
+
+impl fmt::Display for Enum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Serialize for Enum {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.id().serialize(serializer)
+    }
+}
+
+/// Read back with only its id, which is enough to compare it
+impl<'de> Deserialize<'de> for Enum {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Enum::new(EnumDef { id: u32::deserialize(deserializer)?, name: String::new(), labels: Vec::new() }))
+    }
+}
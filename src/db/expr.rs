@@ -0,0 +1,1615 @@
+use pest::iterators::Pair;
+use pest::pratt_parser::{Assoc, Op, PrattParser};
+use std::cmp::Ordering;
+use std::fmt;
+use super::{identifier, Rule};
+use super::select::{Catalog, Select};
+use std::hash::{Hash, Hasher};
+use super::functions::{self, Function};
+use super::sequence::SequenceFunction;
+use super::pattern::{self, Regex};
+use super::window::Window;
+use super::datetime::{self, Interval};
+use super::decimal::{self, Decimal};
+use super::enums::Enum;
+use super::json;
+use super::lob;
+use super::uuid::Uuid;
+use serde::{Serialize, Deserialize};
+
+/// A single value produced while evaluating an expression
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SQLValue {
+    Null,
+    Int(i64),
+    Float(f64),
+    Char(String),
+    Bool(bool),
+    Blob(Vec<u8>),
+    Json(serde_json::Value),
+    Decimal(Decimal),
+    /// Days since 1970-01-01
+    Date(i32),
+    /// Microseconds since midnight
+    Time(i64),
+    /// Microseconds since 1970-01-01 00:00:00
+    Timestamp(i64),
+    /// Microseconds since 1970-01-01 00:00:00 UTC
+    TimestampTz(i64),
+    Interval(Interval),
+    Uuid(Uuid),
+    /// Type and position of the label
+    Enum(Enum, u32),
+}
+
+impl fmt::Display for SQLValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SQLValue::Null => write!(f, "NULL"),
+            SQLValue::Int(val) => write!(f, "{}", val),
+            SQLValue::Float(val) => write!(f, "{}", val),
+            SQLValue::Char(val) => write!(f, "{}", val),
+            SQLValue::Bool(val) => write!(f, "{}", val),
+            SQLValue::Blob(val) => write!(f, "\\x{}", lob::to_hex(val)),
+            SQLValue::Json(val) => write!(f, "{}", val),
+            SQLValue::Decimal(val) => write!(f, "{}", val),
+            SQLValue::Date(val) => write!(f, "{}", datetime::format_date(*val)),
+            SQLValue::Time(val) => write!(f, "{}", datetime::format_time_of_day(*val)),
+            SQLValue::Timestamp(val) => write!(f, "{}", datetime::format_timestamp(*val)),
+            SQLValue::TimestampTz(val) => write!(f, "{}+00:00", datetime::format_timestamp(*val)),
+            SQLValue::Interval(val) => write!(f, "{}", val),
+            SQLValue::Uuid(val) => write!(f, "{}", val),
+            SQLValue::Enum(kind, val) => write!(f, "{}", kind.label(*val)),
+        }
+    }
+}
+
+/// Values are equal when they are the same variant holding the same data
+/// Floats are compared by their bits so NaN equals itself and rows can be hashed
+impl PartialEq for SQLValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (SQLValue::Null, SQLValue::Null) => true,
+            (SQLValue::Int(a), SQLValue::Int(b)) => a == b,
+            (SQLValue::Float(a), SQLValue::Float(b)) => float_bits(*a) == float_bits(*b),
+            (SQLValue::Char(a), SQLValue::Char(b)) => a == b,
+            (SQLValue::Bool(a), SQLValue::Bool(b)) => a == b,
+            (SQLValue::Blob(a), SQLValue::Blob(b)) => a == b,
+            (SQLValue::Json(a), SQLValue::Json(b)) => a == b,
+            (SQLValue::Decimal(a), SQLValue::Decimal(b)) => a == b,
+            (SQLValue::Date(a), SQLValue::Date(b)) => a == b,
+            (SQLValue::Time(a), SQLValue::Time(b)) => a == b,
+            (SQLValue::Timestamp(a), SQLValue::Timestamp(b)) => a == b,
+            (SQLValue::TimestampTz(a), SQLValue::TimestampTz(b)) => a == b,
+            (SQLValue::Interval(a), SQLValue::Interval(b)) => a == b,
+            (SQLValue::Uuid(a), SQLValue::Uuid(b)) => a == b,
+            (SQLValue::Enum(a, i), SQLValue::Enum(b, j)) => a == b && i == j,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for SQLValue {}
+
+impl Hash for SQLValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            SQLValue::Null => (),
+            SQLValue::Int(val) => val.hash(state),
+            SQLValue::Float(val) => float_bits(*val).hash(state),
+            SQLValue::Char(val) => val.hash(state),
+            SQLValue::Bool(val) => val.hash(state),
+            SQLValue::Blob(val) => val.hash(state),
+            // Object keys are kept sorted so equal values print the same
+            SQLValue::Json(val) => val.to_string().hash(state),
+            SQLValue::Decimal(val) => val.hash(state),
+            SQLValue::Date(val) => val.hash(state),
+            SQLValue::Time(val) | SQLValue::Timestamp(val) | SQLValue::TimestampTz(val) => val.hash(state),
+            SQLValue::Interval(val) => val.hash(state),
+            SQLValue::Uuid(val) => val.hash(state),
+            SQLValue::Enum(kind, val) => {
+                kind.hash(state);
+                val.hash(state);
+            },
+        }
+    }
+}
+
+/// Bits of a float with -0.0 folded into 0.0
+fn float_bits(val: f64) -> u64 {
+    if val == 0.0 {
+        0
+    } else {
+        val.to_bits()
+    }
+}
+
+/// Text of a string literal, '' is a quote and a backslash escapes the next character
+/// Escapes other than \n, \t, \r, \0, \\, \' and \" keep their backslash so '\d' still reaches REGEXP as is
+fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\'', _) => {
+                chars.next();
+                out.push('\'');
+            },
+            ('\\', Some(next)) => {
+                chars.next();
+                match next {
+                    'n' => out.push('\n'),
+                    't' => out.push('\t'),
+                    'r' => out.push('\r'),
+                    '0' => out.push('\0'),
+                    '\\' | '\'' | '"' => out.push(next),
+                    _ => {
+                        out.push('\\');
+                        out.push(next);
+                    },
+                }
+            },
+            (c, _) => out.push(c),
+        }
+    }
+    out
+}
+
+impl SQLValue {
+    /// Parses a columnVal literal
+    /// Whole numbers are ints and numbers with a decimal point are exact decimals read from their text
+    /// Numbers with an exponent are floats, as are numbers with too many digits for an int or decimal
+    pub fn from_literal(val: Pair<Rule>) -> Result<Self, String> {
+        let val = val.into_inner().next().unwrap();
+        match val.as_rule() {
+            Rule::intVal => match val.as_str().parse::<i64>() {
+                Ok(k) => Ok(SQLValue::Int(k)),
+                Err(_) => number_literal(val.as_str()),
+            },
+            Rule::floatVal => number_literal(val.as_str()),
+            Rule::charVal => Ok(SQLValue::Char(unescape(val.as_str()))),
+            Rule::boolVal => Ok(SQLValue::Bool(val.as_str().eq_ignore_ascii_case("TRUE"))),
+            Rule::hexVal => match lob::from_hex(val.as_str()) {
+                Some(bytes) => Ok(SQLValue::Blob(bytes)),
+                None => Err(format!("!Hex literal X'{}' must have an even number of digits", val.as_str())),
+            },
+            _ => Err(format!("!Invalid literal {}", val.as_str())),
+        }
+    }
+
+    /// The type of this value on its own
+    pub fn sql_type(&self) -> SQLType {
+        match self {
+            SQLValue::Null => SQLType::Null,
+            SQLValue::Int(val) if SQLType::Int.fits(*val) => SQLType::Int,
+            SQLValue::Int(_) => SQLType::BigInt,
+            SQLValue::Float(_) => SQLType::Float,
+            SQLValue::Char(val) => SQLType::Varchar(val.chars().count() as u32),
+            SQLValue::Bool(_) => SQLType::Bool,
+            SQLValue::Blob(_) => SQLType::Blob,
+            SQLValue::Json(_) => SQLType::Json,
+            SQLValue::Decimal(val) => SQLType::Decimal(decimal::MAX_PRECISION, val.scale()),
+            SQLValue::Date(_) => SQLType::Date,
+            SQLValue::Time(_) => SQLType::Time,
+            SQLValue::Timestamp(_) => SQLType::Timestamp,
+            SQLValue::TimestampTz(_) => SQLType::TimestampTz,
+            SQLValue::Interval(_) => SQLType::Interval,
+            SQLValue::Uuid(_) => SQLType::Uuid,
+            SQLValue::Enum(kind, _) => SQLType::Enum(kind.id()),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == SQLValue::Null
+    }
+
+    /// Compares two values, promoting ints to floats when they are mixed
+    /// Text compared with a date or time is read as one
+    /// Callers are expected to have handled NULL already
+    pub fn compare(&self, other: &Self) -> Result<Ordering, String> {
+        if let Some(ordering) = datetime::compare(self, other) {
+            return ordering;
+        }
+        if let Some(ordering) = decimal::compare(self, other) {
+            return Ok(ordering);
+        }
+        match (self, other) {
+            (SQLValue::Int(a), SQLValue::Int(b)) => Ok(a.cmp(b)),
+            (SQLValue::Char(a), SQLValue::Char(b)) => Ok(a.cmp(b)),
+            (SQLValue::Bool(a), SQLValue::Bool(b)) => Ok(a.cmp(b)),
+            (SQLValue::Blob(a), SQLValue::Blob(b)) => Ok(a.cmp(b)),
+            (SQLValue::Json(a), SQLValue::Json(b)) => Ok(json::compare(a, b)),
+            (SQLValue::Json(a), b) => json::compare_sql(a, b),
+            (a, SQLValue::Json(b)) => json::compare_sql(b, a).map(Ordering::reverse),
+            (SQLValue::Uuid(a), SQLValue::Uuid(b)) => Ok(a.cmp(b)),
+            (SQLValue::Uuid(a), SQLValue::Char(b)) => Ok(a.cmp(&parse_uuid(b)?)),
+            (SQLValue::Char(a), SQLValue::Uuid(b)) => Ok(parse_uuid(a)?.cmp(b)),
+            // Enums sort in the order their labels were declared and text is read as a label
+            (SQLValue::Enum(a, i), SQLValue::Enum(b, j)) if a == b => Ok(i.cmp(j)),
+            (SQLValue::Enum(a, i), SQLValue::Char(b)) => Ok(i.cmp(&a.ordinal(b)?)),
+            (SQLValue::Char(a), SQLValue::Enum(b, j)) => Ok(b.ordinal(a)?.cmp(j)),
+            (a, b) => match (a.as_float(), b.as_float()) {
+                (Some(a), Some(b)) => Ok(a.partial_cmp(&b).unwrap_or(Ordering::Equal)),
+                _ => Err(format!("!Cannot compare {} with {}", a.sql_type(), b.sql_type())),
+            },
+        }
+    }
+
+    /// Total order used for sorting, NULLs sort after every other value
+    /// Values that cannot be compared are ordered by type so sorting never fails
+    pub fn sort_cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (SQLValue::Null, SQLValue::Null) => Ordering::Equal,
+            (SQLValue::Null, _) => Ordering::Greater,
+            (_, SQLValue::Null) => Ordering::Less,
+            (a, b) => a.compare(b).unwrap_or_else(|_| a.rank().cmp(&b.rank())),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            SQLValue::Bool(_) => 0,
+            SQLValue::Int(_) | SQLValue::Float(_) | SQLValue::Decimal(_) => 1,
+            SQLValue::Char(_) => 2,
+            SQLValue::Date(_) | SQLValue::Timestamp(_) | SQLValue::TimestampTz(_) => 3,
+            SQLValue::Time(_) => 4,
+            SQLValue::Interval(_) => 5,
+            SQLValue::Blob(_) => 6,
+            SQLValue::Json(_) => 7,
+            SQLValue::Uuid(_) => 8,
+            SQLValue::Enum(..) => 9,
+            SQLValue::Null => 10,
+        }
+    }
+
+    /// Converts the value for CAST
+    /// Floats round to the nearest int and text has to hold a number or boolean to become one
+    /// Text is cut to the size of the target but numbers that don't fit are an error
+    /// Text becomes a date or time when it is in ISO 8601 form
+    pub fn cast(self, sql_type: SQLType) -> Result<Self, String> {
+        let val = match (self, sql_type) {
+            (SQLValue::Null, _) => return Ok(SQLValue::Null),
+            (SQLValue::Char(val), SQLType::Date) => SQLValue::Date(datetime::parse_date(&val)?),
+            (SQLValue::Char(val), SQLType::Time) => SQLValue::Time(datetime::parse_time(&val)?),
+            (SQLValue::Char(val), SQLType::Timestamp) => SQLValue::Timestamp(datetime::parse_timestamp(&val, false)?),
+            (SQLValue::Char(val), SQLType::TimestampTz) => SQLValue::TimestampTz(datetime::parse_timestamp(&val, true)?),
+            (SQLValue::Char(val), SQLType::Interval) => SQLValue::Interval(datetime::parse_interval(&val)?),
+            (SQLValue::Timestamp(val) | SQLValue::TimestampTz(val), SQLType::Date) => SQLValue::Date(datetime::timestamp_to_date(val)),
+            (SQLValue::Timestamp(val) | SQLValue::TimestampTz(val), SQLType::Time) => SQLValue::Time(datetime::timestamp_to_time(val)),
+            (SQLValue::Date(val), SQLType::Timestamp) => SQLValue::Timestamp(val as i64 * datetime::MICROS_PER_DAY),
+            (SQLValue::Date(val), SQLType::TimestampTz) => SQLValue::TimestampTz(val as i64 * datetime::MICROS_PER_DAY),
+            (SQLValue::Timestamp(val), SQLType::TimestampTz) => SQLValue::TimestampTz(val),
+            (SQLValue::TimestampTz(val), SQLType::Timestamp) => SQLValue::Timestamp(val),
+            (SQLValue::Float(val), sql_type) if sql_type.is_integer() => {
+                let val = val.round();
+                if val.is_finite() && val >= i64::MIN as f64 && val < i64::MAX as f64 {
+                    SQLValue::Int(val as i64)
+                } else {
+                    return Err(format!("!Value {} is out of range for {}", val, sql_type));
+                }
+            },
+            (SQLValue::Decimal(val), sql_type) if sql_type.is_integer() => match val.to_int() {
+                Some(int) => SQLValue::Int(int),
+                None => return Err(format!("!Value {} is out of range for {}", val, sql_type)),
+            },
+            (SQLValue::Bool(val), sql_type) if sql_type.is_numeric() => SQLValue::Int(val as i64),
+            (SQLValue::Char(val), SQLType::Decimal(..)) => SQLValue::Decimal(Decimal::try_from(val)?),
+            (SQLValue::Char(val), sql_type) if sql_type.is_integer() => match val.trim().parse::<i64>() {
+                Ok(int) => SQLValue::Int(int),
+                Err(_) => return Err(format!("!Cannot cast '{}' to {}", val, sql_type)),
+            },
+            (SQLValue::Char(val), sql_type) if sql_type.is_numeric() => match val.trim().parse::<f64>() {
+                Ok(float) if float.is_finite() => SQLValue::Float(float),
+                _ => return Err(format!("!Cannot cast '{}' to {}", val, sql_type)),
+            },
+            (SQLValue::Int(val), SQLType::Bool) => SQLValue::Bool(val != 0),
+            (SQLValue::Float(val), SQLType::Bool) => SQLValue::Bool(val != 0.0),
+            (SQLValue::Char(val), SQLType::Bool) => match val.trim().to_ascii_lowercase().as_str() {
+                "true" | "t" | "yes" | "y" | "on" | "1" => SQLValue::Bool(true),
+                "false" | "f" | "no" | "n" | "off" | "0" => SQLValue::Bool(false),
+                _ => return Err(format!("!Cannot cast '{}' to boolean", val)),
+            },
+            (SQLValue::Char(val), SQLType::Char(size) | SQLType::Varchar(size)) => {
+                return Ok(SQLValue::Char(val.chars().take(size as usize).collect()));
+            },
+            (val, SQLType::Text) => return Ok(SQLValue::Char(val.to_string())),
+            (SQLValue::Char(val), SQLType::Json) => SQLValue::Json(json::parse(&val)?),
+            (SQLValue::Char(val), SQLType::Uuid) => SQLValue::Uuid(parse_uuid(&val)?),
+            // Text in the \xDEADBEEF form is hex, any other text is taken as its bytes
+            (SQLValue::Char(val), SQLType::Blob) => match val.strip_prefix("\\x") {
+                Some(hex) => match lob::from_hex(hex) {
+                    Some(bytes) => SQLValue::Blob(bytes),
+                    None => return Err(format!("!Cannot cast '{}' to blob", val)),
+                },
+                None => SQLValue::Blob(val.into_bytes()),
+            },
+            (val, SQLType::Char(_) | SQLType::Varchar(_)) => {
+                let text = val.to_string();
+                return if text.chars().count() > sql_type.size() as usize {
+                    Err(format!("!Value {} is too long for {}", text, sql_type))
+                } else {
+                    Ok(SQLValue::Char(text))
+                };
+            },
+            (val, sql_type) if !sql_type.accepts(val.sql_type()) => {
+                return Err(format!("!Cannot cast {} to {}", val.sql_type(), sql_type));
+            },
+            (val, _) => val,
+        };
+        if val.fits(sql_type) {
+            Ok(val.coerce(sql_type))
+        } else {
+            Err(format!("!Value {} is out of range for {}", val, sql_type))
+        }
+    }
+
+    /// Widens an int to a float when the column it ends up in is a float
+    /// Values for a real column are rounded to single precision
+    /// Values for a decimal column are rounded to its scale, fits has to have been checked
+    pub fn coerce(self, sql_type: SQLType) -> Self {
+        match (self, sql_type) {
+            (val, SQLType::Decimal(_, scale)) if val.as_float().is_some() => match decimal::to_decimal(&val).and_then(|d| d.rescale(scale)) {
+                Some(d) => SQLValue::Decimal(d),
+                None => val,
+            },
+            (SQLValue::Decimal(val), SQLType::Real) => SQLValue::Float(single(val.to_float())),
+            (SQLValue::Decimal(val), SQLType::Float | SQLType::Double) => SQLValue::Float(val.to_float()),
+            (SQLValue::Int(val), SQLType::Float | SQLType::Double) => SQLValue::Float(val as f64),
+            (SQLValue::Int(val), SQLType::Real) => SQLValue::Float(single(val as f64)),
+            (SQLValue::Float(val), SQLType::Real) => SQLValue::Float(single(val)),
+            (val, _) => val,
+        }
+    }
+
+    /// Whether a number is in the range of a column of sql_type, other values always fit
+    /// A decimal fits when it has at most precision digits once rounded to its scale
+    pub fn fits(&self, sql_type: SQLType) -> bool {
+        match (self, sql_type) {
+            (val, SQLType::Decimal(precision, scale)) if val.as_float().is_some() => {
+                decimal::to_decimal(val).is_some_and(|val| val.fits(precision, scale))
+            },
+            (SQLValue::Int(val), sql_type) => sql_type.fits(*val),
+            (SQLValue::Float(val), SQLType::Real) => !val.is_finite() || val.abs() <= f32::MAX as f64,
+            (SQLValue::Decimal(val), SQLType::Real) => val.to_float().abs() <= f32::MAX as f64,
+            _ => true,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            SQLValue::Int(val) => Some(*val as f64),
+            SQLValue::Float(val) => Some(*val),
+            SQLValue::Decimal(val) => Some(val.to_float()),
+            _ => None,
+        }
+    }
+}
+
+/// A decimal when the text has no exponent and fits in one, otherwise a float
+fn number_literal(text: &str) -> Result<SQLValue, String> {
+    if !text.contains(['e', 'E']) {
+        if let Some(val) = Decimal::parse(text) {
+            return Ok(SQLValue::Decimal(val));
+        }
+    }
+    match text.parse::<f64>() {
+        Ok(k) if k.is_finite() => Ok(SQLValue::Float(k)),
+        _ => Err(format!("!Number {} is out of range", text)),
+    }
+}
+
+/// A minus sign in front of a number literal is part of the literal,
+/// so -9223372036854775808 is a bigint even though 9223372036854775808 alone is too large for one
+fn negative_literal(val: SQLValue) -> SQLExpr {
+    let min = Decimal::from_int(i64::MIN).checked_neg();
+    let out = match val {
+        SQLValue::Int(val) => val.checked_neg().map(SQLValue::Int),
+        SQLValue::Decimal(val) if Some(val) == min && val.scale() == 0 => Some(SQLValue::Int(i64::MIN)),
+        SQLValue::Decimal(val) => val.checked_neg().map(SQLValue::Decimal),
+        SQLValue::Float(val) => Some(SQLValue::Float(-val)),
+        _ => None,
+    };
+    match out {
+        Some(out) => SQLExpr::Literal(out),
+        None => SQLExpr::Unary(UnaryOp::Neg, Box::new(SQLExpr::Literal(val))),
+    }
+}
+
+fn parse_uuid(text: &str) -> Result<Uuid, String> {
+    Uuid::parse(text).ok_or_else(|| format!("!Invalid uuid '{}'", text))
+}
+
+/// Nearest single precision float to val, kept in its shortest decimal form so 0.1 stays 0.1
+pub fn single(val: f64) -> f64 {
+    (val as f32).to_string().parse().unwrap_or(val)
+}
+
+/// Type of an expression, resolved before any rows are evaluated
+/// Every integer type is evaluated as an i64 and every float type as an f64,
+/// the width is only checked when a value is stored or cast
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SQLType {
+    SmallInt,
+    Int,
+    BigInt,
+    Float,
+    Real,
+    Double,
+    /// Precision and scale
+    Decimal(u32, u32),
+    Char(u32),
+    Varchar(u32),
+    /// Text of any length
+    Text,
+    Blob,
+    Json,
+    Bool,
+    Date,
+    Time,
+    Timestamp,
+    TimestampTz,
+    Interval,
+    Uuid,
+    /// A type made by CREATE TYPE ... AS ENUM, by its id in the database
+    Enum(u32),
+    /// Type of a bare NULL, compatible with every other type
+    Null,
+}
+
+impl fmt::Display for SQLType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SQLType::SmallInt => write!(f, "smallint"),
+            SQLType::Int => write!(f, "int"),
+            SQLType::BigInt => write!(f, "bigint"),
+            SQLType::Float => write!(f, "float"),
+            SQLType::Real => write!(f, "real"),
+            SQLType::Double => write!(f, "double"),
+            SQLType::Decimal(precision, scale) => write!(f, "decimal({},{})", precision, scale),
+            SQLType::Char(size) => write!(f, "char({})", size),
+            SQLType::Varchar(size) => write!(f, "varchar({})", size),
+            SQLType::Text => write!(f, "text"),
+            SQLType::Blob => write!(f, "blob"),
+            SQLType::Json => write!(f, "json"),
+            SQLType::Bool => write!(f, "boolean"),
+            SQLType::Date => write!(f, "date"),
+            SQLType::Time => write!(f, "time"),
+            SQLType::Timestamp => write!(f, "timestamp"),
+            SQLType::TimestampTz => write!(f, "timestamptz"),
+            SQLType::Interval => write!(f, "interval"),
+            SQLType::Uuid => write!(f, "uuid"),
+            // Only the database knows the name, ResultSet::show prints it
+            SQLType::Enum(_) => write!(f, "enum"),
+            SQLType::Null => write!(f, "null"),
+        }
+    }
+}
+
+impl SQLType {
+    pub fn is_integer(&self) -> bool {
+        matches!(self, SQLType::SmallInt | SQLType::Int | SQLType::BigInt | SQLType::Null)
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        self.is_integer() || matches!(self, SQLType::Float | SQLType::Real | SQLType::Double | SQLType::Decimal(..))
+    }
+
+    /// Smallest and largest value of an integer type
+    fn range(&self) -> Option<(i64, i64)> {
+        match self {
+            SQLType::SmallInt => Some((i16::MIN as i64, i16::MAX as i64)),
+            SQLType::Int => Some((i32::MIN as i64, i32::MAX as i64)),
+            SQLType::BigInt => Some((i64::MIN, i64::MAX)),
+            _ => None,
+        }
+    }
+
+    /// Type of a sum of values of this type, smallints and ints add up to a bigint as in PostgreSQL
+    pub fn widened(&self) -> SQLType {
+        match self {
+            SQLType::SmallInt | SQLType::Int => SQLType::BigInt,
+            sql_type => *sql_type,
+        }
+    }
+
+    /// Whether an int is in range for this type, true for types that aren't integers
+    pub fn fits(&self, val: i64) -> bool {
+        self.range().is_none_or(|(min, max)| min <= val && val <= max)
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(self, SQLType::Char(_) | SQLType::Varchar(_) | SQLType::Text | SQLType::Null)
+    }
+
+    pub fn is_bool(&self) -> bool {
+        matches!(self, SQLType::Bool | SQLType::Null)
+    }
+
+    /// The type both sides can be converted to, if there is one
+    /// Ints widen to the wider int, a decimal or a float, decimals meeting a float become floats
+    /// and text widens to the longer varchar
+    /// Dates widen to timestamps and text meeting a date or time is read as one
+    pub fn common(&self, other: SQLType) -> Option<SQLType> {
+        match (*self, other) {
+            (SQLType::Null, b) => Some(b),
+            (a, SQLType::Null) => Some(a),
+            (a, b) if a == b => Some(a),
+            (SQLType::TimestampTz, SQLType::Date | SQLType::Timestamp) | (SQLType::Date | SQLType::Timestamp, SQLType::TimestampTz) => Some(SQLType::TimestampTz),
+            (SQLType::Timestamp, SQLType::Date) | (SQLType::Date, SQLType::Timestamp) => Some(SQLType::Timestamp),
+            (a, b) if datetime::is_datetime(a) && b.is_text() => Some(a),
+            (a, b) if a.is_text() && datetime::is_datetime(b) => Some(b),
+            (SQLType::Json, b) if b.is_text() => Some(SQLType::Json),
+            (a, SQLType::Json) if a.is_text() => Some(SQLType::Json),
+            (a @ (SQLType::Uuid | SQLType::Enum(_)), b) if b.is_text() => Some(a),
+            (a, b @ (SQLType::Uuid | SQLType::Enum(_))) if a.is_text() => Some(b),
+            (a, b) if a.is_integer() && b.is_integer() => Some(if a.range().map(|(_, max)| max) > b.range().map(|(_, max)| max) { a } else { b }),
+            (SQLType::Decimal(..), SQLType::Float | SQLType::Real | SQLType::Double) | (SQLType::Float | SQLType::Real | SQLType::Double, SQLType::Decimal(..)) => Some(SQLType::Float),
+            (a, b) if a.is_numeric() && b.is_numeric() && matches!((a, b), (SQLType::Decimal(..), _) | (_, SQLType::Decimal(..))) => {
+                // Enough whole digits for either side, ints count as the digits of their largest value
+                let whole = |t: SQLType| match t {
+                    SQLType::Decimal(precision, scale) => precision - scale,
+                    t => t.range().map_or(decimal::MAX_PRECISION, |(_, max)| max.to_string().len() as u32),
+                };
+                let scale = a.scale().max(b.scale());
+                Some(SQLType::Decimal((whole(a).max(whole(b)) + scale).min(decimal::MAX_PRECISION), scale))
+            },
+            (a, b) if a.is_numeric() && b.is_numeric() => Some(SQLType::Float),
+            (a, b) if a.is_text() && b.is_text() => Some(SQLType::varchar(a.size().max(b.size()) as u64)),
+            _ => None,
+        }
+    }
+
+    /// Whether values of the two types can be compared
+    /// JSON compares with numbers and booleans as well as with the types it has in common
+    pub fn comparable(&self, other: SQLType) -> bool {
+        match (*self, other) {
+            (SQLType::Json, b) | (b, SQLType::Json) if b.is_numeric() || b == SQLType::Bool => true,
+            (a, b) => a.common(b).is_some(),
+        }
+    }
+
+    /// Whether a value of type other can be stored in a column of this type
+    pub fn accepts(&self, other: SQLType) -> bool {
+        match self {
+            _ if other == SQLType::Null => true,
+            // Integers of any width are accepted and range checked when stored
+            _ if self.is_integer() => other.is_integer(),
+            SQLType::Float | SQLType::Real | SQLType::Double => other.is_numeric(),
+            // Text is read as an exact number when stored
+            SQLType::Decimal(..) => other.is_numeric() || other.is_text(),
+            SQLType::Char(_) | SQLType::Varchar(_) | SQLType::Text => other.is_text(),
+            // Text is read as hex or as its bytes when stored
+            SQLType::Blob => other == SQLType::Blob || other.is_text(),
+            // Text is parsed and has to be valid JSON when stored
+            SQLType::Json => other == SQLType::Json || other.is_text(),
+            // Text is parsed as a uuid or looked up as a label of the enum when stored
+            SQLType::Uuid | SQLType::Enum(_) => *self == other || other.is_text(),
+            // Text is parsed when stored
+            SQLType::Timestamp | SQLType::TimestampTz => matches!(other, SQLType::Date | SQLType::Timestamp | SQLType::TimestampTz) || other.is_text(),
+            SQLType::Date | SQLType::Time | SQLType::Interval => *self == other || other.is_text(),
+            _ => *self == other,
+        }
+    }
+
+    /// Reads the type of a column definition or CAST
+    pub fn parse(pair: Pair<Rule>) -> Result<SQLType, String> {
+        Ok(match pair.as_rule() {
+            Rule::decimal => {
+                let mut it = pair.into_inner().filter(|pair| matches!(pair.as_rule(), Rule::number | Rule::intVal));
+                let precision = it.next().map_or(Ok(decimal::DEFAULT_PRECISION), |pair| pair.as_str().parse::<u32>());
+                let scale = it.next().map_or(Ok(0), |pair| pair.as_str().parse::<u32>());
+                match (precision, scale) {
+                    (Ok(precision), Ok(scale)) if precision <= decimal::MAX_PRECISION && scale <= precision => SQLType::Decimal(precision, scale),
+                    _ => return Err(format!("!DECIMAL precision must be 1 to {} and scale 0 to the precision", decimal::MAX_PRECISION)),
+                }
+            },
+            Rule::char => SQLType::Char(pair.into_inner().nth(1).unwrap().as_str().parse::<u32>().unwrap_or(u32::MAX)),
+            Rule::varchar => SQLType::Varchar(pair.into_inner().nth(1).unwrap().as_str().parse::<u32>().unwrap_or(u32::MAX)),
+            Rule::float => SQLType::Float,
+            Rule::real => SQLType::Real,
+            Rule::double => SQLType::Double,
+            Rule::smallint => SQLType::SmallInt,
+            Rule::bigint => SQLType::BigInt,
+            Rule::boolean => SQLType::Bool,
+            Rule::text => SQLType::Text,
+            Rule::blob => SQLType::Blob,
+            Rule::json => SQLType::Json,
+            Rule::date => SQLType::Date,
+            Rule::time => SQLType::Time,
+            Rule::timestamp => SQLType::Timestamp,
+            Rule::timestamptz => SQLType::TimestampTz,
+            Rule::interval => SQLType::Interval,
+            Rule::uuid => SQLType::Uuid,
+            _ => SQLType::Int,
+        })
+    }
+
+    /// Length of a text type, zero for everything else
+    pub fn size(&self) -> u32 {
+        match self {
+            SQLType::Char(size) | SQLType::Varchar(size) => *size,
+            SQLType::Text => u32::MAX,
+            _ => 0,
+        }
+    }
+
+    /// Most characters a value of this type prints as, None when there is no bound
+    /// Integers are evaluated as i64 whatever their type, so every integer type can print as 20 characters
+    pub fn width(&self) -> Option<u64> {
+        match self {
+            SQLType::Text => None,
+            _ if self.is_text() => Some(self.size() as u64),
+            _ if self.is_integer() => Some(20),
+            SQLType::Float | SQLType::Real | SQLType::Double => Some(24),
+            // A sign, a point and a zero before it when every digit is after the point
+            SQLType::Decimal(precision, _) => Some(*precision as u64 + 3),
+            SQLType::Bool => Some(5),
+            // Days and microseconds held in an i32 and i64 reach years with 7 digits
+            SQLType::Date => Some(14),
+            SQLType::Time => Some(15),
+            SQLType::Timestamp => Some(30),
+            SQLType::TimestampTz => Some(36),
+            SQLType::Uuid => Some(36),
+            _ => None,
+        }
+    }
+
+    /// Varchar of a computed length, text when that is too long for a varchar
+    pub fn varchar(size: u64) -> SQLType {
+        match u32::try_from(size) {
+            Ok(size) if size < u32::MAX => SQLType::Varchar(size),
+            _ => SQLType::Text,
+        }
+    }
+
+    /// Digits after the point of a decimal, zero for everything else
+    pub fn scale(&self) -> u32 {
+        match self {
+            SQLType::Decimal(_, scale) => *scale,
+            _ => 0,
+        }
+    }
+}
+
+/// A named and typed column of a table or query result
+#[derive(Debug, Clone)]
+pub struct Field {
+    /// Table or alias the column came from, used to resolve qualified names like t.col
+    pub table: Option<String>,
+    pub name: String,
+    pub sql_type: SQLType,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.sql_type)
+    }
+}
+
+/// The row an expression is evaluated against
+/// Subqueries get a scope of their own whose outer scope is the row of the enclosing query
+/// When type checking values is empty as only the fields are looked at
+pub struct Scope<'a> {
+    pub fields: &'a [Field],
+    pub values: &'a [SQLValue],
+    pub outer: Option<&'a Scope<'a>>,
+    pub catalog: &'a Catalog<'a>,
+    /// Window function results for the row, None where window functions aren't allowed
+    pub windows: Option<&'a [SQLValue]>,
+}
+
+impl<'a> Scope<'a> {
+    pub fn new(catalog: &'a Catalog<'a>, fields: &'a [Field], values: &'a [SQLValue], outer: Option<&'a Scope<'a>>) -> Self {
+        Self { fields, values, outer, catalog, windows: None }
+    }
+
+    /// Allows window functions, windows holds their results for the current row
+    pub fn with_windows(mut self, windows: &'a [SQLValue]) -> Self {
+        self.windows = Some(windows);
+        self
+    }
+
+    /// Finds the scope a column belongs to, starting with the innermost one
+    fn lookup(&self, table: Option<&str>, name: &str) -> Result<(&Scope<'a>, usize), String> {
+        let mut found = self.fields.iter().enumerate().filter(|(_, field)| {
+            field.name == name && (table.is_none() || field.table.as_deref() == table)
+        });
+        match (found.next(), found.next()) {
+            (Some((i, _)), None) => Ok((self, i)),
+            (Some(_), Some(_)) => Err(format!("!Column {} is ambiguous", name)),
+            (None, _) => match self.outer {
+                Some(outer) => outer.lookup(table, name),
+                None => match table {
+                    Some(table) => Err(format!("!Column {}.{} does not exist", table, name)),
+                    None => Err(format!("!Column {} does not exist", name)),
+                },
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Neq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Concat,
+    /// json -> key
+    JsonGet,
+    /// json ->> key, the result as text
+    JsonText,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+/// Parsed expression tree
+#[derive(Debug, Clone, PartialEq)]
+pub enum SQLExpr {
+    Literal(SQLValue),
+    /// Optionally qualified column name
+    Column(Option<String>, String),
+    Unary(UnaryOp, Box<SQLExpr>),
+    Binary(Box<SQLExpr>, BinaryOp, Box<SQLExpr>),
+    Function(&'static Function, Vec<SQLExpr>),
+    /// nextval, currval and setval, which read the sequences of the database
+    Sequence(SequenceFunction, Vec<SQLExpr>),
+    IsNull { expr: Box<SQLExpr>, negated: bool },
+    Like { expr: Box<SQLExpr>, pattern: Box<SQLExpr>, escape: Option<Box<SQLExpr>>, negated: bool },
+    InList { expr: Box<SQLExpr>, list: Vec<SQLExpr>, negated: bool },
+    Between { expr: Box<SQLExpr>, low: Box<SQLExpr>, high: Box<SQLExpr>, negated: bool },
+    Regexp { expr: Box<SQLExpr>, pattern: Box<SQLExpr>, negated: bool },
+    /// A subquery returning at most one row with one column
+    Subquery(Box<Select>),
+    InSelect { expr: Box<SQLExpr>, query: Box<Select>, negated: bool },
+    Exists(Box<Select>),
+    Window(Box<Window>),
+    /// CASE [operand] WHEN ... THEN ... [ELSE ...] END
+    /// With an operand each WHEN is a value compared to it, otherwise each WHEN is a condition
+    Case { operand: Option<Box<SQLExpr>>, whens: Vec<(SQLExpr, SQLExpr)>, otherwise: Option<Box<SQLExpr>> },
+    Cast(Box<SQLExpr>, SQLType),
+}
+
+impl SQLExpr {
+    /// Builds an expression tree from an expr pair
+    pub fn parse(expr: Pair<Rule>) -> Result<Self, String> {
+        PrattParser::new()
+            .op(Op::infix(Rule::or, Assoc::Left))
+            .op(Op::infix(Rule::and, Assoc::Left))
+            .op(Op::prefix(Rule::not))
+            .op(Op::infix(Rule::eq, Assoc::Left) | Op::infix(Rule::neq, Assoc::Left)
+                | Op::infix(Rule::lt, Assoc::Left) | Op::infix(Rule::le, Assoc::Left)
+                | Op::infix(Rule::gt, Assoc::Left) | Op::infix(Rule::ge, Assoc::Left))
+            .op(Op::postfix(Rule::isNull) | Op::postfix(Rule::like) | Op::postfix(Rule::inList)
+                | Op::postfix(Rule::between) | Op::postfix(Rule::regexp))
+            .op(Op::infix(Rule::concat, Assoc::Left))
+            .op(Op::infix(Rule::plus, Assoc::Left) | Op::infix(Rule::minus, Assoc::Left))
+            .op(Op::infix(Rule::times, Assoc::Left) | Op::infix(Rule::divide, Assoc::Left)
+                | Op::infix(Rule::modulo, Assoc::Left))
+            .op(Op::infix(Rule::jsonGet, Assoc::Left) | Op::infix(Rule::jsonText, Assoc::Left))
+            .op(Op::prefix(Rule::neg))
+            .map_primary(|primary| match primary.as_rule() {
+                Rule::columnVal => Ok(SQLExpr::Literal(SQLValue::from_literal(primary)?)),
+                Rule::columnRef => {
+                    let mut it = primary.into_inner().rev();
+                    let name = identifier(it.next().unwrap());
+                    Ok(SQLExpr::Column(it.next().map(identifier), name))
+                },
+                Rule::subquery => Ok(SQLExpr::Subquery(Box::new(Select::parse(primary.into_inner())?))),
+                Rule::exists => {
+                    let query = primary.into_inner().find(|pair| pair.as_rule() == Rule::subquery).unwrap();
+                    Ok(SQLExpr::Exists(Box::new(Select::parse(query.into_inner())?)))
+                },
+                Rule::null => Ok(SQLExpr::Literal(SQLValue::Null)),
+                Rule::case => {
+                    let mut operand = None;
+                    let mut whens = Vec::new();
+                    let mut otherwise = None;
+                    for pair in primary.into_inner() {
+                        match pair.as_rule() {
+                            Rule::expr if whens.is_empty() => operand = Some(Box::new(SQLExpr::parse(pair)?)),
+                            Rule::expr => otherwise = Some(Box::new(SQLExpr::parse(pair)?)),
+                            Rule::when => {
+                                let mut it = pair.into_inner().filter(|pair| pair.as_rule() == Rule::expr);
+                                let when = SQLExpr::parse(it.next().unwrap())?;
+                                whens.push((when, SQLExpr::parse(it.next().unwrap())?));
+                            },
+                            _ => (),
+                        }
+                    }
+                    Ok(SQLExpr::Case { operand, whens, otherwise })
+                },
+                Rule::cast => {
+                    let mut it = primary.into_inner().filter(|pair| !matches!(pair.as_rule(), Rule::castKw | Rule::_as));
+                    let expr = SQLExpr::parse(it.next().unwrap())?;
+                    Ok(SQLExpr::Cast(Box::new(expr), SQLType::parse(it.next().unwrap())?))
+                },
+                Rule::typedLiteral => {
+                    let mut it = primary.into_inner();
+                    let sql_type = SQLType::parse(it.next().unwrap())?;
+                    Ok(SQLExpr::Literal(SQLValue::Char(unescape(it.next().unwrap().as_str())).cast(sql_type)?))
+                },
+                Rule::currentDateTime => Ok(SQLExpr::Function(functions::lookup(primary.as_str()).unwrap(), Vec::new())),
+                // EXTRACT(field FROM expr) is DATE_PART('field', expr)
+                Rule::extract => {
+                    let mut it = primary.into_inner().filter(|pair| matches!(pair.as_rule(), Rule::name | Rule::expr));
+                    let field = it.next().unwrap().as_str();
+                    if !datetime::is_field(field) {
+                        return Err(format!("!Unknown EXTRACT field {}", field));
+                    }
+                    let field = SQLExpr::Literal(SQLValue::Char(field.to_ascii_lowercase()));
+                    let expr = SQLExpr::parse(it.next().unwrap())?;
+                    Ok(SQLExpr::Function(functions::lookup("DATE_PART").unwrap(), vec![field, expr]))
+                },
+                Rule::functionCall => {
+                    let mut it = primary.into_inner();
+                    let name = it.next().unwrap().as_str();
+                    let mut args = Vec::new();
+                    for arg in it.filter(|arg| arg.as_rule() == Rule::expr) {
+                        args.push(SQLExpr::parse(arg)?);
+                    }
+                    if let Some(function) = SequenceFunction::lookup(name) {
+                        return Ok(SQLExpr::Sequence(function, args));
+                    }
+                    if let Some(function) = functions::lookup(name) {
+                        return Ok(SQLExpr::Function(function, args));
+                    }
+                    match Window::aggregate(name, args) {
+                        Some(aggregate) => Ok(SQLExpr::Window(Box::new(aggregate))),
+                        None => Err(format!("!Function {} does not exist", name)),
+                    }
+                },
+                Rule::windowCall => Ok(SQLExpr::Window(Box::new(Window::parse(primary)?))),
+                Rule::expr | Rule::operand => SQLExpr::parse(primary),
+                _ => Err(format!("!Unexpected {} in expression", primary.as_str())),
+            })
+            .map_prefix(|op, rhs| {
+                let op = match op.as_rule() {
+                    Rule::neg => UnaryOp::Neg,
+                    _ => UnaryOp::Not,
+                };
+                match (op, rhs?) {
+                    (UnaryOp::Neg, SQLExpr::Literal(val)) => Ok(negative_literal(val)),
+                    (op, rhs) => Ok(SQLExpr::Unary(op, Box::new(rhs))),
+                }
+            })
+            .map_infix(|lhs, op, rhs| {
+                let op = match op.as_rule() {
+                    Rule::or => BinaryOp::Or,
+                    Rule::and => BinaryOp::And,
+                    Rule::eq => BinaryOp::Eq,
+                    Rule::neq => BinaryOp::Neq,
+                    Rule::lt => BinaryOp::Lt,
+                    Rule::le => BinaryOp::Le,
+                    Rule::gt => BinaryOp::Gt,
+                    Rule::ge => BinaryOp::Ge,
+                    Rule::concat => BinaryOp::Concat,
+                    Rule::jsonGet => BinaryOp::JsonGet,
+                    Rule::jsonText => BinaryOp::JsonText,
+                    Rule::plus => BinaryOp::Add,
+                    Rule::minus => BinaryOp::Sub,
+                    Rule::times => BinaryOp::Mul,
+                    Rule::divide => BinaryOp::Div,
+                    _ => BinaryOp::Mod,
+                };
+                Ok(SQLExpr::Binary(Box::new(lhs?), op, Box::new(rhs?)))
+            })
+            .map_postfix(|lhs, op| {
+                let expr = Box::new(lhs?);
+                let rule = op.as_rule();
+                let mut negated = false;
+                let mut args = Vec::new();
+                for pair in op.into_inner() {
+                    match pair.as_rule() {
+                        Rule::not => negated = true,
+                        Rule::expr | Rule::operand => args.push(SQLExpr::parse(pair)?),
+                        Rule::subquery => {
+                            let query = Box::new(Select::parse(pair.into_inner())?);
+                            return Ok(SQLExpr::InSelect { expr, query, negated });
+                        },
+                        _ => (),
+                    }
+                }
+                let mut args = args.into_iter();
+                match rule {
+                    Rule::isNull => Ok(SQLExpr::IsNull { expr, negated }),
+                    Rule::like => Ok(SQLExpr::Like {
+                        expr,
+                        pattern: Box::new(args.next().unwrap()),
+                        escape: args.next().map(Box::new),
+                        negated,
+                    }),
+                    Rule::inList => Ok(SQLExpr::InList { expr, list: args.collect(), negated }),
+                    Rule::between => Ok(SQLExpr::Between {
+                        expr,
+                        low: Box::new(args.next().unwrap()),
+                        high: Box::new(args.next().unwrap()),
+                        negated,
+                    }),
+                    _ => Ok(SQLExpr::Regexp { expr, pattern: Box::new(args.next().unwrap()), negated }),
+                }
+            })
+            .parse(expr.into_inner())
+    }
+
+    /// Resolves the type of the expression against the columns in scope
+    /// Type errors are reported here so they are caught before any rows are touched
+    pub fn check(&self, scope: &Scope) -> Result<SQLType, String> {
+        match self {
+            SQLExpr::Literal(val) => Ok(val.sql_type()),
+            SQLExpr::Column(table, name) => {
+                let (scope, i) = scope.lookup(table.as_deref(), name)?;
+                Ok(scope.fields[i].sql_type)
+            },
+            SQLExpr::Unary(op, expr) => {
+                let sql_type = expr.check(scope)?;
+                match op {
+                    UnaryOp::Neg if sql_type.is_numeric() || sql_type == SQLType::Interval => Ok(sql_type),
+                    UnaryOp::Not if sql_type.is_bool() => Ok(SQLType::Bool),
+                    UnaryOp::Neg => Err(format!("!Cannot negate {}", sql_type)),
+                    UnaryOp::Not => Err(format!("!NOT expects boolean but got {}", sql_type)),
+                }
+            },
+            SQLExpr::Binary(lhs, op, rhs) => {
+                let lhs = lhs.check(scope)?;
+                let rhs = rhs.check(scope)?;
+                match op {
+                    BinaryOp::Or | BinaryOp::And => {
+                        if lhs.is_bool() && rhs.is_bool() {
+                            Ok(SQLType::Bool)
+                        } else {
+                            Err(format!("!Cannot apply logical operator to {} and {}", lhs, rhs))
+                        }
+                    },
+                    BinaryOp::Eq | BinaryOp::Neq | BinaryOp::Lt
+                    | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+                        if lhs.comparable(rhs) {
+                            Ok(SQLType::Bool)
+                        } else {
+                            Err(format!("!Cannot compare {} with {}", lhs, rhs))
+                        }
+                    },
+                    BinaryOp::JsonGet | BinaryOp::JsonText => {
+                        if !matches!(lhs, SQLType::Json | SQLType::Null) {
+                            return Err(format!("!{} expects json but got {}", if *op == BinaryOp::JsonGet { "->" } else { "->>" }, lhs));
+                        }
+                        if !rhs.is_text() && !rhs.is_integer() {
+                            return Err(format!("!JSON key must be text or int but got {}", rhs));
+                        }
+                        Ok(if *op == BinaryOp::JsonGet { SQLType::Json } else { SQLType::Text })
+                    },
+                    BinaryOp::Concat if matches!((lhs, rhs), (SQLType::Blob, SQLType::Blob | SQLType::Null) | (SQLType::Null, SQLType::Blob)) => Ok(SQLType::Blob),
+                    BinaryOp::Concat => match (lhs.width(), rhs.width()) {
+                        (Some(a), Some(b)) => Ok(SQLType::varchar(a + b)),
+                        _ => Ok(SQLType::Text),
+                    },
+                    _ => {
+                        if let Some(sql_type) = datetime::arithmetic_type(lhs, *op, rhs).or_else(|| decimal::arithmetic_type(lhs, *op, rhs)) {
+                            return Ok(sql_type);
+                        }
+                        match (lhs, rhs) {
+                            (SQLType::Null, SQLType::Null) => Ok(SQLType::Null),
+                            (a, b) if a.is_integer() && b.is_integer() => Ok(a.common(b).unwrap()),
+                            (a, b) if a.is_numeric() && b.is_numeric() => Ok(SQLType::Float),
+                            (a, b) => Err(format!("!Cannot apply arithmetic to {} and {}", a, b)),
+                        }
+                    }
+                }
+            },
+            SQLExpr::Function(function, args) => {
+                let mut types = Vec::new();
+                for arg in args {
+                    types.push(arg.check(scope)?);
+                }
+                function.check(&types)
+            },
+            SQLExpr::Sequence(function, args) => {
+                let mut types = Vec::new();
+                for arg in args {
+                    types.push(arg.check(scope)?);
+                }
+                function.check(&types)
+            },
+            SQLExpr::IsNull { expr, .. } => {
+                expr.check(scope)?;
+                Ok(SQLType::Bool)
+            },
+            SQLExpr::Like { expr, pattern, escape, .. } => {
+                let mut args = vec![expr, pattern];
+                args.extend(escape);
+                for arg in args {
+                    let sql_type = arg.check(scope)?;
+                    if !sql_type.is_text() {
+                        return Err(format!("!LIKE expects char or varchar but got {}", sql_type));
+                    }
+                }
+                Ok(SQLType::Bool)
+            },
+            SQLExpr::Regexp { expr, pattern, .. } => {
+                for arg in [expr, pattern] {
+                    let sql_type = arg.check(scope)?;
+                    if !sql_type.is_text() {
+                        return Err(format!("!REGEXP expects char or varchar but got {}", sql_type));
+                    }
+                }
+                Ok(SQLType::Bool)
+            },
+            SQLExpr::InList { expr, list, .. } => {
+                let sql_type = expr.check(scope)?;
+                for item in list {
+                    let item = item.check(scope)?;
+                    if !sql_type.comparable(item) {
+                        return Err(format!("!Cannot compare {} with {}", sql_type, item));
+                    }
+                }
+                Ok(SQLType::Bool)
+            },
+            SQLExpr::Between { expr, low, high, .. } => {
+                let sql_type = expr.check(scope)?;
+                for bound in [low, high] {
+                    let bound = bound.check(scope)?;
+                    if !sql_type.comparable(bound) {
+                        return Err(format!("!Cannot compare {} with {}", sql_type, bound));
+                    }
+                }
+                Ok(SQLType::Bool)
+            },
+            SQLExpr::Subquery(query) => {
+                let fields = query.fields(scope.catalog, Some(scope))?;
+                match fields.as_slice() {
+                    [field] => Ok(field.sql_type),
+                    _ => Err(format!("!Subquery must return one column but returns {}", fields.len())),
+                }
+            },
+            SQLExpr::InSelect { expr, query, .. } => {
+                let sql_type = expr.check(scope)?;
+                let fields = query.fields(scope.catalog, Some(scope))?;
+                match fields.as_slice() {
+                    [field] if sql_type.comparable(field.sql_type) => Ok(SQLType::Bool),
+                    [field] => Err(format!("!Cannot compare {} with {}", sql_type, field.sql_type)),
+                    _ => Err(format!("!Subquery must return one column but returns {}", fields.len())),
+                }
+            },
+            SQLExpr::Exists(query) => {
+                query.fields(scope.catalog, Some(scope))?;
+                Ok(SQLType::Bool)
+            },
+            SQLExpr::Window(window) => match scope.windows {
+                Some(_) => window.check(scope),
+                None if window.is_aggregate() => Err(String::from("!Aggregate functions are only allowed in the select list")),
+                None => Err(String::from("!Window functions are only allowed in the select list")),
+            },
+            SQLExpr::Case { operand, whens, otherwise } => {
+                let operand = match operand {
+                    Some(operand) => Some(operand.check(scope)?),
+                    None => None,
+                };
+                let mut out = SQLType::Null;
+                let mut results = Vec::new();
+                for (when, then) in whens {
+                    let when = when.check(scope)?;
+                    match operand {
+                        Some(operand) if !operand.comparable(when) => {
+                            return Err(format!("!Cannot compare {} with {}", operand, when));
+                        },
+                        None if !when.is_bool() => return Err(format!("!CASE WHEN condition must be boolean but is {}", when)),
+                        _ => (),
+                    }
+                    results.push(then.check(scope)?);
+                }
+                if let Some(otherwise) = otherwise {
+                    results.push(otherwise.check(scope)?);
+                }
+                for result in results {
+                    out = match out.common(result) {
+                        Some(sql_type) => sql_type,
+                        None => return Err(format!("!CASE results {} and {} do not match", out, result)),
+                    };
+                }
+                Ok(out)
+            },
+            SQLExpr::Cast(expr, sql_type) => {
+                expr.check(scope)?;
+                Ok(*sql_type)
+            },
+        }
+    }
+
+    /// An int worked out in 64 bits by this expression, checked against the range of its type
+    /// so int op int stays an int as in PostgreSQL and only a bigint operand makes room for more
+    /// Every integer type holds the smallint range, so only values past it work the type out
+    fn in_range(&self, scope: &Scope, val: i64) -> Result<SQLValue, String> {
+        if !SQLType::SmallInt.fits(val) {
+            let sql_type = self.check(scope)?;
+            if !sql_type.fits(val) {
+                return Err(format!("!Value {} is out of range for {}", val, sql_type));
+            }
+        }
+        Ok(SQLValue::Int(val))
+    }
+
+    /// Evaluates the expression for a single row
+    pub fn eval(&self, scope: &Scope) -> Result<SQLValue, String> {
+        match self {
+            SQLExpr::Literal(val) => Ok(val.clone()),
+            SQLExpr::Column(table, name) => {
+                let (scope, i) = scope.lookup(table.as_deref(), name)?;
+                Ok(scope.values[i].clone())
+            },
+            SQLExpr::Unary(op, expr) => {
+                match (op, expr.eval(scope)?) {
+                    (_, SQLValue::Null) => Ok(SQLValue::Null),
+                    (UnaryOp::Neg, SQLValue::Int(val)) => match val.checked_neg() {
+                        Some(k) => self.in_range(scope, k),
+                        None => Err(String::from("!Integer out of range")),
+                    },
+                    (UnaryOp::Neg, SQLValue::Decimal(val)) => val.checked_neg().map(SQLValue::Decimal).ok_or_else(|| String::from("!Numeric value out of range")),
+                    (UnaryOp::Neg, SQLValue::Interval(val)) => val.checked_neg().map(SQLValue::Interval).ok_or_else(|| String::from("!Interval out of range")),
+                    (UnaryOp::Neg, SQLValue::Float(val)) => Ok(SQLValue::Float(-val)),
+                    (UnaryOp::Not, SQLValue::Bool(val)) => Ok(SQLValue::Bool(!val)),
+                    (_, val) => Err(format!("!Invalid operand {}", val)),
+                }
+            },
+            SQLExpr::Binary(lhs, op, rhs) => {
+                let lhs = lhs.eval(scope)?;
+                // AND and OR short circuit so the right side is only evaluated when needed
+                match (op, &lhs) {
+                    (BinaryOp::And, SQLValue::Bool(false)) => return Ok(lhs),
+                    (BinaryOp::Or, SQLValue::Bool(true)) => return Ok(lhs),
+                    _ => (),
+                }
+                let rhs = rhs.eval(scope)?;
+                match op {
+                    // Three valued logic, NULL only wins when the other side doesn't decide the result
+                    BinaryOp::Or | BinaryOp::And => match (&lhs, &rhs) {
+                        (_, SQLValue::Bool(val)) if *val == (*op == BinaryOp::Or) => Ok(rhs),
+                        (SQLValue::Null, _) | (_, SQLValue::Null) => Ok(SQLValue::Null),
+                        (_, SQLValue::Bool(_)) => Ok(rhs),
+                        _ => Err(format!("!Invalid operand {}", rhs)),
+                    },
+                    _ if lhs.is_null() || rhs.is_null() => Ok(SQLValue::Null),
+                    BinaryOp::Eq => Ok(SQLValue::Bool(lhs.compare(&rhs)? == Ordering::Equal)),
+                    BinaryOp::Neq => Ok(SQLValue::Bool(lhs.compare(&rhs)? != Ordering::Equal)),
+                    BinaryOp::Lt => Ok(SQLValue::Bool(lhs.compare(&rhs)? == Ordering::Less)),
+                    BinaryOp::Le => Ok(SQLValue::Bool(lhs.compare(&rhs)? != Ordering::Greater)),
+                    BinaryOp::Gt => Ok(SQLValue::Bool(lhs.compare(&rhs)? == Ordering::Greater)),
+                    BinaryOp::Ge => Ok(SQLValue::Bool(lhs.compare(&rhs)? != Ordering::Less)),
+                    BinaryOp::JsonGet | BinaryOp::JsonText => match &lhs {
+                        SQLValue::Json(val) => Ok(match (op, json::get(val, &rhs)) {
+                            (_, None) => SQLValue::Null,
+                            (BinaryOp::JsonGet, Some(val)) => SQLValue::Json(val.clone()),
+                            (_, Some(val)) => json::to_text(val),
+                        }),
+                        _ => Err(format!("!Invalid operand {}", lhs)),
+                    },
+                    BinaryOp::Concat => match (lhs, rhs) {
+                        (SQLValue::Blob(mut lhs), SQLValue::Blob(rhs)) => {
+                            lhs.extend(rhs);
+                            Ok(SQLValue::Blob(lhs))
+                        },
+                        (lhs, rhs) => Ok(SQLValue::Char(format!("{}{}", lhs, rhs))),
+                    },
+                    _ => match arithmetic(lhs, *op, rhs)? {
+                        SQLValue::Int(k) => self.in_range(scope, k),
+                        val => Ok(val),
+                    },
+                }
+            },
+            SQLExpr::Function(function, args) => {
+                let mut vals = Vec::new();
+                for arg in args {
+                    vals.push(arg.eval(scope)?);
+                }
+                match function.eval(vals)? {
+                    SQLValue::Int(k) => self.in_range(scope, k),
+                    val => Ok(val),
+                }
+            },
+            SQLExpr::Sequence(function, args) => {
+                let mut vals = Vec::new();
+                for arg in args {
+                    vals.push(arg.eval(scope)?);
+                }
+                function.eval(vals, scope)
+            },
+            SQLExpr::IsNull { expr, negated } => Ok(SQLValue::Bool(expr.eval(scope)?.is_null() != *negated)),
+            SQLExpr::Like { expr, pattern, escape, negated } => {
+                let escape = match escape {
+                    None => None,
+                    Some(escape) => match escape.eval(scope)? {
+                        SQLValue::Null => return Ok(SQLValue::Null),
+                        SQLValue::Char(escape) if escape.chars().count() == 1 => escape.chars().next(),
+                        _ => return Err(String::from("!ESCAPE must be a single character")),
+                    },
+                };
+                match (expr.eval(scope)?, pattern.eval(scope)?) {
+                    (SQLValue::Char(text), SQLValue::Char(pattern)) => {
+                        Ok(SQLValue::Bool(pattern::like(&text, &pattern, escape)? != *negated))
+                    },
+                    _ => Ok(SQLValue::Null),
+                }
+            },
+            SQLExpr::Regexp { expr, pattern, negated } => {
+                match (expr.eval(scope)?, pattern.eval(scope)?) {
+                    (SQLValue::Char(text), SQLValue::Char(pattern)) => {
+                        Ok(SQLValue::Bool(Regex::new(&pattern)?.is_match(&text) != *negated))
+                    },
+                    _ => Ok(SQLValue::Null),
+                }
+            },
+            SQLExpr::InList { expr, list, negated } => {
+                let val = expr.eval(scope)?;
+                if val.is_null() {
+                    return Ok(SQLValue::Null);
+                }
+                // A miss is only false when no NULL was in the list, otherwise it is unknown
+                let mut out = Some(false);
+                for item in list {
+                    let item = item.eval(scope)?;
+                    if item.is_null() {
+                        out = None;
+                    } else if val.compare(&item)?.is_eq() {
+                        out = Some(true);
+                        break;
+                    }
+                }
+                Ok(tri_state(out.map(|found| found != *negated)))
+            },
+            SQLExpr::Between { expr, low, high, negated } => {
+                let val = expr.eval(scope)?;
+                let low = low.eval(scope)?;
+                let high = high.eval(scope)?;
+                let above = if val.is_null() || low.is_null() {
+                    None
+                } else {
+                    Some(val.compare(&low)?.is_ge())
+                };
+                let below = if val.is_null() || high.is_null() {
+                    None
+                } else {
+                    Some(val.compare(&high)?.is_le())
+                };
+                let out = match (above, below) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                };
+                Ok(tri_state(out.map(|inside| inside != *negated)))
+            },
+            SQLExpr::Subquery(query) => {
+                let result = query.execute(scope.catalog, Some(scope))?;
+                match result.rows.len() {
+                    0 => Ok(SQLValue::Null),
+                    1 => Ok(result.rows[0][0].clone()),
+                    n => Err(format!("!Subquery used as a value returned {} rows", n)),
+                }
+            },
+            SQLExpr::InSelect { expr, query, negated } => {
+                let val = expr.eval(scope)?;
+                if val.is_null() {
+                    return Ok(SQLValue::Null);
+                }
+                let mut out = Some(false);
+                for row in query.execute(scope.catalog, Some(scope))?.rows {
+                    if row[0].is_null() {
+                        out = None;
+                    } else if val.compare(&row[0])?.is_eq() {
+                        out = Some(true);
+                        break;
+                    }
+                }
+                Ok(tri_state(out.map(|found| found != *negated)))
+            },
+            SQLExpr::Exists(query) => Ok(SQLValue::Bool(!query.execute(scope.catalog, Some(scope))?.rows.is_empty())),
+            SQLExpr::Window(window) => match scope.windows.and_then(|windows| windows.get(window.id)) {
+                Some(val) => Ok(val.clone()),
+                None if window.is_aggregate() => Err(String::from("!Aggregate functions are only allowed in the select list")),
+                None => Err(String::from("!Window functions are only allowed in the select list")),
+            },
+            SQLExpr::Case { operand, whens, otherwise } => {
+                let operand = match operand {
+                    Some(operand) => Some(operand.eval(scope)?),
+                    None => None,
+                };
+                for (when, then) in whens {
+                    let when = when.eval(scope)?;
+                    let matched = match &operand {
+                        Some(operand) => !operand.is_null() && !when.is_null() && operand.compare(&when)?.is_eq(),
+                        None => when == SQLValue::Bool(true),
+                    };
+                    if matched {
+                        return then.eval(scope);
+                    }
+                }
+                match otherwise {
+                    Some(otherwise) => otherwise.eval(scope),
+                    None => Ok(SQLValue::Null),
+                }
+            },
+            SQLExpr::Cast(expr, sql_type) => expr.eval(scope)?.cast(*sql_type),
+        }
+    }
+
+    /// Collects the window function calls in the expression, not counting those inside subqueries
+    pub fn windows_mut<'a>(&'a mut self, out: &mut Vec<&'a mut Window>) {
+        match self {
+            SQLExpr::Literal(_) | SQLExpr::Column(..) | SQLExpr::Subquery(_) | SQLExpr::Exists(_) => (),
+            SQLExpr::Window(window) => out.push(window),
+            SQLExpr::Unary(_, expr) | SQLExpr::IsNull { expr, .. } | SQLExpr::InSelect { expr, .. } | SQLExpr::Cast(expr, _) => {
+                expr.windows_mut(out)
+            },
+            SQLExpr::Binary(lhs, _, rhs) | SQLExpr::Regexp { expr: lhs, pattern: rhs, .. } => {
+                lhs.windows_mut(out);
+                rhs.windows_mut(out);
+            },
+            SQLExpr::Like { expr, pattern, escape, .. } => {
+                expr.windows_mut(out);
+                pattern.windows_mut(out);
+                if let Some(escape) = escape {
+                    escape.windows_mut(out);
+                }
+            },
+            SQLExpr::Between { expr, low, high, .. } => {
+                expr.windows_mut(out);
+                low.windows_mut(out);
+                high.windows_mut(out);
+            },
+            SQLExpr::Function(_, args) | SQLExpr::Sequence(_, args) => {
+                for arg in args {
+                    arg.windows_mut(out);
+                }
+            },
+            SQLExpr::Case { operand, whens, otherwise } => {
+                if let Some(operand) = operand {
+                    operand.windows_mut(out);
+                }
+                for (when, then) in whens {
+                    when.windows_mut(out);
+                    then.windows_mut(out);
+                }
+                if let Some(otherwise) = otherwise {
+                    otherwise.windows_mut(out);
+                }
+            },
+            SQLExpr::InList { expr, list, .. } => {
+                expr.windows_mut(out);
+                for expr in list {
+                    expr.windows_mut(out);
+                }
+            },
+        }
+    }
+
+    /// Collects the table qualifiers and names of the column references in the expression, not counting those inside subqueries or windows
+    pub fn columns_mut<'a>(&'a mut self, out: &mut Vec<(&'a mut Option<String>, &'a mut String)>) {
+        match self {
+            SQLExpr::Literal(_) | SQLExpr::Subquery(_) | SQLExpr::Exists(_) | SQLExpr::Window(_) => (),
+            SQLExpr::Column(table, name) => out.push((table, name)),
+            SQLExpr::Unary(_, expr) | SQLExpr::IsNull { expr, .. } | SQLExpr::InSelect { expr, .. } | SQLExpr::Cast(expr, _) => {
+                expr.columns_mut(out)
+            },
+            SQLExpr::Binary(lhs, _, rhs) | SQLExpr::Regexp { expr: lhs, pattern: rhs, .. } => {
+                lhs.columns_mut(out);
+                rhs.columns_mut(out);
+            },
+            SQLExpr::Like { expr, pattern, escape, .. } => {
+                expr.columns_mut(out);
+                pattern.columns_mut(out);
+                if let Some(escape) = escape {
+                    escape.columns_mut(out);
+                }
+            },
+            SQLExpr::Between { expr, low, high, .. } => {
+                expr.columns_mut(out);
+                low.columns_mut(out);
+                high.columns_mut(out);
+            },
+            SQLExpr::Function(_, args) | SQLExpr::Sequence(_, args) => {
+                for arg in args {
+                    arg.columns_mut(out);
+                }
+            },
+            SQLExpr::Case { operand, whens, otherwise } => {
+                if let Some(operand) = operand {
+                    operand.columns_mut(out);
+                }
+                for (when, then) in whens {
+                    when.columns_mut(out);
+                    then.columns_mut(out);
+                }
+                if let Some(otherwise) = otherwise {
+                    otherwise.columns_mut(out);
+                }
+            },
+            SQLExpr::InList { expr, list, .. } => {
+                expr.columns_mut(out);
+                for expr in list {
+                    expr.columns_mut(out);
+                }
+            },
+        }
+    }
+
+    /// Whether the expression only reads the row it is evaluated on
+    /// Subqueries read tables, nextval and its kin read sequences and window functions read other rows
+    pub fn reads_only_row(&self) -> bool {
+        match self {
+            SQLExpr::Literal(_) | SQLExpr::Column(..) => true,
+            SQLExpr::Subquery(_) | SQLExpr::InSelect { .. } | SQLExpr::Exists(_) | SQLExpr::Sequence(..) | SQLExpr::Window(_) => false,
+            SQLExpr::Unary(_, expr) | SQLExpr::IsNull { expr, .. } | SQLExpr::Cast(expr, _) => expr.reads_only_row(),
+            SQLExpr::Binary(lhs, _, rhs) | SQLExpr::Regexp { expr: lhs, pattern: rhs, .. } => lhs.reads_only_row() && rhs.reads_only_row(),
+            SQLExpr::Like { expr, pattern, escape, .. } => {
+                expr.reads_only_row() && pattern.reads_only_row() && escape.iter().all(|escape| escape.reads_only_row())
+            },
+            SQLExpr::Between { expr, low, high, .. } => expr.reads_only_row() && low.reads_only_row() && high.reads_only_row(),
+            SQLExpr::Function(_, args) => args.iter().all(SQLExpr::reads_only_row),
+            SQLExpr::Case { operand, whens, otherwise } => {
+                operand.iter().chain(otherwise).all(|expr| expr.reads_only_row())
+                    && whens.iter().all(|(when, then)| when.reads_only_row() && then.reads_only_row())
+            },
+            SQLExpr::InList { expr, list, .. } => expr.reads_only_row() && list.iter().all(SQLExpr::reads_only_row),
+        }
+    }
+
+    /// Collects the queries of the subqueries in the expression, including those in window calls
+    /// but not those nested inside another subquery
+    pub fn subqueries<'a>(&'a self, out: &mut Vec<&'a Select>) {
+        match self {
+            SQLExpr::Literal(_) | SQLExpr::Column(..) => (),
+            SQLExpr::Subquery(query) | SQLExpr::Exists(query) => out.push(query),
+            SQLExpr::InSelect { expr, query, .. } => {
+                expr.subqueries(out);
+                out.push(query);
+            },
+            SQLExpr::Window(window) => {
+                for expr in window.exprs() {
+                    expr.subqueries(out);
+                }
+            },
+            SQLExpr::Unary(_, expr) | SQLExpr::IsNull { expr, .. } | SQLExpr::Cast(expr, _) => expr.subqueries(out),
+            SQLExpr::Binary(lhs, _, rhs) | SQLExpr::Regexp { expr: lhs, pattern: rhs, .. } => {
+                lhs.subqueries(out);
+                rhs.subqueries(out);
+            },
+            SQLExpr::Like { expr, pattern, escape, .. } => {
+                expr.subqueries(out);
+                pattern.subqueries(out);
+                if let Some(escape) = escape {
+                    escape.subqueries(out);
+                }
+            },
+            SQLExpr::Between { expr, low, high, .. } => {
+                expr.subqueries(out);
+                low.subqueries(out);
+                high.subqueries(out);
+            },
+            SQLExpr::Function(_, args) | SQLExpr::Sequence(_, args) => {
+                for arg in args {
+                    arg.subqueries(out);
+                }
+            },
+            SQLExpr::Case { operand, whens, otherwise } => {
+                if let Some(operand) = operand {
+                    operand.subqueries(out);
+                }
+                for (when, then) in whens {
+                    when.subqueries(out);
+                    then.subqueries(out);
+                }
+                if let Some(otherwise) = otherwise {
+                    otherwise.subqueries(out);
+                }
+            },
+            SQLExpr::InList { expr, list, .. } => {
+                expr.subqueries(out);
+                for expr in list {
+                    expr.subqueries(out);
+                }
+            },
+        }
+    }
+}
+
+/// Turns a boolean that might be unknown into a value
+fn tri_state(val: Option<bool>) -> SQLValue {
+    match val {
+        Some(val) => SQLValue::Bool(val),
+        None => SQLValue::Null,
+    }
+}
+
+/// Applies + - * / % with int/float promotion
+/// Two ints stay an int, anything involving a float becomes a float
+pub fn arithmetic(lhs: SQLValue, op: BinaryOp, rhs: SQLValue) -> Result<SQLValue, String> {
+    if let Some(out) = datetime::arithmetic(&lhs, op, &rhs).or_else(|| decimal::arithmetic(&lhs, op, &rhs)) {
+        return out;
+    }
+    match (lhs, rhs) {
+        (SQLValue::Int(a), SQLValue::Int(b)) => {
+            let out = match op {
+                BinaryOp::Add => a.checked_add(b),
+                BinaryOp::Sub => a.checked_sub(b),
+                BinaryOp::Mul => a.checked_mul(b),
+                BinaryOp::Div if b == 0 => return Err(String::from("!Division by zero")),
+                BinaryOp::Div => a.checked_div(b),
+                BinaryOp::Mod if b == 0 => return Err(String::from("!Division by zero")),
+                _ => a.checked_rem(b),
+            };
+            match out {
+                Some(k) => Ok(SQLValue::Int(k)),
+                None => Err(String::from("!Integer out of range")),
+            }
+        },
+        (a, b) => match (a.as_float(), b.as_float()) {
+            (Some(a), Some(b)) => {
+                if b == 0.0 && matches!(op, BinaryOp::Div | BinaryOp::Mod) {
+                    return Err(String::from("!Division by zero"));
+                }
+                Ok(SQLValue::Float(match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Sub => a - b,
+                    BinaryOp::Mul => a * b,
+                    BinaryOp::Div => a / b,
+                    _ => a % b,
+                }))
+            },
+            _ => Err(format!("!Cannot apply arithmetic to {} and {}", a, b)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pest::Parser;
+    use super::super::{DataBase, SQLParser};
+    use super::super::select::Catalog;
+
+    fn parse(text: &str) -> SQLExpr {
+        SQLExpr::parse(SQLParser::parse(Rule::expr, text).unwrap().next().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn minus_is_part_of_number_literals() {
+        assert!(matches!(parse("-9223372036854775808"), SQLExpr::Literal(SQLValue::Int(i64::MIN))));
+        assert!(matches!(parse("- -5"), SQLExpr::Literal(SQLValue::Int(5))));
+        assert!(matches!(parse("-1e3"), SQLExpr::Literal(SQLValue::Float(val)) if val == -1000.0));
+        let SQLExpr::Literal(SQLValue::Decimal(val)) = parse("-9223372036854775808.0") else { panic!() };
+        assert_eq!(val.to_string(), "-9223372036854775808.0");
+        assert!(matches!(parse("-9223372036854775809"), SQLExpr::Literal(SQLValue::Decimal(_))));
+        // Still an operator in front of anything else
+        assert!(matches!(parse("-NULL"), SQLExpr::Unary(UnaryOp::Neg, _)));
+        assert!(matches!(parse("2 - -3"), SQLExpr::Binary(_, BinaryOp::Sub, rhs) if matches!(*rhs, SQLExpr::Literal(SQLValue::Int(-3)))));
+    }
+
+    #[test]
+    fn integer_arithmetic_keeps_the_type_of_its_operands() {
+        let db = DataBase::new();
+        let catalog = Catalog::new(&db);
+        let field = |name: &str, sql_type| Field { table: None, name: String::from(name), sql_type };
+        let fields = [field("s", SQLType::SmallInt), field("n", SQLType::Int), field("b", SQLType::BigInt)];
+        let row = [SQLValue::Int(30000), SQLValue::Int(2000000000), SQLValue::Int(5)];
+        let scope = Scope::new(&catalog, &fields, &row, None);
+        assert_eq!(parse("n * 2").check(&scope), Ok(SQLType::Int));
+        assert_eq!(parse("n * 2").eval(&scope), Err(String::from("!Value 4000000000 is out of range for int")));
+        assert_eq!(parse("s + s").eval(&scope), Err(String::from("!Value 60000 is out of range for smallint")));
+        assert_eq!(parse("s + 30000").eval(&scope), Ok(SQLValue::Int(60000)));
+        assert_eq!(parse("n * b").check(&scope), Ok(SQLType::BigInt));
+        assert_eq!(parse("n * b").eval(&scope), Ok(SQLValue::Int(10000000000)));
+        assert_eq!(parse("-n - n").eval(&scope), Err(String::from("!Value -4000000000 is out of range for int")));
+    }
+}
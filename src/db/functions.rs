@@ -0,0 +1,359 @@
+use super::datetime;
+use super::json;
+use super::uuid::Uuid;
+use super::expr::{SQLType, SQLValue};
+
+/// A built-in scalar function
+/// check resolves the return type from the argument types before any rows are read
+/// and eval computes the result for one row
+#[derive(Debug)]
+pub struct Function {
+    pub name: &'static str,
+    min_args: usize,
+    max_args: usize,
+    /// Strict functions return NULL as soon as any argument is NULL
+    strict: bool,
+    check: fn(&[SQLType]) -> Result<SQLType, String>,
+    eval: fn(&[SQLValue]) -> Result<SQLValue, String>,
+}
+
+/// Built-ins are told apart by name
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Function {
+    pub fn check(&self, args: &[SQLType]) -> Result<SQLType, String> {
+        if args.len() < self.min_args || args.len() > self.max_args {
+            if self.min_args == self.max_args {
+                Err(format!("!{} takes {} arguments but got {}", self.name, self.min_args, args.len()))
+            } else if self.max_args == usize::MAX {
+                Err(format!("!{} takes at least {} arguments but got {}", self.name, self.min_args, args.len()))
+            } else {
+                Err(format!("!{} takes {} to {} arguments but got {}", self.name, self.min_args, self.max_args, args.len()))
+            }
+        } else {
+            (self.check)(args)
+        }
+    }
+
+    pub fn eval(&self, args: Vec<SQLValue>) -> Result<SQLValue, String> {
+        if self.strict && args.iter().any(|arg| arg.is_null()) {
+            Ok(SQLValue::Null)
+        } else {
+            (self.eval)(&args)
+        }
+    }
+}
+
+/// Finds a built-in by name, ignoring case
+pub fn lookup(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|function| function.name.eq_ignore_ascii_case(name))
+}
+
+static FUNCTIONS: [Function; 19] = [
+    Function { name: "UPPER", min_args: 1, max_args: 1, strict: true, check: text_to_text, eval: upper },
+    Function { name: "LOWER", min_args: 1, max_args: 1, strict: true, check: text_to_text, eval: lower },
+    Function { name: "LENGTH", min_args: 1, max_args: 1, strict: true, check: check_length, eval: length },
+    Function { name: "SUBSTR", min_args: 2, max_args: 3, strict: true, check: check_substr, eval: substr },
+    Function { name: "TRIM", min_args: 1, max_args: 1, strict: true, check: text_to_text, eval: trim },
+    Function { name: "REPLACE", min_args: 3, max_args: 3, strict: true, check: check_replace, eval: replace },
+    Function { name: "ABS", min_args: 1, max_args: 1, strict: true, check: numeric_to_numeric, eval: abs },
+    Function { name: "ROUND", min_args: 1, max_args: 2, strict: true, check: check_round, eval: round },
+    Function { name: "FLOOR", min_args: 1, max_args: 1, strict: true, check: numeric_to_numeric, eval: floor },
+    Function { name: "CEIL", min_args: 1, max_args: 1, strict: true, check: numeric_to_numeric, eval: ceil },
+    Function { name: "COALESCE", min_args: 1, max_args: usize::MAX, strict: false, check: check_coalesce, eval: coalesce },
+    Function { name: "NULLIF", min_args: 2, max_args: 2, strict: false, check: check_nullif, eval: nullif },
+    Function { name: "NOW", min_args: 0, max_args: 0, strict: true, check: check_timestamptz, eval: now },
+    Function { name: "CURRENT_TIMESTAMP", min_args: 0, max_args: 0, strict: true, check: check_timestamptz, eval: now },
+    Function { name: "CURRENT_DATE", min_args: 0, max_args: 0, strict: true, check: check_date, eval: current_date },
+    Function { name: "CURRENT_TIME", min_args: 0, max_args: 0, strict: true, check: check_time, eval: current_time },
+    Function { name: "DATE_PART", min_args: 2, max_args: 2, strict: true, check: check_date_part, eval: date_part },
+    Function { name: "JSON_EXTRACT", min_args: 2, max_args: 2, strict: true, check: check_json_extract, eval: json_extract },
+    Function { name: "GEN_RANDOM_UUID", min_args: 0, max_args: 0, strict: true, check: check_uuid, eval: gen_random_uuid },
+];
+
+fn expect_text(arg: SQLType) -> Result<u32, String> {
+    if arg.is_text() {
+        Ok(arg.size())
+    } else {
+        Err(format!("!Expected char or varchar but got {}", arg))
+    }
+}
+
+fn expect_int(arg: SQLType) -> Result<(), String> {
+    if arg.is_integer() {
+        Ok(())
+    } else {
+        Err(format!("!Expected int but got {}", arg))
+    }
+}
+
+fn expect_numeric(arg: SQLType) -> Result<SQLType, String> {
+    if arg.is_numeric() {
+        Ok(arg)
+    } else {
+        Err(format!("!Expected int or float but got {}", arg))
+    }
+}
+
+fn text_to_text(args: &[SQLType]) -> Result<SQLType, String> {
+    Ok(SQLType::varchar(expect_text(args[0])? as u64))
+}
+
+/// Characters of text or bytes of a blob
+fn check_length(args: &[SQLType]) -> Result<SQLType, String> {
+    if args[0] != SQLType::Blob {
+        expect_text(args[0])?;
+    }
+    Ok(SQLType::Int)
+}
+
+fn numeric_to_numeric(args: &[SQLType]) -> Result<SQLType, String> {
+    expect_numeric(args[0])
+}
+
+fn check_substr(args: &[SQLType]) -> Result<SQLType, String> {
+    let size = expect_text(args[0])?;
+    for arg in &args[1..] {
+        expect_int(*arg)?;
+    }
+    Ok(SQLType::varchar(size as u64))
+}
+
+fn check_replace(args: &[SQLType]) -> Result<SQLType, String> {
+    let size = expect_text(args[0])?;
+    expect_text(args[1])?;
+    // Every match removes at least one character and adds at most the replacement
+    Ok(SQLType::varchar(size as u64 * expect_text(args[2])?.max(1) as u64))
+}
+
+fn check_round(args: &[SQLType]) -> Result<SQLType, String> {
+    if let Some(digits) = args.get(1) {
+        expect_int(*digits)?;
+    }
+    expect_numeric(args[0])
+}
+
+fn check_coalesce(args: &[SQLType]) -> Result<SQLType, String> {
+    let mut out = SQLType::Null;
+    for arg in args {
+        out = match out.common(*arg) {
+            Some(k) => k,
+            None => return Err(format!("!COALESCE arguments {} and {} do not match", out, arg)),
+        };
+    }
+    Ok(out)
+}
+
+fn check_nullif(args: &[SQLType]) -> Result<SQLType, String> {
+    match args[0].common(args[1]) {
+        Some(_) => Ok(args[0]),
+        None => Err(format!("!Cannot compare {} with {}", args[0], args[1])),
+    }
+}
+
+fn check_timestamptz(_: &[SQLType]) -> Result<SQLType, String> {
+    Ok(SQLType::TimestampTz)
+}
+
+fn check_date(_: &[SQLType]) -> Result<SQLType, String> {
+    Ok(SQLType::Date)
+}
+
+fn check_uuid(_: &[SQLType]) -> Result<SQLType, String> {
+    Ok(SQLType::Uuid)
+}
+
+fn check_time(_: &[SQLType]) -> Result<SQLType, String> {
+    Ok(SQLType::Time)
+}
+
+fn check_date_part(args: &[SQLType]) -> Result<SQLType, String> {
+    expect_text(args[0])?;
+    if datetime::is_datetime(args[1]) || args[1] == SQLType::Null {
+        Ok(SQLType::Float)
+    } else {
+        Err(format!("!Expected a date, time or interval but got {}", args[1]))
+    }
+}
+
+fn check_json_extract(args: &[SQLType]) -> Result<SQLType, String> {
+    if args[0] != SQLType::Json {
+        expect_text(args[0])?;
+    }
+    expect_text(args[1])?;
+    Ok(SQLType::Json)
+}
+
+fn text(arg: &SQLValue) -> &str {
+    match arg {
+        SQLValue::Char(val) => val.as_str(),
+        _ => "",
+    }
+}
+
+fn int(arg: &SQLValue) -> i64 {
+    match arg {
+        SQLValue::Int(val) => *val,
+        _ => 0,
+    }
+}
+
+fn upper(args: &[SQLValue]) -> Result<SQLValue, String> {
+    Ok(SQLValue::Char(text(&args[0]).to_uppercase()))
+}
+
+fn lower(args: &[SQLValue]) -> Result<SQLValue, String> {
+    Ok(SQLValue::Char(text(&args[0]).to_lowercase()))
+}
+
+fn length(args: &[SQLValue]) -> Result<SQLValue, String> {
+    if let SQLValue::Blob(bytes) = &args[0] {
+        return Ok(SQLValue::Int(bytes.len() as i64));
+    }
+    Ok(SQLValue::Int(text(&args[0]).chars().count() as i64))
+}
+
+/// SUBSTR(text, start, len) with a 1 based start like every other SQL
+fn substr(args: &[SQLValue]) -> Result<SQLValue, String> {
+    let start = int(&args[1]);
+    let mut end = i64::MAX;
+    if let Some(len) = args.get(2) {
+        let len = int(len);
+        if len < 0 {
+            return Err(String::from("!SUBSTR length cannot be negative"));
+        }
+        end = start.saturating_add(len);
+    }
+    let out = text(&args[0])
+        .chars()
+        .enumerate()
+        .filter(|(i, _)| (*i as i64 + 1) >= start && (*i as i64 + 1) < end)
+        .map(|(_, c)| c)
+        .collect();
+    Ok(SQLValue::Char(out))
+}
+
+fn trim(args: &[SQLValue]) -> Result<SQLValue, String> {
+    Ok(SQLValue::Char(String::from(text(&args[0]).trim())))
+}
+
+fn replace(args: &[SQLValue]) -> Result<SQLValue, String> {
+    let from = text(&args[1]);
+    if from.is_empty() {
+        Ok(args[0].clone())
+    } else {
+        Ok(SQLValue::Char(text(&args[0]).replace(from, text(&args[2]))))
+    }
+}
+
+fn abs(args: &[SQLValue]) -> Result<SQLValue, String> {
+    match &args[0] {
+        SQLValue::Int(val) => match val.checked_abs() {
+            Some(k) => Ok(SQLValue::Int(k)),
+            None => Err(String::from("!Integer out of range")),
+        },
+        SQLValue::Float(val) => Ok(SQLValue::Float(val.abs())),
+        SQLValue::Decimal(val) => match val.checked_abs() {
+            Some(k) => Ok(SQLValue::Decimal(k)),
+            None => Err(String::from("!Numeric value out of range")),
+        },
+        val => Err(format!("!Invalid operand {}", val)),
+    }
+}
+
+/// ROUND(x, digits) rounds half away from zero, negative digits round to the left of the point
+fn round(args: &[SQLValue]) -> Result<SQLValue, String> {
+    // 10^19 is past every i64 so rounding to more digits than that changes nothing
+    let digits = args.get(1).map(int).unwrap_or(0).clamp(-19, 18) as i32;
+    match &args[0] {
+        SQLValue::Int(val) if digits >= 0 => Ok(SQLValue::Int(*val)),
+        SQLValue::Int(val) => {
+            let val = *val as i128;
+            let scale = 10_i128.pow(digits.unsigned_abs());
+            let mut rounded = val / scale;
+            if (val % scale).abs() * 2 >= scale {
+                rounded += val.signum();
+            }
+            match i64::try_from(rounded * scale) {
+                Ok(k) => Ok(SQLValue::Int(k)),
+                Err(_) => Err(String::from("!Integer out of range")),
+            }
+        },
+        SQLValue::Float(val) => {
+            let scale = 10_f64.powi(digits);
+            Ok(SQLValue::Float((val * scale).round() / scale))
+        },
+        SQLValue::Decimal(val) => match val.round(digits) {
+            Some(k) => Ok(SQLValue::Decimal(k)),
+            None => Err(String::from("!Numeric value out of range")),
+        },
+        val => Err(format!("!Invalid operand {}", val)),
+    }
+}
+
+fn floor(args: &[SQLValue]) -> Result<SQLValue, String> {
+    match &args[0] {
+        SQLValue::Float(val) => Ok(SQLValue::Float(val.floor())),
+        SQLValue::Decimal(val) => Ok(val.whole(false).map_or(SQLValue::Null, SQLValue::Decimal)),
+        val => Ok(val.clone()),
+    }
+}
+
+fn ceil(args: &[SQLValue]) -> Result<SQLValue, String> {
+    match &args[0] {
+        SQLValue::Float(val) => Ok(SQLValue::Float(val.ceil())),
+        SQLValue::Decimal(val) => Ok(val.whole(true).map_or(SQLValue::Null, SQLValue::Decimal)),
+        val => Ok(val.clone()),
+    }
+}
+
+fn coalesce(args: &[SQLValue]) -> Result<SQLValue, String> {
+    Ok(args.iter().find(|arg| !arg.is_null()).cloned().unwrap_or(SQLValue::Null))
+}
+
+fn nullif(args: &[SQLValue]) -> Result<SQLValue, String> {
+    if !args[0].is_null() && !args[1].is_null() && args[0].compare(&args[1])?.is_eq() {
+        Ok(SQLValue::Null)
+    } else {
+        Ok(args[0].clone())
+    }
+}
+
+/// Dates and times are in UTC
+fn now(_: &[SQLValue]) -> Result<SQLValue, String> {
+    Ok(SQLValue::TimestampTz(datetime::now()))
+}
+
+fn gen_random_uuid(_: &[SQLValue]) -> Result<SQLValue, String> {
+    Ok(SQLValue::Uuid(Uuid::random()))
+}
+
+fn current_date(_: &[SQLValue]) -> Result<SQLValue, String> {
+    Ok(SQLValue::Date(datetime::timestamp_to_date(datetime::now())))
+}
+
+fn current_time(_: &[SQLValue]) -> Result<SQLValue, String> {
+    Ok(SQLValue::Time(datetime::timestamp_to_time(datetime::now())))
+}
+
+/// DATE_PART('field', val), what EXTRACT(field FROM val) turns into
+fn date_part(args: &[SQLValue]) -> Result<SQLValue, String> {
+    datetime::part(text(&args[0]), &args[1])
+}
+
+/// JSON_EXTRACT(json, '$.a.b[0]'), text is read as JSON and a missing path gives NULL
+fn json_extract(args: &[SQLValue]) -> Result<SQLValue, String> {
+    let parsed;
+    let val = match &args[0] {
+        SQLValue::Json(val) => val,
+        val => {
+            parsed = json::parse(text(val))?;
+            &parsed
+        },
+    };
+    Ok(json::extract(val, text(&args[1]))?.map_or(SQLValue::Null, |val| SQLValue::Json(val.clone())))
+}
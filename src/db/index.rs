@@ -0,0 +1,220 @@
+use pest::Parser;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use super::decimal;
+use super::expr::{BinaryOp, Field, SQLExpr, SQLType, SQLValue, Scope};
+use super::json;
+use super::select::Catalog;
+use super::{DataBase, Rule, SQLParser};
+
+/// An index made by CREATE INDEX over expressions of a table's columns, such as doc->>'email'
+/// It holds the key of every row version so a WHERE that compares each expression with a literal
+/// only reads the rows holding that key instead of working the expressions out for every row
+#[derive(Serialize, Deserialize)]
+pub struct Index {
+    pub name: String,
+    /// Text of each expression, parsed again when the index is used
+    exprs: Vec<String>,
+    /// Key of each row version, by position in the table
+    keys: Vec<Vec<SQLValue>>,
+    /// Positions holding each key, built from keys when the table is loaded
+    #[serde(skip)]
+    lookup: HashMap<Vec<SQLValue>, Vec<usize>>,
+}
+
+impl Index {
+    /// Checks the expressions against the columns of a table and works out the key of each of its rows
+    /// The expressions may only read the row, not tables or sequences
+    pub fn new(name: &str, exprs: Vec<String>, fields: &[Field], rows: &[&[SQLValue]]) -> Result<Self, String> {
+        let mut index = Index { name: String::from(name), exprs, keys: Vec::new(), lookup: HashMap::new() };
+        let db = DataBase::new();
+        let catalog = Catalog::new(&db);
+        let scope = Scope::new(&catalog, fields, &[], None);
+        for (expr, source) in index.parse()?.iter().zip(&index.exprs) {
+            if !expr.reads_only_row() {
+                return Err(format!("!Index expression {} can only read the columns of the row", source));
+            }
+            expr.check(&scope)?;
+        }
+        for key in index.keys(fields, rows)? {
+            index.push(key);
+        }
+        Ok(index)
+    }
+
+    fn parse(&self) -> Result<Vec<SQLExpr>, String> {
+        self.exprs.iter().map(|source| match SQLParser::parse(Rule::expr, source) {
+            Ok(mut pairs) => SQLExpr::parse(pairs.next().unwrap()),
+            Err(e) => Err(format!("!Invalid index expression {}\n{}", source, e)),
+        }).collect()
+    }
+
+    /// Key of each row, worked out before a table changes so an expression that fails changes nothing
+    /// The expressions only read the row, as new checks, so they run against an empty database
+    pub fn keys(&self, fields: &[Field], rows: &[&[SQLValue]]) -> Result<Vec<Vec<SQLValue>>, String> {
+        let exprs = self.parse()?;
+        let db = DataBase::new();
+        let catalog = Catalog::new(&db);
+        rows.iter().map(|row| {
+            let scope = Scope::new(&catalog, fields, row, None);
+            exprs.iter().map(|expr| Ok(key(expr.eval(&scope)?))).collect()
+        }).collect()
+    }
+
+    /// Adds the key of a row appended to the table
+    pub fn push(&mut self, key: Vec<SQLValue>) {
+        self.lookup.entry(key.clone()).or_default().push(self.keys.len());
+        self.keys.push(key);
+    }
+
+    /// Changes the key of the row at i
+    pub fn set(&mut self, i: usize, key: Vec<SQLValue>) {
+        if let Some(positions) = self.lookup.get_mut(&self.keys[i]) {
+            positions.retain(|j| *j != i);
+        }
+        self.lookup.entry(key.clone()).or_default().push(i);
+        self.keys[i] = key;
+    }
+
+    /// Appends a copy of the key of the row at i, for a version copied to the end of the table
+    pub fn copy(&mut self, i: usize) {
+        self.push(self.keys[i].clone());
+    }
+
+    /// Drops the keys of the row versions marked in removed, the rest move up as the rows do
+    pub fn remove(&mut self, removed: &[bool]) {
+        let mut keep = removed.iter().map(|removed| !removed);
+        self.keys.retain(|_| keep.next().unwrap());
+        self.build();
+    }
+
+    /// Fills in the positions of each key
+    pub fn build(&mut self) {
+        self.lookup.clear();
+        for (i, key) in self.keys.iter().enumerate() {
+            self.lookup.entry(key.clone()).or_default().push(i);
+        }
+    }
+
+    /// Positions of the rows that can pass filter when it compares every expression of the index
+    /// with a literal in a chain of ANDs, None when it doesn't
+    /// Columns of the expressions in filter may be qualified by alias
+    /// Rows are only candidates, the caller still checks filter against them
+    pub fn find(&self, fields: &[Field], filter: &SQLExpr, alias: &str) -> Result<Option<Vec<usize>>, String> {
+        let mut conjuncts = Vec::new();
+        equalities(filter, &mut conjuncts);
+        let db = DataBase::new();
+        let catalog = Catalog::new(&db);
+        let scope = Scope::new(&catalog, fields, &[], None);
+        // Keys that can equal the literals compared with each expression
+        let mut probes = Vec::new();
+        for expr in self.parse()? {
+            let literal = conjuncts.iter().find_map(|(other, literal)| (unqualified(other, alias) == expr).then_some(*literal));
+            match literal.and_then(|literal| candidates(expr.check(&scope).ok()?, literal)) {
+                Some(keys) => probes.push(keys),
+                None => return Ok(None),
+            }
+        }
+        let mut keys = vec![Vec::new()];
+        for candidates in probes {
+            keys = keys.iter().flat_map(|key: &Vec<SQLValue>| candidates.iter().map(move |val| {
+                let mut key = key.clone();
+                key.push(val.clone());
+                key
+            })).collect();
+        }
+        let mut positions: Vec<usize> = keys.iter().filter_map(|key| self.lookup.get(key)).flatten().copied().collect();
+        // In table order, as a scan would read them
+        positions.sort_unstable();
+        positions.dedup();
+        Ok(Some(positions))
+    }
+}
+
+/// Index keys hold equal values the same way, so numbers are exact decimals where they can be
+/// and JSON strings, numbers and booleans are their SQL counterparts
+fn key(val: SQLValue) -> SQLValue {
+    match val {
+        SQLValue::Json(Value::String(text)) => SQLValue::Char(text),
+        SQLValue::Json(Value::Number(val)) => key(json::number(&val)),
+        SQLValue::Json(Value::Bool(val)) => SQLValue::Bool(val),
+        SQLValue::Int(_) | SQLValue::Decimal(_) => SQLValue::Decimal(decimal::to_decimal(&val).unwrap()),
+        val => val,
+    }
+}
+
+/// Keys a value of type sql_type has when it equals literal, None when equality isn't decided by the key
+/// Floats compare with ints as floats and text compared with a date or uuid is read as one, so those aren't used
+/// Text compared with JSON is either a string or read as JSON, only scalars are looked up
+fn candidates(sql_type: SQLType, literal: &SQLValue) -> Option<Vec<SQLValue>> {
+    match (sql_type, literal) {
+        (_, SQLValue::Null) => Some(Vec::new()),
+        (SQLType::Json, SQLValue::Char(text)) => {
+            let mut keys = vec![literal.clone()];
+            match json::parse(text) {
+                Ok(Value::Array(_) | Value::Object(_)) => return None,
+                Ok(Value::Null) => keys.push(SQLValue::Json(Value::Null)),
+                Ok(parsed) => keys.push(key(SQLValue::Json(parsed))),
+                Err(_) => (),
+            }
+            Some(keys)
+        },
+        (SQLType::Json | SQLType::Bool, SQLValue::Bool(_)) => Some(vec![literal.clone()]),
+        (SQLType::Json | SQLType::Decimal(..), SQLValue::Int(_) | SQLValue::Decimal(_)) => Some(vec![key(literal.clone())]),
+        (sql_type, SQLValue::Int(_) | SQLValue::Decimal(_)) if sql_type.is_integer() => Some(vec![key(literal.clone())]),
+        (sql_type, SQLValue::Char(_)) if sql_type.is_text() && sql_type != SQLType::Null => Some(vec![literal.clone()]),
+        _ => None,
+    }
+}
+
+/// Collects the expr = literal comparisons of a chain of ANDs, with the literal on either side
+fn equalities<'a>(filter: &'a SQLExpr, out: &mut Vec<(&'a SQLExpr, &'a SQLValue)>) {
+    match filter {
+        SQLExpr::Binary(lhs, BinaryOp::And, rhs) => {
+            equalities(lhs, out);
+            equalities(rhs, out);
+        },
+        SQLExpr::Binary(lhs, BinaryOp::Eq, rhs) => match (lhs.as_ref(), rhs.as_ref()) {
+            (expr, SQLExpr::Literal(literal)) | (SQLExpr::Literal(literal), expr) => out.push((expr, literal)),
+            _ => (),
+        },
+        _ => (),
+    }
+}
+
+/// expr with the columns qualified by alias written without it, as index expressions name them
+fn unqualified(expr: &SQLExpr, alias: &str) -> SQLExpr {
+    let mut expr = expr.clone();
+    let mut columns = Vec::new();
+    expr.columns_mut(&mut columns);
+    for (table, _) in columns {
+        if table.as_deref() == Some(alias) {
+            *table = None;
+        }
+    }
+    expr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> SQLExpr {
+        SQLExpr::parse(SQLParser::parse(Rule::expr, text).unwrap().next().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn filters_are_matched_by_their_expressions() {
+        let fields = [Field { table: None, name: String::from("n"), sql_type: SQLType::Int }];
+        let rows = [[SQLValue::Int(1)], [SQLValue::Int(2)], [SQLValue::Int(1)]];
+        let rows: Vec<&[SQLValue]> = rows.iter().map(|row| row.as_slice()).collect();
+        let index = Index::new("i", vec![String::from("n + 1")], &fields, &rows).unwrap();
+        assert_eq!(index.find(&fields, &parse("t.n + 1 = 2"), "t"), Ok(Some(vec![0, 2])));
+        assert_eq!(index.find(&fields, &parse("3 = (n + 1) AND n > 0"), "t"), Ok(Some(vec![1])));
+        assert_eq!(index.find(&fields, &parse("n + 2 = 2"), "t"), Ok(None));
+        assert_eq!(index.find(&fields, &parse("u.n + 1 = 2"), "t"), Ok(None));
+        let error = Index::new("j", vec![String::from("n + (SELECT 1)")], &fields, &rows).err().unwrap();
+        assert_eq!(error, "!Index expression n + (SELECT 1) can only read the columns of the row");
+    }
+}
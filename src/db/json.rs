@@ -0,0 +1,120 @@
+use serde_json::{Number, Value};
+use std::cmp::Ordering;
+use super::decimal::Decimal;
+use super::expr::SQLValue;
+
+/// Reads text as JSON, the error says where parsing failed
+pub fn parse(text: &str) -> Result<Value, String> {
+    serde_json::from_str(text).map_err(|e| format!("!Invalid JSON '{}': {}", text, e))
+}
+
+/// json -> key for objects and json -> n for arrays, negative n counts from the end
+pub fn get<'a>(json: &'a Value, key: &SQLValue) -> Option<&'a Value> {
+    match (json, key) {
+        (Value::Object(map), SQLValue::Char(key)) => map.get(key),
+        (Value::Array(items), SQLValue::Int(i)) => {
+            let i = if *i < 0 { items.len() as i64 + i } else { *i };
+            usize::try_from(i).ok().and_then(|i| items.get(i))
+        },
+        _ => None,
+    }
+}
+
+/// Follows a path like $.a.b[0] or $."a key"[1], None when part of it is missing
+pub fn extract<'a>(json: &'a Value, path: &str) -> Result<Option<&'a Value>, String> {
+    let invalid = || format!("!Invalid JSON path '{}'", path);
+    let mut rest = path.trim().strip_prefix('$').ok_or_else(invalid)?;
+    let mut out = Some(json);
+    while !rest.is_empty() {
+        let key = if let Some(after) = rest.strip_prefix('.') {
+            let (key, after) = match after.strip_prefix('"') {
+                Some(quoted) => {
+                    let end = quoted.find('"').ok_or_else(invalid)?;
+                    (&quoted[..end], &quoted[end + 1..])
+                },
+                None => after.split_at(after.find(['.', '[']).unwrap_or(after.len())),
+            };
+            if key.is_empty() {
+                return Err(invalid());
+            }
+            rest = after;
+            SQLValue::Char(String::from(key))
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(invalid)?;
+            let index = after[..end].trim();
+            rest = &after[end + 1..];
+            match index.strip_prefix('"').and_then(|index| index.strip_suffix('"')) {
+                Some(key) => SQLValue::Char(String::from(key)),
+                None => SQLValue::Int(index.parse().map_err(|_| invalid())?),
+            }
+        } else {
+            return Err(invalid());
+        };
+        out = out.and_then(|json| get(json, &key));
+    }
+    Ok(out)
+}
+
+/// What json ->> key returns, strings without their quotes, other values as JSON text and null as NULL
+pub fn to_text(json: &Value) -> SQLValue {
+    match json {
+        Value::Null => SQLValue::Null,
+        Value::String(text) => SQLValue::Char(text.clone()),
+        json => SQLValue::Char(json.to_string()),
+    }
+}
+
+/// A JSON number as an SQL number, exact when it can be
+pub fn number(val: &Number) -> SQLValue {
+    match (val.as_i64(), Decimal::parse(&val.to_string())) {
+        (Some(val), _) => SQLValue::Int(val),
+        (None, Some(val)) => SQLValue::Decimal(val),
+        (None, None) => SQLValue::Float(val.as_f64().unwrap_or(f64::NAN)),
+    }
+}
+
+/// Where values of each kind sort against other kinds
+fn rank(json: &Value) -> u8 {
+    match json {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
+
+/// Orders values of different kinds null, false, true, numbers, strings, arrays then objects
+/// Arrays and objects compare item by item
+pub fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => number(a).sort_cmp(&number(b)),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => {
+            a.iter().zip(b).map(|(a, b)| compare(a, b)).find(|ordering| ordering.is_ne()).unwrap_or(a.len().cmp(&b.len()))
+        },
+        (Value::Object(a), Value::Object(b)) => {
+            // Keys are kept sorted so objects with the same keys line up
+            a.iter().zip(b).map(|((ka, va), (kb, vb))| ka.cmp(kb).then_with(|| compare(va, vb)))
+                .find(|ordering| ordering.is_ne()).unwrap_or(a.len().cmp(&b.len()))
+        },
+        (a, b) => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Orders JSON against a value of another type
+/// A string compares with text by its content and other JSON reads the text as JSON, or as a string when it isn't JSON,
+/// numbers and booleans compare with SQL numbers and booleans and other kinds sort as compare orders them
+pub fn compare_sql(a: &Value, b: &SQLValue) -> Result<Ordering, String> {
+    match (a, b) {
+        (Value::String(a), SQLValue::Char(b)) => Ok(a.as_str().cmp(b.as_str())),
+        (a, SQLValue::Char(b)) => Ok(compare(a, &parse(b).unwrap_or_else(|_| Value::String(b.clone())))),
+        (Value::Number(a), b) if b.as_float().is_some() => number(a).compare(b),
+        (Value::Bool(a), SQLValue::Bool(b)) => Ok(a.cmp(b)),
+        (a, SQLValue::Bool(_)) => Ok(rank(a).cmp(&1)),
+        (a, b) if b.as_float().is_some() => Ok(rank(a).cmp(&2)),
+        (_, b) => Err(format!("!Cannot compare json with {}", b.sql_type())),
+    }
+}
@@ -0,0 +1,88 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use super::expr::{Field, SQLValue};
+
+/// A PRIMARY KEY or UNIQUE constraint over some columns of a table
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Key {
+    pub columns: Vec<usize>,
+    pub primary: bool,
+}
+
+impl Key {
+    /// Values of the key columns in row, None when one of them is NULL since NULLs never conflict
+    fn values(&self, row: &[SQLValue]) -> Option<Vec<SQLValue>> {
+        let values: Vec<SQLValue> = self.columns.iter().map(|i| row[*i].clone()).collect();
+        if values.iter().any(|val| val.is_null()) {
+            None
+        } else {
+            Some(values)
+        }
+    }
+
+    /// e.g. primary key (id)
+    pub fn describe(&self, fields: &[Field]) -> String {
+        let names: Vec<&str> = self.columns.iter().map(|i| fields[*i].name.as_str()).collect();
+        if self.primary {
+            format!("primary key ({})", names.join(", "))
+        } else {
+            format!("unique key ({})", names.join(", "))
+        }
+    }
+}
+
+/// The key values of every row of a table while a statement works out its changes
+/// INSERT and UPDATE use it to find duplicates before anything is written
+pub struct KeyIndex<'a> {
+    keys: &'a [Key],
+    fields: &'a [Field],
+    /// For each key the row holding each value
+    rows: Vec<HashMap<Vec<SQLValue>, usize>>,
+}
+
+impl<'a> KeyIndex<'a> {
+    /// rows are (position, row) so rows that aren't current versions can be left out
+    pub fn new(keys: &'a [Key], fields: &'a [Field], rows: impl Iterator<Item = (usize, Vec<SQLValue>)>) -> Result<Self, String> {
+        let mut index = Self { keys, fields, rows: vec![HashMap::new(); keys.len()] };
+        for (i, row) in rows {
+            index.insert(i, &row)?;
+        }
+        Ok(index)
+    }
+
+    /// The first key row has the same value for as (key, row holding it)
+    pub fn conflict(&self, row: &[SQLValue]) -> Option<(usize, usize)> {
+        self.keys.iter().enumerate().find_map(|(k, key)| {
+            let values = key.values(row)?;
+            self.rows[k].get(&values).map(|i| (k, *i))
+        })
+    }
+
+    /// Records row as row i, failing if it repeats a key or has NULL in the primary key
+    pub fn insert(&mut self, i: usize, row: &[SQLValue]) -> Result<(), String> {
+        for key in self.keys.iter().filter(|key| key.primary) {
+            if let Some(column) = key.columns.iter().find(|column| row[**column].is_null()) {
+                return Err(format!("!Column {} cannot be NULL as it is part of the primary key", self.fields[*column].name));
+            }
+        }
+        if let Some((k, _)) = self.conflict(row) {
+            let values: Vec<String> = self.keys[k].columns.iter().map(|i| row[*i].to_string()).collect();
+            return Err(format!("!Duplicate value ({}) for {}", values.join(", "), self.keys[k].describe(self.fields)));
+        }
+        for (k, key) in self.keys.iter().enumerate() {
+            if let Some(values) = key.values(row) {
+                self.rows[k].insert(values, i);
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets the key values of row
+    pub fn remove(&mut self, row: &[SQLValue]) {
+        for (k, key) in self.keys.iter().enumerate() {
+            if let Some(values) = key.values(row) {
+                self.rows[k].remove(&values);
+            }
+        }
+    }
+}
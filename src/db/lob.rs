@@ -0,0 +1,170 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Values of TEXT and BLOB columns longer than this many bytes are saved in an overflow file instead of the dump
+const INLINE_LIMIT: usize = 1024;
+
+/// A TEXT or BLOB value
+/// Large values are written once to a file of their own in the overflow directory next to the dump,
+/// which then only holds the file name
+#[derive(Debug, Clone)]
+pub struct Lob {
+    data: Vec<u8>,
+    /// Overflow file holding data, None while it is kept inline
+    file: Option<String>,
+}
+
+/// How a Lob appears in the dump, text that isn't UTF-8 is written as hex
+#[derive(Serialize, Deserialize)]
+enum Stored<'a> {
+    Text(Cow<'a, str>),
+    Hex(Cow<'a, str>),
+    File(Cow<'a, str>),
+}
+
+impl Lob {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data, file: None }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.data).into_owned()
+    }
+
+    /// Moves a large value to its overflow file, named by a hash of its contents so equal values share one
+    /// A file with the same name but other contents is left alone and the next free name is taken instead
+    /// The file name is added to used whether it was just written or already there
+    pub fn spill(&mut self, dir: &Path, used: &mut HashSet<String>) -> Result<(), String> {
+        if self.file.is_none() && self.data.len() > INLINE_LIMIT {
+            let name = format!("{:016x}-{}", fnv1a(&self.data), self.data.len());
+            for n in 0.. {
+                let file = match n {
+                    0 => name.clone(),
+                    n => format!("{}-{}", name, n),
+                };
+                let path = dir.join(&file);
+                let write_error = |e: std::io::Error| format!("!Could not write overflow file {}: {}", path.display(), e);
+                match std::fs::read(&path) {
+                    Ok(data) if data == self.data => (),
+                    Ok(_) => continue,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&path, &self.data)).map_err(write_error)?;
+                    },
+                    Err(e) => return Err(write_error(e)),
+                }
+                self.file = Some(file);
+                break;
+            }
+        }
+        if let Some(file) = &self.file {
+            used.insert(file.clone());
+        }
+        Ok(())
+    }
+
+    /// Reads the value back from its overflow file after the dump was loaded
+    /// Has to be called under the lock the dump was read under, a save by another instance may remove the file after
+    pub fn fill(&mut self, dir: &Path) -> Result<(), String> {
+        if let Some(file) = &self.file {
+            let path = dir.join(file);
+            self.data = std::fs::read(&path).map_err(|e| format!("!Could not read overflow file {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes overflow files no value refers to anymore
+pub fn sweep(dir: &Path, used: &HashSet<String>) {
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if !used.contains(entry.file_name().to_string_lossy().as_ref()) {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+/// 64 bit FNV-1a hash, which unlike the standard library's hasher stays the same across Rust releases
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Bytes of a string of hex digits, None if it has an odd length or other characters
+pub fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+impl Serialize for Lob {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let stored = match (&self.file, std::str::from_utf8(&self.data)) {
+            (Some(file), _) => Stored::File(Cow::Borrowed(file)),
+            (None, Ok(text)) => Stored::Text(Cow::Borrowed(text)),
+            (None, Err(_)) => Stored::Hex(Cow::Owned(to_hex(&self.data))),
+        };
+        stored.serialize(serializer)
+    }
+}
+
+/// Values in overflow files are empty until filled
+impl<'de> Deserialize<'de> for Lob {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Text(text) => Lob::new(text.into_owned().into_bytes()),
+            Stored::Hex(hex) => Lob::new(from_hex(&hex).ok_or_else(|| serde::de::Error::custom("invalid hex"))?),
+            Stored::File(file) => Lob { data: Vec::new(), file: Some(file.into_owned()) },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spilled_values_share_files_only_when_equal() {
+        let dir = std::env::temp_dir().join(format!("rust_db_lob_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let data = vec![b'a'; INLINE_LIMIT + 1];
+        let name = format!("{:016x}-{}", fnv1a(&data), data.len());
+        // Another value already holds the name the hash gives
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(&name), vec![b'b'; INLINE_LIMIT + 1]).unwrap();
+        let mut used = HashSet::new();
+        let mut lob = Lob::new(data.clone());
+        lob.spill(&dir, &mut used).unwrap();
+        assert_eq!(lob.file, Some(format!("{}-1", name)));
+        let mut same = Lob::new(data.clone());
+        same.spill(&dir, &mut used).unwrap();
+        assert_eq!(same.file, lob.file);
+        let mut read = Lob { data: Vec::new(), file: lob.file.clone() };
+        read.fill(&dir).unwrap();
+        assert_eq!(read.bytes(), data.as_slice());
+        // Small values stay inline
+        let mut small = Lob::new(vec![b'c'; 10]);
+        small.spill(&dir, &mut used).unwrap();
+        assert_eq!(small.file, None);
+        assert_eq!(used.len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(to_hex(&[0x00, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00AB10"), Some(vec![0x00, 0xab, 0x10]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
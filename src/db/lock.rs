@@ -0,0 +1,513 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use super::datetime;
+
+/// How long a statement waits for a lock held by another process unless told otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time between attempts while waiting for a lock
+const RETRY: Duration = Duration::from_millis(50);
+
+/// Shared locks can be held by any number of processes at once, an exclusive lock by one process alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Mode {
+    Shared,
+    Exclusive,
+}
+
+/// What a lock covers
+/// Every statement locks the database before any of its tables,
+/// so an exclusive database lock keeps out everyone using any of its tables
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Resource {
+    Database(String),
+    /// Database and table
+    Table(String, String),
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resource::Database(name) => write!(f, "Database {}", name),
+            Resource::Table(_, name) => write!(f, "Table {}", name),
+        }
+    }
+}
+
+/// What acquire had to do to hold a lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Acquired {
+    /// It was not held before, or was let go while waiting to make it exclusive
+    New,
+    /// It was held shared and is now exclusive
+    Upgraded,
+    /// It was already held in a strong enough mode
+    Held,
+}
+
+/// The process holding a lock exclusively, written into the lock file so others can tell who it is
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Holder {
+    pid: u32,
+    host: String,
+    /// Microseconds since 1970-01-01 UTC when the process started
+    started: i64,
+    /// Start time of the process in clock ticks since boot, as the OS reports it
+    /// A live process with the same pid and different ticks is another process that reused the pid
+    ticks: Option<u64>,
+}
+
+impl Holder {
+    fn current(started: i64) -> Self {
+        Holder { pid: std::process::id(), host: hostname(), started, ticks: ticks(std::process::id()) }
+    }
+
+    /// Reads the holder written in a lock file, None if nobody holds it exclusively
+    fn read(path: &Path) -> Option<Self> {
+        serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+    }
+
+    /// Whether this is a process on this host that has ended
+    /// Processes on other hosts, or written where the start time couldn't be read, can't be checked and are taken to be alive
+    fn is_dead(&self) -> bool {
+        self.host == hostname() && self.ticks.is_some() && ticks(self.pid) != self.ticks
+    }
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Shown to the second
+        write!(f, "process {} on {} started {}", self.pid, self.host, datetime::format_timestamp(self.started.div_euclid(1_000_000) * 1_000_000))
+    }
+}
+
+fn hostname() -> String {
+    match std::fs::read_to_string("/proc/sys/kernel/hostname").or_else(|_| std::env::var("HOSTNAME")) {
+        Ok(host) if !host.trim().is_empty() => String::from(host.trim()),
+        _ => String::from("localhost"),
+    }
+}
+
+/// Start time of a running process from /proc, None if it isn't running or there is no /proc to read it from
+fn ticks(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name is in parentheses and can hold spaces, start time is the 22nd field
+    stat[stat.rfind(')')? + 1..].split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Whether path still names the file that was opened, breaking a lock removes its file
+#[cfg(unix)]
+fn same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), std::fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(_: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+/// Part of a lock file name, characters other than letters, digits and _ are written as %XX
+fn escape(name: &str) -> String {
+    name.bytes().map(|byte| match byte {
+        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' => String::from(byte as char),
+        byte => format!("%{:02X}", byte),
+    }).collect()
+}
+
+/// Opens a lock file, making it and its directory if needed
+fn open(path: &Path) -> Result<File, String> {
+    std::fs::create_dir_all(path.parent().unwrap()).map_err(|e| format!("!Could not create lock directory: {}", e))?;
+    OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+        .map_err(|e| format!("!Could not open lock file {}: {}", path.display(), e))
+}
+
+fn try_lock(file: &File, mode: Mode) -> Result<bool, String> {
+    let result = match mode {
+        Mode::Shared => file.try_lock_shared(),
+        Mode::Exclusive => file.try_lock(),
+    };
+    match result {
+        Ok(()) => Ok(true),
+        Err(TryLockError::WouldBlock) => Ok(false),
+        Err(TryLockError::Error(e)) => Err(format!("!Could not lock: {}", e)),
+    }
+}
+
+/// Locks one process holds on the databases and tables of a dump
+/// Each resource has a file in the lock directory next to the dump and is locked with the OS advisory locks on it,
+/// which are dropped by the OS when the process ends however it ends
+/// The exclusive holder of a lock writes itself into the file, which is emptied again when the lock is let go
+#[derive(Debug, Default)]
+pub struct LockManager {
+    /// None when the DBMS isn't saved anywhere and there is no one to share it with
+    dir: Option<PathBuf>,
+    held: HashMap<Resource, (File, Mode)>,
+    /// How long to wait for a lock another process holds
+    timeout: Duration,
+    /// When this process started, written into the locks it holds
+    started: i64,
+    /// File registering the snapshot of the open transaction, locked for as long as the transaction runs
+    snapshot: Option<File>,
+}
+
+impl LockManager {
+    /// Lock files go in {path}.locks
+    pub fn new(path: &str) -> Self {
+        Self {
+            dir: Some(PathBuf::from(format!("{}.locks", path))),
+            held: HashMap::new(),
+            timeout: DEFAULT_TIMEOUT,
+            started: datetime::now(),
+            snapshot: None,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn file(&self, resource: &Resource) -> Option<PathBuf> {
+        let name = match resource {
+            Resource::Database(db) => format!("db.{}", escape(db)),
+            Resource::Table(db, table) => format!("table.{}.{}", escape(db), escape(table)),
+        };
+        self.dir.as_ref().map(|dir| dir.join(name))
+    }
+
+    fn snapshot_file(&self) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(format!("snapshot.{}", std::process::id())))
+    }
+
+    /// Lets other processes know a transaction reads as of commit, so they keep the row versions it can see
+    /// A transaction that registered already moves its snapshot up to commit
+    /// The file is waited for until the timeout if another manager in this process holds it
+    pub fn register(&mut self, commit: u64) -> Result<(), String> {
+        let path = match self.snapshot_file() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut file = match self.snapshot.take() {
+            Some(file) => file,
+            None => {
+                let file = open(&path)?;
+                let start = Instant::now();
+                while !try_lock(&file, Mode::Exclusive)? {
+                    if start.elapsed() >= self.timeout {
+                        return Err(format!("Error: Snapshot file {} is locked!", path.display()));
+                    }
+                    std::thread::sleep(RETRY);
+                }
+                file
+            },
+        };
+        let _ = file.set_len(0).and_then(|_| file.seek(SeekFrom::Start(0))).and_then(|_| file.write_all(commit.to_string().as_bytes()));
+        self.snapshot = Some(file);
+        Ok(())
+    }
+
+    /// Ends what register started
+    pub fn unregister(&mut self) {
+        if self.snapshot.take().is_some() {
+            if let Some(path) = self.snapshot_file() {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Oldest snapshot other processes' transactions read as of, or commit if none is older
+    /// Files left by processes that ended without unregistering are removed
+    pub fn horizon(&self, commit: u64) -> u64 {
+        let entries = match self.dir.as_ref().and_then(|dir| std::fs::read_dir(dir).ok()) {
+            Some(entries) => entries,
+            None => return commit,
+        };
+        let own = format!("snapshot.{}", std::process::id());
+        let mut horizon = commit;
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let name = path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+            if !name.starts_with("snapshot.") || name == own {
+                continue;
+            }
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            if file.try_lock_shared().is_ok() {
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            if let Ok(Ok(snapshot)) = std::fs::read_to_string(&path).map(|text| text.trim().parse::<u64>()) {
+                horizon = horizon.min(snapshot);
+            }
+        }
+        horizon
+    }
+
+    /// Takes a lock or makes a shared one exclusive
+    /// A lock another process holds is waited for until the timeout, unless its holder has died in which case it is broken
+    pub fn acquire(&mut self, resource: &Resource, mode: Mode) -> Result<Acquired, String> {
+        let path = match self.file(resource) {
+            Some(path) => path,
+            None => return Ok(Acquired::Held),
+        };
+        if self.mode(resource) >= Some(mode) {
+            return Ok(Acquired::Held);
+        }
+        let (mut file, mut acquired) = match self.held.remove(resource) {
+            Some((file, _)) => (file, Acquired::Upgraded),
+            None => (open(&path)?, Acquired::New),
+        };
+        let upgrading = acquired == Acquired::Upgraded;
+        let start = Instant::now();
+        while !try_lock(&file, mode)? {
+            // Failing to make a lock exclusive can let go of the shared lock as well,
+            // so whatever it covered has to be read again once it is taken
+            acquired = Acquired::New;
+            match Holder::read(&path) {
+                // The OS lets go of the locks of a process when it ends, so a dead holder only left its record behind
+                // and whoever holds the file now may not have written itself in yet
+                // The file is only taken over once nobody holds it, rather than removed under live holders
+                Some(holder) if holder.is_dead() && try_lock(&file, Mode::Exclusive)? => {
+                    if mode == Mode::Exclusive {
+                        break;
+                    }
+                    file.unlock().map_err(|e| format!("!Could not unlock: {}", e))?;
+                    continue;
+                },
+                holder if start.elapsed() >= self.timeout => {
+                    // Keep the shared lock if it can be had back
+                    if upgrading && try_lock(&file, Mode::Shared)? {
+                        self.held.insert(resource.clone(), (file, Mode::Shared));
+                    }
+                    return Err(match holder.filter(|holder| !holder.is_dead()) {
+                        Some(holder) => format!("Error: {} is locked by {}!", resource, holder),
+                        None => format!("Error: {} is locked!", resource),
+                    });
+                },
+                _ => std::thread::sleep(RETRY),
+            }
+        }
+        // Whoever wrote the file let go of it without emptying it if this process could take it
+        let written = match mode {
+            Mode::Exclusive => serde_json::to_string(&Holder::current(self.started)).unwrap(),
+            Mode::Shared => String::new(),
+        };
+        if mode == Mode::Exclusive || file.metadata().is_ok_and(|meta| meta.len() > 0) {
+            let _ = file.set_len(0).and_then(|_| file.seek(SeekFrom::Start(0))).and_then(|_| file.write_all(written.as_bytes()));
+        }
+        self.held.insert(resource.clone(), (file, mode));
+        Ok(acquired)
+    }
+
+    pub fn mode(&self, resource: &Resource) -> Option<Mode> {
+        self.held.get(resource).map(|(_, mode)| *mode)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    /// Whether any lock on the database or one of its tables is held
+    pub fn holds(&self, db: &str) -> bool {
+        self.held.keys().any(|resource| match resource {
+            Resource::Database(name) | Resource::Table(name, _) => name == db,
+        })
+    }
+
+    /// Resources held exclusively, the only ones this process may write back to the dump
+    pub fn exclusive(&self) -> Vec<Resource> {
+        self.held.iter().filter(|(_, (_, mode))| *mode == Mode::Exclusive).map(|(resource, _)| resource.clone()).collect()
+    }
+
+    /// Locks that were broken while this process held them, another process may have taken them since
+    pub fn broken(&self) -> Vec<Resource> {
+        self.held.iter().filter(|(resource, (file, _))| !self.file(resource).is_some_and(|path| same_file(file, &path)))
+            .map(|(resource, _)| resource.clone()).collect()
+    }
+
+    /// Drops every lock, closing a lock file releases its lock
+    pub fn release(&mut self) {
+        for (_, (file, mode)) in self.held.drain() {
+            if mode == Mode::Exclusive {
+                let _ = file.set_len(0);
+            }
+        }
+    }
+}
+
+/// Lock on the dump itself, held while it is read or written so no one reads half a file
+/// Waits for the lock since writing a dump doesn't take long
+pub fn storage(path: &str, mode: Mode) -> Result<File, String> {
+    let path = format!("{}.lock", path);
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)
+        .map_err(|e| format!("!Could not open lock file {}: {}", path, e))?;
+    match mode {
+        Mode::Shared => file.lock_shared(),
+        Mode::Exclusive => file.lock(),
+    }.map_err(|e| format!("!Could not lock {}: {}", path, e))?;
+    Ok(file)
+}
+
+/// A lock file in use, found by locks
+pub struct Lock {
+    pub path: PathBuf,
+    /// Who holds it, None when it is only held shared
+    pub holder: Option<Holder>,
+}
+
+impl fmt::Display for Lock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        match &self.holder {
+            Some(holder) => write!(f, "{} held by {}", name, holder),
+            None => write!(f, "{} held shared", name),
+        }
+    }
+}
+
+/// Lock files of a dump that some process holds, all of them or only those of one database
+pub fn locks(path: &str, database: Option<&str>) -> Vec<Lock> {
+    let prefixes = database.map(|db| [format!("db.{}", escape(db)), format!("table.{}.", escape(db))]);
+    let mut paths = vec![PathBuf::from(format!("{}.lock", path))];
+    if let Ok(entries) = std::fs::read_dir(format!("{}.locks", path)) {
+        paths.extend(entries.flatten().map(|entry| entry.path()));
+    }
+    let mut found = Vec::new();
+    for path in paths {
+        let name = path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        // Snapshots are not locks anyone waits on
+        if name.starts_with("snapshot.") {
+            continue;
+        }
+        match &prefixes {
+            Some([db, tables]) if name != *db && !name.starts_with(tables.as_str()) => continue,
+            _ => (),
+        }
+        // A lock that can be taken exclusively is held by nobody
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+        if file.try_lock().is_ok() {
+            continue;
+        }
+        found.push(Lock { holder: Holder::read(&path), path });
+    }
+    found
+}
+
+/// Breaks locks by removing their files, whoever held them finds out when it next saves
+pub fn break_locks(locks: &[Lock]) {
+    for lock in locks {
+        let _ = std::fs::remove_file(&lock.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A manager on its own lock directory, locks of two managers conflict as locks of two processes would
+    fn managers(name: &str, timeout: Duration) -> (LockManager, LockManager) {
+        let path = std::env::temp_dir().join(format!("rust_db_lock_test_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(format!("{}.locks", path.display()));
+        let mut a = LockManager::new(path.to_str().unwrap());
+        let mut b = LockManager::new(path.to_str().unwrap());
+        a.set_timeout(timeout);
+        b.set_timeout(timeout);
+        (a, b)
+    }
+
+    #[test]
+    fn shared_locks_are_shared() {
+        let (mut a, mut b) = managers("shared", Duration::from_millis(100));
+        let db = Resource::Database(String::from("d"));
+        assert_eq!(a.acquire(&db, Mode::Shared), Ok(Acquired::New));
+        assert_eq!(b.acquire(&db, Mode::Shared), Ok(Acquired::New));
+        assert_eq!(a.acquire(&db, Mode::Shared), Ok(Acquired::Held));
+        assert!(a.holds("d"));
+        assert!(!a.holds("e"));
+    }
+
+    #[test]
+    fn exclusive_locks_time_out_and_name_their_holder() {
+        let (mut a, mut b) = managers("exclusive", Duration::from_millis(100));
+        let table = Resource::Table(String::from("d"), String::from("t"));
+        assert_eq!(a.acquire(&table, Mode::Exclusive), Ok(Acquired::New));
+        assert_eq!(a.acquire(&table, Mode::Shared), Ok(Acquired::Held));
+        let start = Instant::now();
+        let e = b.acquire(&table, Mode::Shared).unwrap_err();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(e.starts_with("Error: Table t is locked by process"), "{}", e);
+        assert!(b.is_empty());
+        a.release();
+        assert_eq!(b.acquire(&table, Mode::Shared), Ok(Acquired::New));
+    }
+
+    #[test]
+    fn upgrades_wait_for_other_readers() {
+        let (mut a, mut b) = managers("upgrade", Duration::from_millis(100));
+        let db = Resource::Database(String::from("d"));
+        a.acquire(&db, Mode::Shared).unwrap();
+        b.acquire(&db, Mode::Shared).unwrap();
+        assert!(a.acquire(&db, Mode::Exclusive).is_err());
+        // The shared lock is kept when the upgrade gives up
+        assert_eq!(a.mode(&db), Some(Mode::Shared));
+        b.release();
+        assert_eq!(a.acquire(&db, Mode::Exclusive), Ok(Acquired::Upgraded));
+        assert_eq!(a.exclusive(), vec![db.clone()]);
+        assert!(a.broken().is_empty());
+    }
+
+    #[test]
+    fn registering_waits_for_the_snapshot_file() {
+        let (mut a, mut b) = managers("register", Duration::from_millis(100));
+        a.register(3).unwrap();
+        let e = b.register(4).unwrap_err();
+        assert!(e.starts_with("Error: Snapshot file"), "{}", e);
+        a.unregister();
+        b.register(4).unwrap();
+        b.unregister();
+    }
+
+    #[test]
+    fn dead_holders_are_only_replaced_once_nobody_holds_the_file() {
+        let (mut a, mut b) = managers("dead", Duration::from_millis(100));
+        let table = Resource::Table(String::from("d"), String::from("t"));
+        let path = a.file(&table).unwrap();
+        let dead = Holder { pid: u32::MAX, host: hostname(), started: 0, ticks: Some(1) };
+        assert!(dead.is_dead());
+        a.acquire(&table, Mode::Exclusive).unwrap();
+        // a holds the file but the record names a process that has ended
+        std::fs::write(&path, serde_json::to_string(&dead).unwrap()).unwrap();
+        let e = b.acquire(&table, Mode::Shared).unwrap_err();
+        assert_eq!(e, "Error: Table t is locked!");
+        assert!(a.broken().is_empty());
+        a.release();
+        std::fs::write(&path, serde_json::to_string(&dead).unwrap()).unwrap();
+        assert_eq!(b.acquire(&table, Mode::Exclusive), Ok(Acquired::New));
+        assert_eq!(Holder::read(&path).map(|holder| holder.pid), Some(std::process::id()));
+    }
+
+    #[test]
+    fn unknown_start_times_are_alive() {
+        let holder = Holder { pid: u32::MAX, host: hostname(), started: 0, ticks: None };
+        assert!(!holder.is_dead());
+        let elsewhere = Holder { pid: u32::MAX, host: String::from("elsewhere.invalid"), started: 0, ticks: Some(1) };
+        assert!(!elsewhere.is_dead());
+    }
+
+    #[test]
+    fn lock_file_names_are_escaped() {
+        assert_eq!(escape("my_db1"), "my_db1");
+        assert_eq!(escape("a.b/c"), "a%2Eb%2Fc");
+    }
+}
@@ -0,0 +1,166 @@
+use pest::iterators::Pair;
+use serde::{Serialize, Deserialize};
+use std::fmt;
+use super::Rule;
+
+/// Commit number of versions written by this instance's open transaction or line, stamped with a real one when it is saved
+/// The dump never holds it
+pub const PENDING: u64 = u64::MAX;
+
+/// SQLSTATE of errors that roll a transaction back so it can be run again
+pub const SERIALIZATION_FAILURE: &str = "40001";
+
+/// What a statement that changes a row another transaction changed after this one's snapshot fails with
+pub const CONCURRENT_UPDATE: &str = "could not serialize access due to a concurrent update";
+
+/// Error that aborts the open transaction, reason says what got in its way
+pub fn serialization_failure(reason: &str) -> String {
+    format!("!Serialization failure (SQLSTATE {}): {}, the transaction was rolled back and can be retried", SERIALIZATION_FAILURE, reason)
+}
+
+pub fn is_serialization_failure(e: &str) -> bool {
+    e.starts_with(&format!("!Serialization failure (SQLSTATE {})", SERIALIZATION_FAILURE))
+}
+
+/// How much a transaction sees of transactions running alongside it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Isolation {
+    /// Every statement reads as of the latest commit when it starts
+    ReadCommitted,
+    /// Every statement reads as of the commit the transaction started at,
+    /// changing a row another transaction changed since is a serialization failure
+    #[default]
+    RepeatableRead,
+    /// Strict two phase locking, tables read are locked shared until COMMIT
+    /// Waiting too long for a lock or losing one while making it exclusive is a serialization failure
+    Serializable,
+}
+
+impl Isolation {
+    pub fn parse(level: Pair<Rule>) -> Self {
+        match level.as_rule() {
+            Rule::readCommitted => Isolation::ReadCommitted,
+            Rule::repeatableRead => Isolation::RepeatableRead,
+            _ => Isolation::Serializable,
+        }
+    }
+}
+
+impl fmt::Display for Isolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Isolation::ReadCommitted => write!(f, "READ COMMITTED"),
+            Isolation::RepeatableRead => write!(f, "REPEATABLE READ"),
+            Isolation::Serializable => write!(f, "SERIALIZABLE"),
+        }
+    }
+}
+
+/// Commits a statement sees, those numbered up to the snapshot or every commit when None
+pub type Snapshot = Option<u64>;
+
+/// When a version of a row was created and deleted, as commit numbers
+/// An UPDATE deletes the version it changes and creates a new one, so readers with older snapshots still see the old one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Version {
+    pub created: u64,
+    pub deleted: Option<u64>,
+}
+
+impl Version {
+    /// A version written by this instance and not saved yet
+    pub fn pending() -> Self {
+        Version { created: PENDING, deleted: None }
+    }
+
+    /// Versions of rows saved before there were versions, seen by everyone
+    pub fn committed() -> Self {
+        Version { created: 0, deleted: None }
+    }
+
+    /// Whether a statement reading with snapshot sees this version, it always sees its own changes
+    pub fn visible(&self, snapshot: Snapshot) -> bool {
+        let seen = |commit: u64| commit == PENDING || snapshot.is_none_or(|snapshot| commit <= snapshot);
+        seen(self.created) && !self.deleted.is_some_and(seen)
+    }
+
+    /// Whether this is the latest version of its row, the one keys are checked against and writers change
+    pub fn is_current(&self) -> bool {
+        self.deleted.is_none()
+    }
+
+    /// Gives pending changes the commit number they are saved with
+    pub fn stamp(&mut self, commit: u64) {
+        if self.created == PENDING {
+            self.created = commit;
+        }
+        if self.deleted == Some(PENDING) {
+            self.deleted = Some(commit);
+        }
+    }
+
+    /// Whether no snapshot from horizon on can see this version, so it can be dropped
+    /// A version created and deleted by the same commit was never seen by anyone
+    pub fn is_garbage(&self, horizon: u64) -> bool {
+        self.deleted.is_some_and(|deleted| deleted != PENDING && (deleted <= horizon || deleted == self.created))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_see_commits_up_to_theirs() {
+        let version = Version { created: 5, deleted: None };
+        assert!(version.visible(None));
+        assert!(version.visible(Some(5)));
+        assert!(!version.visible(Some(4)));
+        let deleted = Version { created: 5, deleted: Some(8) };
+        assert!(!deleted.visible(None));
+        assert!(deleted.visible(Some(7)));
+        assert!(!deleted.visible(Some(8)));
+        assert!(Version::committed().visible(Some(0)));
+    }
+
+    #[test]
+    fn own_changes_are_always_seen() {
+        assert!(Version::pending().visible(Some(0)));
+        assert!(Version::pending().visible(None));
+        // Deleted by this instance, even though an older snapshot would still see it
+        let deleted = Version { created: 3, deleted: Some(PENDING) };
+        assert!(!deleted.visible(Some(3)));
+        assert!(!deleted.is_current());
+        assert!(!Version { created: PENDING, deleted: Some(PENDING) }.visible(None));
+    }
+
+    #[test]
+    fn stamp_replaces_pending_commits() {
+        let mut version = Version { created: PENDING, deleted: Some(PENDING) };
+        version.stamp(9);
+        assert_eq!(version, Version { created: 9, deleted: Some(9) });
+        let mut version = Version { created: 2, deleted: Some(PENDING) };
+        version.stamp(9);
+        assert_eq!(version, Version { created: 2, deleted: Some(9) });
+        let mut version = Version { created: 2, deleted: None };
+        version.stamp(9);
+        assert_eq!(version, Version { created: 2, deleted: None });
+    }
+
+    #[test]
+    fn garbage_is_what_no_snapshot_can_see() {
+        assert!(!Version { created: 1, deleted: None }.is_garbage(100));
+        assert!(!Version { created: 1, deleted: Some(PENDING) }.is_garbage(100));
+        assert!(Version { created: 1, deleted: Some(5) }.is_garbage(5));
+        // A snapshot at 4 still sees it
+        assert!(!Version { created: 1, deleted: Some(5) }.is_garbage(4));
+        // Created and deleted by the same commit, so never seen
+        assert!(Version { created: 5, deleted: Some(5) }.is_garbage(0));
+    }
+
+    #[test]
+    fn serialization_failures_are_recognised() {
+        assert!(is_serialization_failure(&serialization_failure(CONCURRENT_UPDATE)));
+        assert!(!is_serialization_failure("!Division by zero"));
+    }
+}
@@ -0,0 +1,442 @@
+/// Matches text against a LIKE pattern
+/// % matches any run of characters, _ matches exactly one and the escape character makes the next one literal
+pub fn like(text: &str, pattern: &str, escape: Option<char>) -> Result<bool, String> {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if Some(c) == escape {
+            match chars.next() {
+                Some(c) => tokens.push(LikeToken::Literal(c)),
+                None => return Err(String::from("!LIKE pattern must not end with the escape character")),
+            }
+        } else if c == '%' {
+            tokens.push(LikeToken::Any);
+        } else if c == '_' {
+            tokens.push(LikeToken::One);
+        } else {
+            tokens.push(LikeToken::Literal(c));
+        }
+    }
+    let text: Vec<char> = text.chars().collect();
+    // matches[j] is whether the first i characters of text match the first j tokens
+    let mut matches = vec![false; tokens.len() + 1];
+    matches[0] = true;
+    for (j, token) in tokens.iter().enumerate() {
+        matches[j + 1] = matches[j] && *token == LikeToken::Any;
+    }
+    for c in text {
+        let mut next = vec![false; tokens.len() + 1];
+        for (j, token) in tokens.iter().enumerate() {
+            next[j + 1] = match token {
+                LikeToken::Any => matches[j + 1] || next[j],
+                LikeToken::One => matches[j],
+                LikeToken::Literal(l) => matches[j] && *l == c,
+            };
+        }
+        matches = next;
+    }
+    Ok(matches[tokens.len()])
+}
+
+#[derive(PartialEq)]
+enum LikeToken {
+    Any,
+    One,
+    Literal(char),
+}
+
+/// A small regular expression compiled to instructions and run as a Pike VM
+/// Supports literals, ., [] classes, \d \w \s, ^ $, groups, | and the * + ? {n,m} quantifiers
+/// Every instruction is tried at most once per character, so matching takes time linear in the text
+pub struct Regex {
+    program: Vec<Inst>,
+}
+
+/// Most instructions a compiled expression may have, counted repetitions like {1000} are copied out
+const MAX_PROGRAM: usize = 10_000;
+/// Deepest nesting of groups, the parser recurses once per group
+const MAX_NESTING: usize = 100;
+
+struct Item {
+    atom: Atom,
+    min: usize,
+    max: usize,
+}
+
+enum Atom {
+    Char(char),
+    Any,
+    Class(Vec<(char, char)>, bool),
+    Start,
+    End,
+    Group(Vec<Vec<Item>>),
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut pos = 0;
+        let alternatives = parse_alternatives(&chars, &mut pos, 0)?;
+        if pos < chars.len() {
+            return Err(format!("!Unmatched ) in regular expression {}", pattern));
+        }
+        let mut program = Vec::new();
+        compile_alternatives(&alternatives, &mut program)?;
+        push(&mut program, Inst::Match)?;
+        Ok(Self { program })
+    }
+
+    /// Whether the expression matches anywhere in text
+    /// Runs every thread of the program in lockstep over the text, a new thread starting at each character
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let mut current = Vec::new();
+        let mut next = Vec::new();
+        // seen[pc] is the last position pc was added at, so each is added once per position
+        let mut seen = vec![usize::MAX; self.program.len()];
+        for pos in 0..=text.len() {
+            if self.add(&mut current, &mut seen, &text, pos, 0) {
+                return true;
+            }
+            let Some(c) = text.get(pos) else {
+                break;
+            };
+            next.clear();
+            for &pc in &current {
+                let consumed = match &self.program[pc] {
+                    Inst::Char(l) => l == c,
+                    Inst::Any => true,
+                    Inst::Class(ranges, negated) => ranges.iter().any(|(low, high)| low <= c && c <= high) != *negated,
+                    _ => false,
+                };
+                if consumed && self.add(&mut next, &mut seen, &text, pos + 1, pc + 1) {
+                    return true;
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+        false
+    }
+
+    /// Follows jumps, splits and anchors from pc at pos, adding the instructions that consume a character to threads
+    /// Returns true when a match is reached
+    fn add(&self, threads: &mut Vec<usize>, seen: &mut [usize], text: &[char], pos: usize, pc: usize) -> bool {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if seen[pc] == pos {
+                continue;
+            }
+            seen[pc] = pos;
+            match self.program[pc] {
+                Inst::Match => return true,
+                Inst::Jmp(to) => stack.push(to),
+                Inst::Split(a, b) => {
+                    stack.push(b);
+                    stack.push(a);
+                },
+                Inst::Start if pos == 0 => stack.push(pc + 1),
+                Inst::End if pos == text.len() => stack.push(pc + 1),
+                Inst::Start | Inst::End => (),
+                _ => threads.push(pc),
+            }
+        }
+        false
+    }
+}
+
+/// One step of a compiled expression
+enum Inst {
+    Char(char),
+    Any,
+    Class(Vec<(char, char)>, bool),
+    Start,
+    End,
+    /// Continues at both targets
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+}
+
+fn parse_alternatives(chars: &[char], pos: &mut usize, depth: usize) -> Result<Vec<Vec<Item>>, String> {
+    if depth > MAX_NESTING {
+        return Err(String::from("!Regular expression is nested too deeply"));
+    }
+    let mut alternatives = vec![Vec::new()];
+    while *pos < chars.len() {
+        let atom = match chars[*pos] {
+            ')' => break,
+            '|' => {
+                *pos += 1;
+                alternatives.push(Vec::new());
+                continue;
+            },
+            '(' => {
+                *pos += 1;
+                let group = parse_alternatives(chars, pos, depth + 1)?;
+                if chars.get(*pos) != Some(&')') {
+                    return Err(String::from("!Unclosed ( in regular expression"));
+                }
+                *pos += 1;
+                Atom::Group(group)
+            },
+            '[' => parse_class(chars, pos)?,
+            '.' => {
+                *pos += 1;
+                Atom::Any
+            },
+            '^' => {
+                *pos += 1;
+                Atom::Start
+            },
+            '$' => {
+                *pos += 1;
+                Atom::End
+            },
+            '\\' => {
+                *pos += 2;
+                match chars.get(*pos - 1) {
+                    Some(c) => escape_class(*c),
+                    None => return Err(String::from("!Regular expression must not end with \\")),
+                }
+            },
+            c => {
+                *pos += 1;
+                Atom::Char(c)
+            },
+        };
+        let (min, max) = parse_quantifier(chars, pos)?;
+        alternatives.last_mut().unwrap().push(Item { atom, min, max });
+    }
+    Ok(alternatives)
+}
+
+fn escape_class(c: char) -> Atom {
+    match c {
+        'd' => Atom::Class(vec![('0', '9')], false),
+        'D' => Atom::Class(vec![('0', '9')], true),
+        'w' => Atom::Class(vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')], false),
+        'W' => Atom::Class(vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')], true),
+        's' => Atom::Class(vec![(' ', ' '), ('\t', '\t'), ('\n', '\n'), ('\r', '\r')], false),
+        'S' => Atom::Class(vec![(' ', ' '), ('\t', '\t'), ('\n', '\n'), ('\r', '\r')], true),
+        c => Atom::Char(c),
+    }
+}
+
+fn parse_class(chars: &[char], pos: &mut usize) -> Result<Atom, String> {
+    *pos += 1;
+    let negated = chars.get(*pos) == Some(&'^');
+    if negated {
+        *pos += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = match chars.get(*pos) {
+            None => return Err(String::from("!Unclosed [ in regular expression")),
+            Some(']') if !first => break,
+            Some('\\') => {
+                *pos += 1;
+                match chars.get(*pos) {
+                    Some(c) => *c,
+                    None => return Err(String::from("!Unclosed [ in regular expression")),
+                }
+            },
+            Some(c) => *c,
+        };
+        first = false;
+        *pos += 1;
+        if chars.get(*pos) == Some(&'-') && chars.get(*pos + 1).is_some_and(|end| *end != ']') {
+            ranges.push((c, chars[*pos + 1]));
+            *pos += 2;
+        } else {
+            ranges.push((c, c));
+        }
+    }
+    *pos += 1;
+    Ok(Atom::Class(ranges, negated))
+}
+
+fn parse_quantifier(chars: &[char], pos: &mut usize) -> Result<(usize, usize), String> {
+    let out = match chars.get(*pos) {
+        Some('*') => (0, usize::MAX),
+        Some('+') => (1, usize::MAX),
+        Some('?') => (0, 1),
+        Some('{') => {
+            let end = match chars[*pos..].iter().position(|c| *c == '}') {
+                Some(end) => *pos + end,
+                None => return Err(String::from("!Unclosed { in regular expression")),
+            };
+            let inner: String = chars[*pos + 1..end].iter().collect();
+            let bounds: Vec<&str> = inner.split(',').collect();
+            let parse = |s: &str, default: usize| -> Result<usize, String> {
+                match s.trim() {
+                    "" => Ok(default),
+                    s => s.parse::<usize>().map_err(|_| format!("!Invalid repetition {{{}}}", inner)),
+                }
+            };
+            let out = match bounds.as_slice() {
+                [n] => (parse(n, 0)?, parse(n, 0)?),
+                [min, max] => (parse(min, 0)?, parse(max, usize::MAX)?),
+                _ => return Err(format!("!Invalid repetition {{{}}}", inner)),
+            };
+            if out.0 > out.1 {
+                return Err(format!("!Invalid repetition {{{}}}", inner));
+            }
+            *pos = end;
+            out
+        },
+        _ => return Ok((1, 1)),
+    };
+    *pos += 1;
+    Ok(out)
+}
+
+fn push(program: &mut Vec<Inst>, inst: Inst) -> Result<usize, String> {
+    if program.len() >= MAX_PROGRAM {
+        return Err(String::from("!Regular expression is too large"));
+    }
+    program.push(inst);
+    Ok(program.len() - 1)
+}
+
+/// Each alternative but the last is tried through a split and jumps past the rest when it matches
+fn compile_alternatives(alternatives: &[Vec<Item>], program: &mut Vec<Inst>) -> Result<(), String> {
+    let mut jumps = Vec::new();
+    for (i, items) in alternatives.iter().enumerate() {
+        if i + 1 == alternatives.len() {
+            compile_items(items, program)?;
+            break;
+        }
+        let split = push(program, Inst::Split(0, 0))?;
+        compile_items(items, program)?;
+        jumps.push(push(program, Inst::Jmp(0))?);
+        program[split] = Inst::Split(split + 1, program.len());
+    }
+    for jump in jumps {
+        program[jump] = Inst::Jmp(program.len());
+    }
+    Ok(())
+}
+
+/// The atom is copied out min times, then either loops or is copied out once more for each optional repetition
+fn compile_items(items: &[Item], program: &mut Vec<Inst>) -> Result<(), String> {
+    for item in items {
+        // Anchors don't consume anything so they aren't repeated
+        if matches!(item.atom, Atom::Start | Atom::End) {
+            compile_atom(&item.atom, program)?;
+            continue;
+        }
+        for _ in 0..item.min {
+            compile_atom(&item.atom, program)?;
+        }
+        if item.max == usize::MAX {
+            let split = push(program, Inst::Split(0, 0))?;
+            compile_atom(&item.atom, program)?;
+            push(program, Inst::Jmp(split))?;
+            program[split] = Inst::Split(split + 1, program.len());
+        } else {
+            let mut splits = Vec::new();
+            for _ in item.min..item.max {
+                splits.push(push(program, Inst::Split(0, 0))?);
+                compile_atom(&item.atom, program)?;
+            }
+            for split in splits {
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn compile_atom(atom: &Atom, program: &mut Vec<Inst>) -> Result<(), String> {
+    let inst = match atom {
+        Atom::Char(c) => Inst::Char(*c),
+        Atom::Any => Inst::Any,
+        Atom::Class(ranges, negated) => Inst::Class(ranges.clone(), *negated),
+        Atom::Start => Inst::Start,
+        Atom::End => Inst::End,
+        Atom::Group(alternatives) => return compile_alternatives(alternatives, program),
+    };
+    push(program, inst)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn like_wildcards_and_escapes() {
+        assert_eq!(like("hello", "h%o", None), Ok(true));
+        assert_eq!(like("hello", "h_llo", None), Ok(true));
+        assert_eq!(like("hello", "h_lo", None), Ok(false));
+        assert_eq!(like("", "%", None), Ok(true));
+        assert_eq!(like("", "_", None), Ok(false));
+        assert_eq!(like("100%", "100!%", Some('!')), Ok(true));
+        assert_eq!(like("1000", "100!%", Some('!')), Ok(false));
+        assert_eq!(like("a_b", "a\\_b", Some('\\')), Ok(true));
+        assert!(like("abc", "abc!", Some('!')).is_err());
+        // Characters, not bytes
+        assert_eq!(like("héllo", "h_llo", None), Ok(true));
+    }
+
+    #[test]
+    fn like_is_linear_in_the_text() {
+        let text = "a".repeat(20_000);
+        let start = Instant::now();
+        assert_eq!(like(&text, "%a%a%a%a%a%b", None), Ok(false));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn regex_matches_anywhere_unless_anchored() {
+        assert!(matches("b.d", "abcde"));
+        assert!(!matches("^b", "abc"));
+        assert!(matches("c$", "abc"));
+        assert!(matches("^$", ""));
+        assert!(matches("^(cat|dog)s?$", "dogs"));
+        assert!(!matches("^(cat|dog)s?$", "cow"));
+        assert!(matches("^[a-c]+[^0-9]$", "abcx"));
+        assert!(!matches("^[a-c]+[^0-9]$", "abc1"));
+        assert!(matches("^\\d{3}-\\w+\\s?$", "555-call_me "));
+        assert!(matches("^a{2,3}$", "aaa"));
+        assert!(!matches("^a{2,3}$", "aaaa"));
+        assert!(matches("^a{2,}$", "aaaaa"));
+        assert!(matches("^[]a]+$", "]a]"));
+        assert!(matches("^\\.$", "."));
+        assert!(!matches("^\\.$", "x"));
+    }
+
+    #[test]
+    fn regex_rejects_bad_patterns() {
+        for pattern in ["(a", "a)", "[a", "a{2", "a{x}", "a{3,2}", "a\\"] {
+            assert!(Regex::new(pattern).is_err(), "{}", pattern);
+        }
+        assert_eq!(Regex::new(&format!("{}a{}", "(".repeat(200), ")".repeat(200))).err(), Some(String::from("!Regular expression is nested too deeply")));
+        assert_eq!(Regex::new("(a{1000}){1000}").err(), Some(String::from("!Regular expression is too large")));
+    }
+
+    #[test]
+    fn regex_does_not_backtrack() {
+        // A backtracking matcher tries every way of splitting the a's between the two loops
+        let text = format!("{}b", "a".repeat(5_000));
+        let start = Instant::now();
+        assert!(!matches("^(a+)+$", &text));
+        assert!(!matches("^(a|aa)*$", &text));
+        assert!(matches("(a*)*b", &text));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn regex_handles_long_text_without_recursing() {
+        let text = "a".repeat(200_000);
+        assert!(matches("^a*$", &text));
+        assert!(matches("^(a|b)+$", &text));
+        assert!(!matches("^a*b$", &text));
+    }
+}
@@ -0,0 +1,846 @@
+use pest::iterators::{Pair, Pairs};
+use pest::pratt_parser::{Assoc, Op, PrattParser};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use super::distinct::{self, RowSet};
+use super::expr::{Field, SQLExpr, SQLType, SQLValue, Scope};
+use super::window::Window;
+use super::{identifier, DataBase, Rule};
+
+/// Rows produced by a query along with the name and type of each column
+pub struct ResultSet {
+    pub fields: Vec<Field>,
+    pub rows: Vec<Vec<SQLValue>>,
+}
+
+impl ResultSet {
+    /// The header and rows as printed, the names of enum types are looked up in db
+    pub fn show(&self, db: &DataBase) -> String {
+        // Every line ends in a space, as the output always has
+        let header: Vec<String> = self.fields.iter().map(|field| format!("{} {} ", field.name, db.type_name(field.sql_type))).collect();
+        let mut out = header.join("| ");
+        for row in &self.rows {
+            let row: Vec<String> = row.iter().map(|val| format!("{} ", val)).collect();
+            out.push_str(&format!("\n{}", row.join("| ")));
+        }
+        out
+    }
+}
+
+/// Everything a query can read rows from
+/// That is the tables of the database plus the results of any WITH clauses in scope
+pub struct Catalog<'a> {
+    pub db: &'a DataBase,
+    ctes: Vec<(String, ResultSet)>,
+    parent: Option<&'a Catalog<'a>>,
+}
+
+impl<'a> Catalog<'a> {
+    pub fn new(db: &'a DataBase) -> Self {
+        Self { db, ctes: Vec::new(), parent: None }
+    }
+
+    fn child(&'a self) -> Self {
+        Self { db: self.db, ctes: Vec::new(), parent: Some(self) }
+    }
+
+    /// Finds a WITH result by name, later and inner definitions hide earlier ones
+    fn cte(&self, name: &str) -> Option<&ResultSet> {
+        match self.ctes.iter().rev().find(|(cte, _)| cte == name) {
+            Some((_, result)) => Some(result),
+            None => self.parent.and_then(|parent| parent.cte(name)),
+        }
+    }
+}
+
+/// A WITH RECURSIVE query gives up after this many rounds without reaching a fixpoint
+const MAX_RECURSION: usize = 1000;
+
+/// One named query of a WITH clause
+#[derive(Debug, Clone, PartialEq)]
+struct Cte {
+    name: String,
+    query: Select,
+}
+
+impl Cte {
+    fn parse(cte: Pair<Rule>) -> Result<Self, String> {
+        let mut it = cte.into_inner();
+        let name = identifier(it.next().unwrap());
+        let query = Select::parse(it.nth(1).unwrap().into_inner())?;
+        Ok(Self { name, query })
+    }
+
+    /// Runs the query, making it visible to the queries after it
+    /// In a recursive WITH a query of the form anchor UNION [ALL] step, where the step reads the query itself, is repeated
+    /// with the step reading the rows of the previous round until no new rows come out
+    /// or, when wanted is given, until there are that many rows
+    fn materialize(&self, catalog: &Catalog, recursive: bool, only_fields: bool, wanted: Option<usize>) -> Result<ResultSet, String> {
+        let query = &self.query;
+        let (anchor, all, step) = match &query.body {
+            SetExpr::Op(anchor, SetOp::Union, all, step)
+                if recursive && query.with.is_empty() && query.order_by.is_empty() && query.limit.is_none()
+                    && step.references(&self.name) => (anchor, *all, step),
+            _ if only_fields => return Ok(ResultSet { fields: query.fields(catalog, None)?, rows: Vec::new() }),
+            _ => return query.execute(catalog, None),
+        };
+        let mut result = if only_fields {
+            ResultSet { fields: anchor.fields(catalog, None)?, rows: Vec::new() }
+        } else {
+            anchor.execute(catalog, None)?
+        };
+        let mut seen = RowSet::new();
+        if !all {
+            result.rows = dedup(result.rows, &mut seen)?;
+        }
+        let mut working = ResultSet { fields: result.fields.clone(), rows: result.rows.clone() };
+        for depth in 0.. {
+            let mut round = catalog.child();
+            round.ctes.push((self.name.clone(), working));
+            result.fields = check_set_op(SetOp::Union, &result.fields, &step.fields(&round, None)?)?;
+            if only_fields || wanted.is_some_and(|wanted| result.rows.len() >= wanted) {
+                break;
+            }
+            let mut rows = step.execute(&round, None)?.rows;
+            if !all {
+                rows = dedup(rows, &mut seen)?;
+            }
+            if rows.is_empty() {
+                break;
+            }
+            if depth >= MAX_RECURSION {
+                return Err(format!("!Recursive query {} did not finish after {} rounds", self.name, MAX_RECURSION));
+            }
+            result.rows.extend(rows.iter().cloned());
+            working = ResultSet { fields: result.fields.clone(), rows };
+        }
+        Ok(result)
+    }
+}
+
+/// Removes rows that are already in seen, adding the new ones to it
+fn dedup(rows: Vec<Vec<SQLValue>>, seen: &mut RowSet) -> Result<Vec<Vec<SQLValue>>, String> {
+    let new = seen.insert(&rows)?;
+    Ok(rows.into_iter().zip(new).filter(|(_, new)| *new).map(|(row, _)| row).collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SetOp {
+    Union,
+    Intersect,
+    Except,
+}
+
+impl fmt::Display for SetOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SetOp::Union => write!(f, "UNION"),
+            SetOp::Intersect => write!(f, "INTERSECT"),
+            SetOp::Except => write!(f, "EXCEPT"),
+        }
+    }
+}
+
+/// Both sides of a set operation need the same number of columns with compatible types
+/// The result takes its names from the left side and the wider of the two types
+fn check_set_op(op: SetOp, lhs: &[Field], rhs: &[Field]) -> Result<Vec<Field>, String> {
+    if lhs.len() != rhs.len() {
+        return Err(format!("!Each side of {} must have the same number of columns but got {} and {}", op, lhs.len(), rhs.len()));
+    }
+    let mut fields = Vec::new();
+    for (lhs, rhs) in lhs.iter().zip(rhs) {
+        match lhs.sql_type.common(rhs.sql_type) {
+            Some(sql_type) => fields.push(Field { table: None, name: lhs.name.clone(), sql_type }),
+            None => return Err(format!("!{} column {} is {} on one side and {} on the other", op, lhs.name, lhs.sql_type, rhs.sql_type)),
+        }
+    }
+    Ok(fields)
+}
+
+/// SELECTs combined with set operations
+#[derive(Debug, Clone, PartialEq)]
+enum SetExpr {
+    Core(Box<SelectCore>),
+    /// A parenthesized query which can have its own WITH, ORDER BY and LIMIT
+    Nested(Box<Select>),
+    /// Left side, operation, whether it was ALL and right side
+    Op(Box<SetExpr>, SetOp, bool, Box<SetExpr>),
+}
+
+impl SetExpr {
+    fn parse(set_expr: Pair<Rule>) -> Result<Self, String> {
+        PrattParser::new()
+            .op(Op::infix(Rule::union, Assoc::Left) | Op::infix(Rule::except, Assoc::Left))
+            .op(Op::infix(Rule::intersect, Assoc::Left))
+            .map_primary(|core| {
+                let first = core.clone().into_inner().next().unwrap();
+                match first.as_rule() {
+                    Rule::subquery => Ok(SetExpr::Nested(Box::new(Select::parse(first.into_inner())?))),
+                    _ => Ok(SetExpr::Core(Box::new(SelectCore::parse(core.into_inner())?))),
+                }
+            })
+            .map_infix(|lhs, op, rhs| {
+                let set_op = match op.as_rule() {
+                    Rule::union => SetOp::Union,
+                    Rule::intersect => SetOp::Intersect,
+                    _ => SetOp::Except,
+                };
+                let all = op.into_inner().any(|pair| pair.as_rule() == Rule::all);
+                Ok(SetExpr::Op(Box::new(lhs?), set_op, all, Box::new(rhs?)))
+            })
+            .parse(set_expr.into_inner())
+    }
+
+    /// Whether the expression reads the table or WITH query called name, in a FROM list or a subquery
+    fn references(&self, name: &str) -> bool {
+        match self {
+            SetExpr::Core(core) => core.references(name),
+            SetExpr::Nested(query) => query.references(name),
+            SetExpr::Op(lhs, _, _, rhs) => lhs.references(name) || rhs.references(name),
+        }
+    }
+
+    fn fields(&self, catalog: &Catalog, outer: Option<&Scope>) -> Result<Vec<Field>, String> {
+        match self {
+            SetExpr::Core(core) => core.fields(catalog, outer),
+            SetExpr::Nested(query) => query.fields(catalog, outer),
+            SetExpr::Op(lhs, op, _, rhs) => check_set_op(*op, &lhs.fields(catalog, outer)?, &rhs.fields(catalog, outer)?),
+        }
+    }
+
+    fn execute(&self, catalog: &Catalog, outer: Option<&Scope>) -> Result<ResultSet, String> {
+        let (lhs, op, all, rhs) = match self {
+            SetExpr::Core(core) => return core.execute(catalog, outer, &[]),
+            SetExpr::Nested(query) => return query.execute(catalog, outer),
+            SetExpr::Op(lhs, op, all, rhs) => (lhs, *op, *all, rhs),
+        };
+        let fields = self.fields(catalog, outer)?;
+        // Values are widened to the column type first so 1 and 1.0 are the same row
+        let coerce = |rows: Vec<Vec<SQLValue>>| -> Vec<Vec<SQLValue>> {
+            rows.into_iter()
+                .map(|row| row.into_iter().zip(&fields).map(|(val, field)| val.coerce(field.sql_type)).collect())
+                .collect()
+        };
+        let lhs = coerce(lhs.execute(catalog, outer)?.rows);
+        let rhs = coerce(rhs.execute(catalog, outer)?.rows);
+        let rows = match (op, all) {
+            (SetOp::Union, true) => lhs.into_iter().chain(rhs).collect(),
+            (SetOp::Union, false) => distinct::unique(lhs.into_iter().chain(rhs).collect())?,
+            (SetOp::Intersect, false) | (SetOp::Except, false) => {
+                let mut set = RowSet::new();
+                set.insert(&rhs)?;
+                drop(rhs);
+                let found = set.contains(&lhs)?;
+                let keep = op == SetOp::Intersect;
+                distinct::unique(lhs.into_iter().zip(found).filter(|(_, found)| *found == keep).map(|(row, _)| row).collect())?
+            },
+            // The ALL versions match rows up one to one, so duplicates are counted
+            (SetOp::Intersect, true) | (SetOp::Except, true) => {
+                let mut counts: HashMap<Vec<SQLValue>, usize> = HashMap::new();
+                for row in rhs {
+                    *counts.entry(row).or_insert(0) += 1;
+                }
+                let keep = op == SetOp::Intersect;
+                lhs.into_iter().filter(|row| {
+                    let matched = match counts.get_mut(row) {
+                        Some(count) if *count > 0 => {
+                            *count -= 1;
+                            true
+                        },
+                        _ => false,
+                    };
+                    matched == keep
+                }).collect()
+            },
+        };
+        Ok(ResultSet { fields, rows })
+    }
+}
+
+/// One ORDER BY entry
+/// A bare integer refers to a column of the result by position, starting at 1
+#[derive(Debug, Clone, PartialEq)]
+struct OrderItem {
+    expr: SQLExpr,
+    desc: bool,
+}
+
+impl OrderItem {
+    fn parse(item: Pair<Rule>) -> Result<Self, String> {
+        let mut it = item.into_inner();
+        let expr = SQLExpr::parse(it.next().unwrap())?;
+        let desc = it.next().is_some_and(|dir| dir.as_rule() == Rule::desc);
+        Ok(Self { expr, desc })
+    }
+
+    fn position(&self) -> Option<i64> {
+        match self.expr {
+            SQLExpr::Literal(SQLValue::Int(i)) => Some(i),
+            _ => None,
+        }
+    }
+
+    fn check(&self, scope: &Scope) -> Result<(), String> {
+        match self.position() {
+            Some(i) if i < 1 || i as usize > scope.fields.len() => Err(format!("!ORDER BY position {} is not in the select list", i)),
+            Some(_) => Ok(()),
+            None => self.expr.check(scope).map(|_| ()),
+        }
+    }
+
+    /// Sort key for a row, scope holds the output row
+    fn key(&self, scope: &Scope) -> Result<SQLValue, String> {
+        match self.position() {
+            Some(i) => Ok(scope.values[i as usize - 1].clone()),
+            None => self.expr.eval(scope),
+        }
+    }
+}
+
+/// Sorts rows by their keys, NULLs come last when ascending and first when descending
+fn sort<T>(rows: Vec<T>, keys: Vec<Vec<SQLValue>>, order: &[OrderItem]) -> Vec<T> {
+    let mut keyed: Vec<(Vec<SQLValue>, T)> = keys.into_iter().zip(rows).collect();
+    keyed.sort_by(|(a, _), (b, _)| {
+        for ((a, b), item) in a.iter().zip(b).zip(order) {
+            let ordering = a.sort_cmp(b);
+            if ordering != Ordering::Equal {
+                return if item.desc { ordering.reverse() } else { ordering };
+            }
+        }
+        Ordering::Equal
+    });
+    keyed.into_iter().map(|(_, row)| row).collect()
+}
+
+/// A full query, SELECTs combined with set operations plus the clauses that apply to the combined result
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    with: Vec<Cte>,
+    recursive: bool,
+    body: SetExpr,
+    order_by: Vec<OrderItem>,
+    limit: Option<SQLExpr>,
+    offset: Option<SQLExpr>,
+}
+
+impl Select {
+    /// Parses the inner pairs of a select or subquery rule
+    pub fn parse(list: Pairs<Rule>) -> Result<Self, String> {
+        let mut with = Vec::new();
+        let mut recursive = false;
+        let mut body = None;
+        let mut order_by = Vec::new();
+        let mut limit = None;
+        let mut offset = None;
+        for pair in list {
+            match pair.as_rule() {
+                Rule::r#with => {
+                    for pair in pair.into_inner() {
+                        match pair.as_rule() {
+                            Rule::recursive => recursive = true,
+                            Rule::cte => with.push(Cte::parse(pair)?),
+                            _ => (),
+                        }
+                    }
+                },
+                Rule::setExpr => body = Some(SetExpr::parse(pair)?),
+                Rule::orderBy => {
+                    for item in pair.into_inner().filter(|item| item.as_rule() == Rule::orderItem) {
+                        order_by.push(OrderItem::parse(item)?);
+                    }
+                },
+                Rule::limit => {
+                    let mut it = pair.into_inner().filter(|pair| pair.as_rule() == Rule::expr);
+                    limit = Some(SQLExpr::parse(it.next().unwrap())?);
+                    if let Some(expr) = it.next() {
+                        offset = Some(SQLExpr::parse(expr)?);
+                    }
+                },
+                _ => (),
+            }
+        }
+        Ok(Self { with, recursive, body: body.unwrap(), order_by, limit, offset })
+    }
+
+    /// Whether the query reads the table or WITH query called name, a WITH query of its own by that name hides it
+    fn references(&self, name: &str) -> bool {
+        self.with.iter().any(|cte| cte.query.references(name))
+            || !self.with.iter().any(|cte| cte.name == name) && (self.body.references(name) || self.tail().any(|expr| reads(expr, name)))
+    }
+
+    /// The ORDER BY, LIMIT and OFFSET expressions
+    fn tail(&self) -> impl Iterator<Item = &SQLExpr> {
+        self.order_by.iter().map(|item| &item.expr).chain(&self.limit).chain(&self.offset)
+    }
+
+    /// How many rows of the WITH query at i are read, when the body only passes its rows through
+    /// up to a LIMIT and no other part of the query reads it, None when every row is needed
+    fn wanted(&self, i: usize, catalog: &Catalog, outer: Option<&Scope>) -> Result<Option<usize>, String> {
+        let name = &self.with[i].name;
+        let passes_through = match &self.body {
+            SetExpr::Core(core) => core.passes_through(name),
+            _ => false,
+        };
+        if self.limit.is_none() || !self.order_by.is_empty() || !passes_through
+            || self.with[i + 1..].iter().any(|cte| cte.name == *name || cte.query.references(name))
+            || self.tail().any(|expr| reads(expr, name)) {
+            return Ok(None);
+        }
+        let offset = self.count(&self.offset, catalog, outer)?.unwrap_or(0);
+        Ok(self.count(&self.limit, catalog, outer)?.map(|limit| limit.saturating_add(offset)))
+    }
+
+    /// Runs the WITH clause, making its results visible to the rest of the query
+    fn with<'a>(&self, catalog: &'a Catalog<'a>, only_fields: bool, outer: Option<&Scope>) -> Result<Catalog<'a>, String> {
+        let mut catalog = catalog.child();
+        for (i, cte) in self.with.iter().enumerate() {
+            let wanted = if only_fields { None } else { self.wanted(i, &catalog, outer)? };
+            let result = cte.materialize(&catalog, self.recursive, only_fields, wanted)?;
+            catalog.ctes.push((cte.name.clone(), result));
+        }
+        Ok(catalog)
+    }
+
+    /// Name and type of every column the query returns
+    pub fn fields(&self, catalog: &Catalog, outer: Option<&Scope>) -> Result<Vec<Field>, String> {
+        let catalog = self.with(catalog, true, outer)?;
+        self.body.fields(&catalog, outer)
+    }
+
+    /// Runs the query against a database
+    /// outer is the row of the enclosing query when this is a correlated subquery
+    pub fn execute(&self, catalog: &Catalog, outer: Option<&Scope>) -> Result<ResultSet, String> {
+        let catalog = self.with(catalog, false, outer)?;
+        let mut result = match &self.body {
+            // A single SELECT can also order by columns it doesn't return
+            SetExpr::Core(core) => core.execute(&catalog, outer, &self.order_by)?,
+            body => {
+                let mut result = body.execute(&catalog, outer)?;
+                if !self.order_by.is_empty() {
+                    let check = Scope::new(&catalog, &result.fields, &[], outer);
+                    for item in &self.order_by {
+                        item.check(&check)?;
+                    }
+                    let mut keys = Vec::new();
+                    for row in &result.rows {
+                        let scope = Scope::new(&catalog, &result.fields, row, outer);
+                        keys.push(self.order_by.iter().map(|item| item.key(&scope)).collect::<Result<Vec<SQLValue>, String>>()?);
+                    }
+                    result.rows = sort(result.rows, keys, &self.order_by);
+                }
+                result
+            },
+        };
+        let offset = self.count(&self.offset, &catalog, outer)?.unwrap_or(0);
+        let limit = self.count(&self.limit, &catalog, outer)?.unwrap_or(usize::MAX);
+        result.rows = result.rows.into_iter().skip(offset).take(limit).collect();
+        Ok(result)
+    }
+
+    /// Evaluates a LIMIT or OFFSET, NULL means no limit
+    fn count(&self, expr: &Option<SQLExpr>, catalog: &Catalog, outer: Option<&Scope>) -> Result<Option<usize>, String> {
+        let expr = match expr {
+            Some(expr) => expr,
+            None => return Ok(None),
+        };
+        let scope = Scope::new(catalog, &[], &[], outer);
+        match expr.eval(&scope)? {
+            SQLValue::Null => Ok(None),
+            SQLValue::Int(n) if n >= 0 => Ok(Some(n as usize)),
+            val => Err(format!("!LIMIT and OFFSET must be a non negative int but got {}", val)),
+        }
+    }
+}
+
+/// One entry of the select list
+#[derive(Debug, Clone, PartialEq)]
+struct SelectItem {
+    expr: SQLExpr,
+    name: String,
+}
+
+/// Where the rows of a query come from
+#[derive(Debug, Clone, PartialEq)]
+enum Source {
+    Table { name: String, alias: Option<String> },
+    /// A subquery in the FROM clause, its columns are qualified by the alias
+    Derived { query: Box<Select>, alias: Option<String> },
+}
+
+/// Whether a subquery in expr reads the table or WITH query called name
+fn reads(expr: &SQLExpr, name: &str) -> bool {
+    let mut queries = Vec::new();
+    expr.subqueries(&mut queries);
+    queries.iter().any(|query| query.references(name))
+}
+
+/// A single SELECT ... FROM ... WHERE
+#[derive(Debug, Clone, PartialEq)]
+struct SelectCore {
+    /// DISTINCT has no expressions and compares whole rows
+    /// DISTINCT ON (...) keeps the first row for each value of its expressions
+    distinct: Option<Vec<SQLExpr>>,
+    /// None when selecting *
+    items: Option<Vec<SelectItem>>,
+    /// Window function calls in the select list, in the order of their ids
+    windows: Vec<Window>,
+    /// Every combination of rows from these is read, an empty list reads a single empty row
+    from: Vec<Source>,
+    filter: Option<SQLExpr>,
+}
+
+impl SelectCore {
+    /// Parses the inner pairs of a selectCore rule
+    fn parse(mut list: Pairs<Rule>) -> Result<Self, String> {
+        let mut first = list.next().unwrap();
+        let mut distinct = None;
+        if first.as_rule() == Rule::distinct {
+            let exprs = first.into_inner().filter(|pair| pair.as_rule() == Rule::expr);
+            distinct = Some(exprs.map(SQLExpr::parse).collect::<Result<Vec<SQLExpr>, String>>()?);
+            first = list.next().unwrap();
+        }
+        let mut items = SelectCore::parse_items(first)?;
+        let mut windows = Vec::new();
+        if let Some(items) = &mut items {
+            let mut found = Vec::new();
+            for item in items.iter_mut() {
+                item.expr.windows_mut(&mut found);
+            }
+            for (id, window) in found.into_iter().enumerate() {
+                window.id = id;
+                windows.push(window.clone());
+            }
+        }
+        if windows.iter().any(Window::is_aggregate) && !windows.iter().all(Window::is_aggregate) {
+            return Err(String::from("!Window functions cannot be used together with aggregate functions"));
+        }
+        let mut from = Vec::new();
+        let mut filter = None;
+        for pair in list {
+            match pair.as_rule() {
+                Rule::source => from.push(SelectCore::parse_source(pair)?),
+                Rule::r#where => filter = Some(SQLExpr::parse(pair.into_inner().next().unwrap())?),
+                _ => (),
+            }
+        }
+        Ok(Self { distinct, items, windows, from, filter })
+    }
+
+    /// Whether the select list calls SUM or AVG without OVER, which makes the query return a single row
+    fn aggregates(&self) -> bool {
+        self.windows.first().is_some_and(Window::is_aggregate)
+    }
+
+    /// The expressions of the select list, DISTINCT ON and WHERE
+    fn exprs(&self) -> impl Iterator<Item = &SQLExpr> {
+        let distinct = self.distinct.iter().flatten();
+        let items = self.items.iter().flatten().map(|item| &item.expr);
+        distinct.chain(items).chain(&self.filter)
+    }
+
+    /// Whether the FROM list or a subquery reads the table or WITH query called name
+    fn references(&self, name: &str) -> bool {
+        self.from.iter().any(|source| match source {
+            Source::Table { name: table, .. } => table == name,
+            Source::Derived { query, .. } => query.references(name),
+        }) || self.exprs().any(|expr| reads(expr, name))
+    }
+
+    /// Whether the SELECT returns a row for each row of name in the order it reads them
+    /// That is when it reads only name, with no WHERE, DISTINCT or window functions
+    fn passes_through(&self, name: &str) -> bool {
+        matches!(self.from.as_slice(), [Source::Table { name: table, .. }] if table == name)
+            && self.filter.is_none() && self.distinct.is_none() && self.windows.is_empty()
+            && !self.exprs().any(|expr| reads(expr, name))
+    }
+
+    fn parse_items(list: Pair<Rule>) -> Result<Option<Vec<SelectItem>>, String> {
+        let mut items = Vec::new();
+        for item in list.into_inner() {
+            match item.as_rule() {
+                Rule::star => return Ok(None),
+                Rule::selectItem => {
+                    let mut it = item.into_inner();
+                    let expr = it.next().unwrap();
+                    let text = expr.as_str().trim();
+                    let expr = SQLExpr::parse(expr)?;
+                    let alias = it.nth(1);
+                    // Unaliased columns keep their column name or are named after the text of their expression
+                    let name = match (alias, &expr) {
+                        (Some(alias), _) => identifier(alias),
+                        (None, SQLExpr::Column(_, name)) => name.clone(),
+                        (None, _) => String::from(text),
+                    };
+                    items.push(SelectItem { expr, name });
+                },
+                _ => (),
+            }
+        }
+        Ok(Some(items))
+    }
+
+    fn parse_source(source: Pair<Rule>) -> Result<Source, String> {
+        let mut it = source.into_inner();
+        let from = it.next().unwrap();
+        let alias = it.nth(1).map(identifier);
+        match from.as_rule() {
+            Rule::subquery => Ok(Source::Derived { query: Box::new(Select::parse(from.into_inner())?), alias }),
+            _ => Ok(Source::Table { name: identifier(from), alias }),
+        }
+    }
+
+    /// Reads the rows the query works on, which is every combination of rows of the FROM list
+    /// With only_fields set no rows are read, which is all type checking needs
+    fn input(&self, catalog: &Catalog, only_fields: bool) -> Result<ResultSet, String> {
+        let mut input = ResultSet { fields: Vec::new(), rows: vec![Vec::new()] };
+        // A WHERE over a single table can go through one of its indexes
+        let filter = self.filter.as_ref().filter(|_| self.from.len() == 1);
+        for source in &self.from {
+            let source = SelectCore::read(source, catalog, only_fields, filter)?;
+            let mut rows = Vec::new();
+            for row in &input.rows {
+                for other in &source.rows {
+                    rows.push(row.iter().chain(other).cloned().collect());
+                }
+            }
+            input.fields.extend(source.fields);
+            input.rows = rows;
+        }
+        Ok(input)
+    }
+
+    /// Reads a single entry of the FROM list with its columns qualified by its name or alias
+    /// A table only returns rows that can pass filter when it has an index for it
+    fn read(source: &Source, catalog: &Catalog, only_fields: bool, filter: Option<&SQLExpr>) -> Result<ResultSet, String> {
+        let (mut input, alias) = match source {
+            Source::Table { name, alias } => match (catalog.cte(name), catalog.db.tables.get(name)) {
+                (Some(cte), _) => (ResultSet { fields: cte.fields.clone(), rows: cte.rows.clone() }, alias.as_ref().unwrap_or(name)),
+                (None, Some(table)) if only_fields => (ResultSet { fields: table.fields(), rows: Vec::new() }, alias.as_ref().unwrap_or(name)),
+                (None, Some(table)) => {
+                    let alias = alias.as_ref().unwrap_or(name);
+                    match filter {
+                        Some(filter) => (table.scan_where(catalog.db.snapshot, filter, alias)?, alias),
+                        None => (table.scan(catalog.db.snapshot), alias),
+                    }
+                },
+                (None, None) => return Err(format!("!Failed to query {} as it does not exist", name)),
+            },
+            Source::Derived { query, alias } => {
+                let input = if only_fields {
+                    ResultSet { fields: query.fields(catalog, None)?, rows: Vec::new() }
+                } else {
+                    query.execute(catalog, None)?
+                };
+                match alias {
+                    Some(alias) => (input, alias),
+                    None => return Ok(input),
+                }
+            },
+        };
+        for field in input.fields.iter_mut() {
+            field.table = Some(alias.clone());
+        }
+        Ok(input)
+    }
+
+    /// Name and type of every column the query returns
+    fn fields(&self, catalog: &Catalog, outer: Option<&Scope>) -> Result<Vec<Field>, String> {
+        let input = self.input(catalog, true)?;
+        let scope = Scope::new(catalog, &input.fields, &[], outer);
+        if let Some(filter) = &self.filter {
+            check_filter(filter, &scope)?;
+        }
+        match &self.items {
+            None => Ok(input.fields.clone()),
+            Some(items) => {
+                let scope = scope.with_windows(&[]);
+                let mut fields = Vec::new();
+                for item in items {
+                    let sql_type = item.expr.check(&scope)?;
+                    if self.aggregates() {
+                        check_aggregated(&item.expr, &input.fields)?;
+                    }
+                    fields.push(Field { table: None, name: item.name.clone(), sql_type });
+                }
+                Ok(fields)
+            }
+        }
+    }
+
+    /// Runs the query, sorting the result when order isn't empty
+    /// Sort keys and DISTINCT ON can use the output columns and fall back to the columns of the input row
+    /// Duplicates are removed after sorting so DISTINCT ON keeps the first row in that order
+    fn execute(&self, catalog: &Catalog, outer: Option<&Scope>, order: &[OrderItem]) -> Result<ResultSet, String> {
+        let fields = self.fields(catalog, outer)?;
+        let input = self.input(catalog, false)?;
+        let inner = Scope::new(catalog, &input.fields, &[], outer);
+        let check = Scope::new(catalog, &fields, &[], Some(&inner));
+        for item in order {
+            item.check(&check)?;
+        }
+        for expr in self.distinct.iter().flatten() {
+            expr.check(&check)?;
+        }
+        let mut kept = Vec::new();
+        for row in input.rows {
+            if let Some(filter) = &self.filter {
+                if filter.eval(&Scope::new(catalog, &input.fields, &row, outer))? != SQLValue::Bool(true) {
+                    continue;
+                }
+            }
+            kept.push(row);
+        }
+        // Window functions see every row left after WHERE, results are stored per row
+        let mut windows = vec![Vec::new(); kept.len()];
+        for window in &self.windows {
+            for (i, val) in window.compute(catalog, &input.fields, &kept, outer)?.into_iter().enumerate() {
+                windows[i].push(val);
+            }
+        }
+        // Aggregates turn the rows left after WHERE into one, which is there even when none are left
+        // Columns are only read inside the aggregates so a row of NULLs stands in for the input
+        if self.aggregates() {
+            windows = vec![windows.into_iter().next().unwrap_or_else(|| vec![SQLValue::Null; self.windows.len()])];
+            kept = vec![vec![SQLValue::Null; input.fields.len()]];
+        }
+        let mut rows = Vec::new();
+        let mut keys = Vec::new();
+        for (row, windows) in kept.iter().zip(&windows) {
+            let scope = Scope::new(catalog, &input.fields, row, outer).with_windows(windows);
+            let out = match &self.items {
+                None => row.clone(),
+                Some(items) => {
+                    let mut out = Vec::new();
+                    for item in items {
+                        out.push(item.expr.eval(&scope)?);
+                    }
+                    out
+                }
+            };
+            let scope = Scope::new(catalog, &fields, &out, Some(&scope));
+            if !order.is_empty() {
+                keys.push(order.iter().map(|item| item.key(&scope)).collect::<Result<Vec<SQLValue>, String>>()?);
+            }
+            let on = match &self.distinct {
+                Some(exprs) if !exprs.is_empty() => exprs.iter().map(|expr| expr.eval(&scope)).collect::<Result<Vec<SQLValue>, String>>()?,
+                _ => Vec::new(),
+            };
+            rows.push((out, on));
+        }
+        if !order.is_empty() {
+            rows = sort(rows, keys, order);
+        }
+        let rows = match &self.distinct {
+            None => rows.into_iter().map(|(row, _)| row).collect(),
+            Some(exprs) if exprs.is_empty() => distinct::unique(rows.into_iter().map(|(row, _)| row).collect())?,
+            Some(_) => {
+                let (rows, on): (Vec<Vec<SQLValue>>, Vec<Vec<SQLValue>>) = rows.into_iter().unzip();
+                let mut keep = distinct::first_of_each(&on)?.into_iter().peekable();
+                drop(on);
+                rows.into_iter().enumerate().filter(|(i, _)| keep.next_if_eq(i).is_some()).map(|(_, row)| row).collect()
+            },
+        };
+        Ok(ResultSet { fields, rows })
+    }
+}
+
+/// Makes sure expr reads no column of fields outside an aggregate function, as those belong to a single row
+fn check_aggregated(expr: &SQLExpr, fields: &[Field]) -> Result<(), String> {
+    let mut expr = expr.clone();
+    let mut columns = Vec::new();
+    expr.columns_mut(&mut columns);
+    for (table, name) in columns {
+        if fields.iter().any(|field| field.name == *name && (table.is_none() || field.table == *table)) {
+            return Err(format!("!Column {} must be used in an aggregate function", name));
+        }
+    }
+    Ok(())
+}
+
+/// Makes sure a WHERE clause is a boolean expression over the columns in scope
+pub fn check_filter(filter: &SQLExpr, scope: &Scope) -> Result<(), String> {
+    match filter.check(scope)? {
+        SQLType::Bool | SQLType::Null => Ok(()),
+        sql_type => Err(format!("!WHERE clause must be boolean but is {}", sql_type)),
+    }
+}
+
+/// RETURNING clause of INSERT, UPDATE and DELETE, a select list run over the rows the statement changed
+#[derive(Debug, Clone)]
+pub struct Returning {
+    /// None when returning *
+    items: Option<Vec<SelectItem>>,
+}
+
+impl Returning {
+    /// Parses a returning rule
+    pub fn parse(pair: Pair<Rule>) -> Result<Self, String> {
+        let list = pair.into_inner().find(|pair| pair.as_rule() == Rule::selectList).unwrap();
+        Ok(Self { items: SelectCore::parse_items(list)? })
+    }
+
+    /// Evaluates the list for each row, fields are the columns of the table
+    /// Runs before the statement writes anything so an error here leaves the table as it was
+    pub fn execute(&self, catalog: &Catalog, fields: &[Field], rows: Vec<Vec<SQLValue>>) -> Result<ResultSet, String> {
+        let items = match &self.items {
+            None => {
+                let fields = fields.iter().map(|field| Field { table: None, ..field.clone() }).collect();
+                return Ok(ResultSet { fields, rows });
+            },
+            Some(items) => items,
+        };
+        let scope = Scope::new(catalog, fields, &[], None);
+        let mut out_fields = Vec::new();
+        for item in items {
+            out_fields.push(Field { table: None, name: item.name.clone(), sql_type: item.expr.check(&scope)? });
+        }
+        let mut out = Vec::new();
+        for row in &rows {
+            let scope = Scope::new(catalog, fields, row, None);
+            out.push(items.iter().map(|item| item.expr.eval(&scope)).collect::<Result<Vec<SQLValue>, String>>()?);
+        }
+        Ok(ResultSet { fields: out_fields, rows: out })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pest::Parser;
+    use super::super::SQLParser;
+
+    fn query(text: &str) -> Result<Vec<Vec<SQLValue>>, String> {
+        let db = DataBase::new();
+        let select = SQLParser::parse(Rule::select, text).unwrap().next().unwrap();
+        Ok(Select::parse(select.into_inner())?.execute(&Catalog::new(&db), None)?.rows)
+    }
+
+    #[test]
+    fn recursion_stops_once_a_limit_is_reached() {
+        let rows = query("WITH RECURSIVE n AS (SELECT 1 AS x UNION ALL SELECT x + 1 FROM n) SELECT x FROM n LIMIT 2 OFFSET 1").unwrap();
+        assert_eq!(rows, vec![vec![SQLValue::Int(2)], vec![SQLValue::Int(3)]]);
+        // Sorting or filtering needs every row, so an endless query still gives up
+        let endless = "WITH RECURSIVE n AS (SELECT 1 AS x UNION ALL SELECT x + 1 FROM n) SELECT x FROM n";
+        for tail in [" ORDER BY x LIMIT 3", " WHERE x > 1 LIMIT 3"] {
+            assert!(query(&format!("{}{}", endless, tail)).unwrap_err().contains("did not finish"));
+        }
+    }
+
+    #[test]
+    fn recursion_is_found_in_subqueries() {
+        let rows = query("WITH RECURSIVE n AS (SELECT 1 AS x UNION ALL SELECT 2 FROM (SELECT 1) AS d WHERE NOT EXISTS (SELECT 1 FROM n WHERE x = 2)) SELECT x FROM n").unwrap();
+        assert_eq!(rows, vec![vec![SQLValue::Int(1)], vec![SQLValue::Int(2)]]);
+    }
+
+    #[test]
+    fn aggregates_return_one_exact_row() {
+        let prices = "WITH t AS (SELECT CAST(0.10 AS DECIMAL(10, 2)) AS p UNION ALL SELECT CAST(0.20 AS DECIMAL(10, 2)))";
+        let rows = query(&format!("{} SELECT SUM(p), AVG(p) FROM t", prices)).unwrap();
+        let shown: Vec<String> = rows[0].iter().map(SQLValue::to_string).collect();
+        assert_eq!((rows.len(), shown), (1, vec![String::from("0.30"), String::from("0.1500000000000000")]));
+        let rows = query(&format!("{} SELECT SUM(p), AVG(p) FROM t WHERE p > 1", prices)).unwrap();
+        assert_eq!(rows, vec![vec![SQLValue::Null, SQLValue::Null]]);
+        assert!(query(&format!("{} SELECT p, SUM(p) FROM t", prices)).unwrap_err().contains("aggregate"));
+    }
+}
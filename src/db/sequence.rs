@@ -0,0 +1,121 @@
+use serde::{Serialize, Deserialize};
+use std::cell::Cell;
+use super::expr::{SQLType, SQLValue, Scope};
+
+/// A counter made with CREATE SEQUENCE and read with nextval, currval and setval
+/// Its values are cells so any expression can advance it while the database is only read
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Sequence {
+    next: Cell<i64>,
+    increment: i64,
+    /// Last value given out by nextval or set by setval in this session, which is all currval can read
+    #[serde(skip)]
+    last: Cell<Option<i64>>,
+}
+
+impl Sequence {
+    pub fn new(start: i64, increment: i64) -> Result<Self, String> {
+        if increment == 0 {
+            return Err(String::from("!INCREMENT cannot be 0"));
+        }
+        Ok(Self { next: Cell::new(start), increment, last: Cell::new(None) })
+    }
+
+    /// Keeps what currval returns when the sequence is read again from the dump
+    pub fn keep_currval(&self, ours: &Sequence) {
+        self.last.set(ours.last.get());
+    }
+
+    fn nextval(&self, name: &str) -> Result<i64, String> {
+        let val = self.next.get();
+        match val.checked_add(self.increment) {
+            Some(next) => self.next.set(next),
+            None => return Err(format!("!Sequence {} has run out of values", name)),
+        }
+        self.last.set(Some(val));
+        Ok(val)
+    }
+
+    fn currval(&self, name: &str) -> Result<i64, String> {
+        match self.last.get() {
+            Some(val) => Ok(val),
+            None => Err(format!("!currval of sequence {} is not defined until nextval is called", name)),
+        }
+    }
+
+    /// The next call to nextval returns the value after val
+    fn setval(&self, name: &str, val: i64) -> Result<i64, String> {
+        match val.checked_add(self.increment) {
+            Some(next) => self.next.set(next),
+            None => return Err(format!("!Value {} is out of range for sequence {}", val, name)),
+        }
+        self.last.set(Some(val));
+        Ok(val)
+    }
+}
+
+/// nextval('seq'), currval('seq') and setval('seq', n)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SequenceFunction {
+    Next,
+    Current,
+    Set,
+}
+
+impl SequenceFunction {
+    pub fn lookup(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "NEXTVAL" => Some(SequenceFunction::Next),
+            "CURRVAL" => Some(SequenceFunction::Current),
+            "SETVAL" => Some(SequenceFunction::Set),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SequenceFunction::Next => "NEXTVAL",
+            SequenceFunction::Current => "CURRVAL",
+            SequenceFunction::Set => "SETVAL",
+        }
+    }
+
+    /// Takes the sequence name as text, setval also takes an int
+    pub fn check(&self, args: &[SQLType]) -> Result<SQLType, String> {
+        let arity = match self {
+            SequenceFunction::Set => 2,
+            _ => 1,
+        };
+        if args.len() != arity {
+            return Err(format!("!{} takes {} arguments but got {}", self.name(), arity, args.len()));
+        }
+        if !args[0].is_text() && args[0] != SQLType::Null {
+            return Err(format!("!{} expected a sequence name but got {}", self.name(), args[0]));
+        }
+        if let Some(val) = args.get(1) {
+            if !val.is_integer() {
+                return Err(format!("!{} expected int but got {}", self.name(), val));
+            }
+        }
+        Ok(SQLType::BigInt)
+    }
+
+    /// Returns NULL if any argument is NULL
+    pub fn eval(&self, args: Vec<SQLValue>, scope: &Scope) -> Result<SQLValue, String> {
+        let name = match &args[0] {
+            SQLValue::Char(name) => name.as_str(),
+            _ => return Ok(SQLValue::Null),
+        };
+        let sequence = match scope.catalog.db.sequences.get(name) {
+            Some(sequence) => sequence,
+            None => return Err(format!("!Sequence {} does not exist", name)),
+        };
+        let val = match (self, args.get(1)) {
+            (SequenceFunction::Next, _) => sequence.nextval(name)?,
+            (SequenceFunction::Current, _) => sequence.currval(name)?,
+            (SequenceFunction::Set, Some(SQLValue::Int(val))) => sequence.setval(name, *val)?,
+            (SequenceFunction::Set, _) => return Ok(SQLValue::Null),
+        };
+        Ok(SQLValue::Int(val))
+    }
+}
@@ -0,0 +1,84 @@
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use std::fmt;
+
+/// A 128 bit UUID kept as its 16 bytes, in memory and in the dump
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "Saved")]
+pub struct Uuid([u8; 16]);
+
+/// A UUID as found in the dump, dumps from before it saved the bytes hold its text
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Saved {
+    Bytes([u8; 16]),
+    Text(String),
+}
+
+impl Uuid {
+    /// A random version 4 UUID
+    pub fn random() -> Self {
+        let mut bytes: [u8; 16] = rand::thread_rng().gen();
+        bytes[6] = bytes[6] & 0x0f | 0x40;
+        bytes[8] = bytes[8] & 0x3f | 0x80;
+        Uuid(bytes)
+    }
+
+    /// Reads 32 hex digits in either case, with or without the usual hyphens and surrounding braces
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let text = text.strip_prefix('{').and_then(|text| text.strip_suffix('}')).unwrap_or(text);
+        let hex: String = match text.len() {
+            32 => String::from(text),
+            36 if [8, 13, 18, 23].iter().all(|i| text.as_bytes()[*i] == b'-') => text.chars().filter(|c| *c != '-').collect(),
+            _ => return None,
+        };
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let mut bytes = [0; 16];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+        }
+        Some(Uuid(bytes))
+    }
+}
+
+/// Lower case hex in the 8-4-4-4-12 form
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<Saved> for Uuid {
+    type Error = String;
+
+    fn try_from(saved: Saved) -> Result<Self, String> {
+        match saved {
+            Saved::Bytes(bytes) => Ok(Uuid(bytes)),
+            Saved::Text(text) => Uuid::parse(&text).ok_or_else(|| format!("!Invalid uuid '{}'", text)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dumps_hold_the_bytes_and_old_text_still_reads() {
+        let uuid = Uuid::parse("0123456789abcdef0123456789ABCDEF").unwrap();
+        let saved = serde_json::to_string(&uuid).unwrap();
+        assert_eq!(saved, "[1,35,69,103,137,171,205,239,1,35,69,103,137,171,205,239]");
+        assert_eq!(serde_json::from_str::<Uuid>(&saved).unwrap(), uuid);
+        assert_eq!(serde_json::from_str::<Uuid>("\"01234567-89ab-cdef-0123-456789abcdef\"").unwrap(), uuid);
+        assert!(serde_json::from_str::<Uuid>("\"not a uuid\"").is_err());
+    }
+}
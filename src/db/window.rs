@@ -0,0 +1,320 @@
+use pest::iterators::Pair;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use super::Rule;
+use super::decimal;
+use super::expr::{self, BinaryOp, Field, SQLExpr, SQLType, SQLValue, Scope};
+use super::select::Catalog;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WindowFunction {
+    RowNumber,
+    Rank,
+    DenseRank,
+    Lag,
+    Lead,
+    FirstValue,
+    Sum,
+    Avg,
+}
+
+impl WindowFunction {
+    fn lookup(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "ROW_NUMBER" => Some(WindowFunction::RowNumber),
+            "RANK" => Some(WindowFunction::Rank),
+            "DENSE_RANK" => Some(WindowFunction::DenseRank),
+            "LAG" => Some(WindowFunction::Lag),
+            "LEAD" => Some(WindowFunction::Lead),
+            "FIRST_VALUE" => Some(WindowFunction::FirstValue),
+            "SUM" => Some(WindowFunction::Sum),
+            "AVG" => Some(WindowFunction::Avg),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            WindowFunction::RowNumber => "ROW_NUMBER",
+            WindowFunction::Rank => "RANK",
+            WindowFunction::DenseRank => "DENSE_RANK",
+            WindowFunction::Lag => "LAG",
+            WindowFunction::Lead => "LEAD",
+            WindowFunction::FirstValue => "FIRST_VALUE",
+            WindowFunction::Sum => "SUM",
+            WindowFunction::Avg => "AVG",
+        }
+    }
+
+    /// Smallest and largest number of arguments
+    fn arity(&self) -> (usize, usize) {
+        match self {
+            WindowFunction::RowNumber | WindowFunction::Rank | WindowFunction::DenseRank => (0, 0),
+            WindowFunction::Lag | WindowFunction::Lead => (1, 3),
+            WindowFunction::FirstValue | WindowFunction::Sum | WindowFunction::Avg => (1, 1),
+        }
+    }
+}
+
+/// One end of a ROWS frame, None is UNBOUNDED
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bound {
+    Preceding(Option<usize>),
+    Current,
+    Following(Option<usize>),
+}
+
+impl Bound {
+    fn parse(bound: Pair<Rule>) -> Result<Self, String> {
+        let mut it = bound.into_inner();
+        let first = it.next().unwrap();
+        let rows = match first.as_rule() {
+            Rule::currentKw => return Ok(Bound::Current),
+            Rule::unbounded => None,
+            _ => match SQLValue::from_literal(first)? {
+                SQLValue::Int(n) if n >= 0 => Some(n as usize),
+                val => return Err(format!("!Frame offset must be a non negative int but got {}", val)),
+            },
+        };
+        match it.next().unwrap().as_rule() {
+            Rule::preceding => Ok(Bound::Preceding(rows)),
+            _ => Ok(Bound::Following(rows)),
+        }
+    }
+
+    /// Position the bound points at for the row at p in a partition of len rows
+    /// It can be outside the partition and is clamped by the caller
+    fn position(&self, p: usize, len: usize) -> isize {
+        match self {
+            Bound::Preceding(None) => 0,
+            Bound::Preceding(Some(n)) => p as isize - *n as isize,
+            Bound::Current => p as isize,
+            Bound::Following(Some(n)) => p as isize + *n as isize,
+            Bound::Following(None) => len as isize - 1,
+        }
+    }
+}
+
+/// A window function call with its OVER clause
+/// The result for every row is computed up front by the SELECT it is in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    /// Position of the result in Scope::windows, numbered per SELECT
+    pub id: usize,
+    function: WindowFunction,
+    args: Vec<SQLExpr>,
+    partition: Vec<SQLExpr>,
+    /// Expression and whether it is descending
+    order: Vec<(SQLExpr, bool)>,
+    /// ROWS frame, without one the frame runs from the start of the partition
+    /// to the last row that sorts equal to the current one
+    frame: Option<(Bound, Bound)>,
+    /// Called without OVER, its SELECT then returns a single row over all of its rows
+    aggregate: bool,
+}
+
+impl Window {
+    /// Parses a windowCall rule
+    pub fn parse(call: Pair<Rule>) -> Result<Self, String> {
+        let mut it = call.into_inner();
+        let name = it.next().unwrap().as_str();
+        let function = match WindowFunction::lookup(name) {
+            Some(function) => function,
+            None => return Err(format!("!Window function {} does not exist", name)),
+        };
+        let mut window = Self { id: 0, function, args: Vec::new(), partition: Vec::new(), order: Vec::new(), frame: None, aggregate: false };
+        for pair in it {
+            match pair.as_rule() {
+                Rule::expr => window.args.push(SQLExpr::parse(pair)?),
+                Rule::partitionBy => {
+                    for expr in pair.into_inner().filter(|pair| pair.as_rule() == Rule::expr) {
+                        window.partition.push(SQLExpr::parse(expr)?);
+                    }
+                },
+                Rule::orderBy => {
+                    for item in pair.into_inner().filter(|pair| pair.as_rule() == Rule::orderItem) {
+                        let mut it = item.into_inner();
+                        let expr = SQLExpr::parse(it.next().unwrap())?;
+                        let desc = it.next().is_some_and(|dir| dir.as_rule() == Rule::desc);
+                        window.order.push((expr, desc));
+                    }
+                },
+                Rule::frame => {
+                    let mut bounds = pair.into_inner().filter(|pair| pair.as_rule() == Rule::frameBound);
+                    let start = Bound::parse(bounds.next().unwrap())?;
+                    let end = match bounds.next() {
+                        Some(end) => Bound::parse(end)?,
+                        None => Bound::Current,
+                    };
+                    if matches!(start, Bound::Following(None)) || matches!(end, Bound::Preceding(None)) {
+                        return Err(String::from("!Frame cannot start at UNBOUNDED FOLLOWING or end at UNBOUNDED PRECEDING"));
+                    }
+                    window.frame = Some((start, end));
+                },
+                _ => (),
+            }
+        }
+        Ok(window)
+    }
+
+    /// SUM or AVG called without OVER, None for any other name
+    pub fn aggregate(name: &str, args: Vec<SQLExpr>) -> Option<Self> {
+        match WindowFunction::lookup(name) {
+            Some(function @ (WindowFunction::Sum | WindowFunction::Avg)) => {
+                Some(Self { id: 0, function, args, partition: Vec::new(), order: Vec::new(), frame: None, aggregate: true })
+            },
+            _ => None,
+        }
+    }
+
+    pub fn is_aggregate(&self) -> bool {
+        self.aggregate
+    }
+
+    /// The arguments, PARTITION BY and ORDER BY expressions of the call
+    pub fn exprs(&self) -> impl Iterator<Item = &SQLExpr> {
+        self.args.iter().chain(&self.partition).chain(self.order.iter().map(|(expr, _)| expr))
+    }
+
+    /// Resolves the result type, scope is the row of the SELECT the window is in
+    pub fn check(&self, scope: &Scope) -> Result<SQLType, String> {
+        let name = self.function.name();
+        let (min, max) = self.function.arity();
+        if self.args.len() < min || self.args.len() > max {
+            return if min == max {
+                Err(format!("!{} takes {} arguments but got {}", name, min, self.args.len()))
+            } else {
+                Err(format!("!{} takes {} to {} arguments but got {}", name, min, max, self.args.len()))
+            };
+        }
+        // Window functions cannot be nested
+        let inner = Scope { windows: None, ..*scope };
+        for expr in self.partition.iter().chain(self.order.iter().map(|(expr, _)| expr)) {
+            expr.check(&inner)?;
+        }
+        let mut args = Vec::new();
+        for arg in &self.args {
+            args.push(arg.check(&inner)?);
+        }
+        match self.function {
+            WindowFunction::RowNumber | WindowFunction::Rank | WindowFunction::DenseRank => Ok(SQLType::Int),
+            WindowFunction::Lag | WindowFunction::Lead => {
+                if let Some(offset) = args.get(1) {
+                    if !offset.is_integer() {
+                        return Err(format!("!{} offset must be int but got {}", name, offset));
+                    }
+                }
+                match args.get(2) {
+                    Some(default) => match args[0].common(*default) {
+                        Some(sql_type) => Ok(sql_type),
+                        None => Err(format!("!{} default {} does not match {}", name, default, args[0])),
+                    },
+                    None => Ok(args[0]),
+                }
+            },
+            WindowFunction::FirstValue => Ok(args[0]),
+            WindowFunction::Sum | WindowFunction::Avg if !args[0].is_numeric() => {
+                Err(format!("!{} expected int or float but got {}", name, args[0]))
+            },
+            // Decimals stay exact, AVG divides the exact sum
+            WindowFunction::Sum if matches!(args[0], SQLType::Decimal(..)) => Ok(decimal::arithmetic_type(args[0], BinaryOp::Add, args[0]).unwrap()),
+            WindowFunction::Avg if matches!(args[0], SQLType::Decimal(..)) => Ok(decimal::arithmetic_type(args[0], BinaryOp::Div, SQLType::BigInt).unwrap()),
+            WindowFunction::Sum => Ok(args[0].widened()),
+            WindowFunction::Avg => Ok(SQLType::Float),
+        }
+    }
+
+    /// Result for each of rows, which are the rows of the SELECT left after WHERE
+    pub fn compute(&self, catalog: &Catalog, fields: &[Field], rows: &[Vec<SQLValue>], outer: Option<&Scope>) -> Result<Vec<SQLValue>, String> {
+        let mut partitions: Vec<Vec<usize>> = Vec::new();
+        let mut index = HashMap::new();
+        let mut keys = Vec::new();
+        let mut args = Vec::new();
+        for (i, row) in rows.iter().enumerate() {
+            let scope = Scope::new(catalog, fields, row, outer);
+            let partition = self.partition.iter().map(|expr| expr.eval(&scope)).collect::<Result<Vec<SQLValue>, String>>()?;
+            keys.push(self.order.iter().map(|(expr, _)| expr.eval(&scope)).collect::<Result<Vec<SQLValue>, String>>()?);
+            args.push(self.args.iter().map(|expr| expr.eval(&scope)).collect::<Result<Vec<SQLValue>, String>>()?);
+            let next = partitions.len();
+            let p = *index.entry(partition).or_insert(next);
+            if p == next {
+                partitions.push(Vec::new());
+            }
+            partitions[p].push(i);
+        }
+        let mut out = vec![SQLValue::Null; rows.len()];
+        for mut partition in partitions {
+            partition.sort_by(|a, b| self.compare(&keys[*a], &keys[*b]));
+            let len = partition.len();
+            let peers = |a: usize, b: usize| self.compare(&keys[partition[a]], &keys[partition[b]]) == Ordering::Equal;
+            // Last row sorting equal to each row, the end of the default frame
+            let mut last_peer = vec![len - 1; len];
+            for p in (0..len - 1).rev() {
+                last_peer[p] = if peers(p, p + 1) { last_peer[p + 1] } else { p };
+            }
+            let mut rank = 0;
+            let mut dense_rank = 0;
+            for p in 0..len {
+                if p == 0 || !peers(p - 1, p) {
+                    rank = p + 1;
+                    dense_rank += 1;
+                }
+                let row = partition[p];
+                let (start, end) = match self.frame {
+                    Some((start, end)) => (start.position(p, len), end.position(p, len)),
+                    None if self.order.is_empty() => (0, len as isize - 1),
+                    None => (0, last_peer[p] as isize),
+                };
+                let frame = start.max(0)..=end.min(len as isize - 1);
+                let frame = frame.map(|q| &args[partition[q as usize]][0]);
+                out[row] = match self.function {
+                    WindowFunction::RowNumber => SQLValue::Int(p as i64 + 1),
+                    WindowFunction::Rank => SQLValue::Int(rank as i64),
+                    WindowFunction::DenseRank => SQLValue::Int(dense_rank),
+                    WindowFunction::Lag | WindowFunction::Lead => {
+                        let offset = match args[row].get(1) {
+                            Some(SQLValue::Int(offset)) => *offset,
+                            Some(_) => continue,
+                            None => 1,
+                        };
+                        let target = match self.function {
+                            WindowFunction::Lag => (p as i64).checked_sub(offset),
+                            _ => (p as i64).checked_add(offset),
+                        };
+                        match target {
+                            Some(target) if target >= 0 && (target as usize) < len => args[partition[target as usize]][0].clone(),
+                            _ => args[row].get(2).cloned().unwrap_or(SQLValue::Null),
+                        }
+                    },
+                    WindowFunction::FirstValue => frame.into_iter().next().cloned().unwrap_or(SQLValue::Null),
+                    WindowFunction::Sum | WindowFunction::Avg => {
+                        let mut sum = SQLValue::Null;
+                        let mut count = 0;
+                        for val in frame.filter(|val| !val.is_null()) {
+                            sum = if sum.is_null() { val.clone() } else { expr::arithmetic(sum, BinaryOp::Add, val.clone())? };
+                            count += 1;
+                        }
+                        match (self.function, sum.as_float()) {
+                            (WindowFunction::Avg, Some(_)) if matches!(sum, SQLValue::Decimal(_)) => {
+                                expr::arithmetic(sum, BinaryOp::Div, SQLValue::Int(count))?
+                            },
+                            (WindowFunction::Avg, Some(sum)) => SQLValue::Float(sum / count as f64),
+                            _ => sum,
+                        }
+                    },
+                };
+            }
+        }
+        Ok(out)
+    }
+
+    fn compare(&self, a: &[SQLValue], b: &[SQLValue]) -> Ordering {
+        for ((a, b), (_, desc)) in a.iter().zip(b).zip(&self.order) {
+            let ordering = a.sort_cmp(b);
+            if ordering != Ordering::Equal {
+                return if *desc { ordering.reverse() } else { ordering };
+            }
+        }
+        Ordering::Equal
+    }
+}
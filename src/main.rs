@@ -1,12 +1,34 @@
 // Keaton Clark
 // 03/05/23
 mod db;
+use argh::FromArgs;
 use db::DBMS as DB;
+use std::time::Duration;
 
-
+/// A small SQL database kept in ./dbms
+#[derive(FromArgs)]
+struct Args {
+    /// break every lock on the database files after asking for confirmation, for when an instance is stuck holding them
+    #[argh(switch)]
+    break_lock: bool,
+    /// seconds a statement waits for a lock another instance holds before it fails, 5 by default
+    #[argh(option, default = "5")]
+    lock_timeout: u64,
+}
 
 fn main() {
-    
-    let mut db = DB::new(Some(String::from("dbms")));
+    let args: Args = argh::from_env();
+    if args.break_lock {
+        DB::break_locks("dbms");
+        return;
+    }
+    let mut db = match DB::new(Some(String::from("dbms"))) {
+        Ok(db) => db,
+        Err(e) => {
+            println!("{e}");
+            return;
+        },
+    };
+    db.set_lock_timeout(Duration::from_secs(args.lock_timeout));
     db.interactive()
 }